@@ -19,13 +19,13 @@ impl BitBoard {
 
     #[must_use]
     #[inline]
-    pub fn from_u64(v: u64) -> Self {
+    pub const fn from_u64(v: u64) -> Self {
         Self(v)
     }
 
     #[must_use]
     #[inline]
-    pub fn to_u64(self) -> u64 {
+    pub const fn to_u64(self) -> u64 {
         self.0
     }
 
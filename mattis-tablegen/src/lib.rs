@@ -1,29 +1,110 @@
 use mattis_bitboard::BitBoard;
-use mattis_types::{CastlePerms, File, Rank, Square, TryFromPrimitive};
-use rand::Rng;
+use mattis_types::{CastlePerms, Direction, File, Rank, Square, TryFromPrimitive};
 use std::ops::BitAnd;
 
+/// Calls the macro `$callback` with the name, the type and the generator of every table, separated by semicolons
+/// like `ROOK_MAGICS: [u64; 64] = rook_magics;`. The types refer to `BitBoard`, so it has to be in scope.
+#[macro_export]
+macro_rules! tables {
+    ($callback:ident) => {
+        $callback! {
+            ZOBRIST_PIECE_KEYS: [[u64; 12]; 64] = zobrist_piece_keys;
+            ZOBRIST_COLOR_KEY: u64 = zobrist_color_key;
+            ZOBRIST_CASTLE_KEYS: [u64; 16] = zobrist_castle_keys;
+            ZOBRIST_EN_PASSANT_KEYS: [u64; 64] = zobrist_en_passant_keys;
+            ZOBRIST_MATERIAL_KEYS: [[u64; 16]; 12] = zobrist_material_keys;
+            BORDER: BitBoard = border;
+            FILE_BITBOARDS: [BitBoard; 8] = file_bitboards;
+            NOT_FILE_BITBOARDS: [BitBoard; 8] = not_file_bitboards;
+            RANK_BITBOARDS: [BitBoard; 8] = rank_bitboards;
+            NOT_RANK_BITBOARDS: [BitBoard; 8] = not_rank_bitboards;
+            WHITE_PAWN_PASSED_MASKS: [BitBoard; 64] = white_pawn_passed_masks;
+            BLACK_PAWN_PASSED_MASKS: [BitBoard; 64] = black_pawn_passed_masks;
+            ISOLATED_PAWN_MASKS: [BitBoard; 64] = isolated_pawn_masks;
+            KNIGHT_MOVE_PATTERNS: [BitBoard; 64] = knight_move_patterns;
+            KING_MOVE_PATTERNS: [BitBoard; 64] = king_move_patterns;
+            ROOK_MOVE_PATTERNS: [BitBoard; 64] = rook_move_patterns;
+            BISHOP_MOVE_PATTERNS: [BitBoard; 64] = bishop_move_patterns;
+            ROOK_MAGIC_BIT_COUNT: [u32; 64] = rook_magic_bit_count;
+            BISHOP_MAGIC_BIT_COUNT: [u32; 64] = bishop_magic_bit_count;
+            ROOK_MAGIC_MASKS: [BitBoard; 64] = rook_magic_masks;
+            BISHOP_MAGIC_MASKS: [BitBoard; 64] = bishop_magic_masks;
+            ROOK_MAGICS: [u64; 64] = rook_magics;
+            BISHOP_MAGICS: [u64; 64] = bishop_magics;
+            KPK_BITBASE: [[u64; 64]; 48] = kpk_bitbase;
+            RAYS: [[BitBoard; 64]; 8] = ray_bitboards;
+            BETWEEN: [[BitBoard; 64]; 64] = between_bitboards;
+            LINE: [[BitBoard; 64]; 64] = line_bitboards;
+            DISTANCE: [[u8; 64]; 64] = square_distances;
+        }
+    };
+}
+
+// Every table with random numbers has its own seed, so changing one table doesn't change the others.
+const ZOBRIST_PIECE_SEED: u64 = 0x6d61_7474_6973_0001;
+const ZOBRIST_COLOR_SEED: u64 = 0x6d61_7474_6973_0002;
+const ZOBRIST_CASTLE_SEED: u64 = 0x6d61_7474_6973_0003;
+const ZOBRIST_EN_PASSANT_SEED: u64 = 0x6d61_7474_6973_0004;
+const ZOBRIST_MATERIAL_SEED: u64 = 0x6d61_7474_6973_0005;
+const ROOK_MAGIC_SEED: u64 = 0x6d61_7474_6973_0006;
+const BISHOP_MAGIC_SEED: u64 = 0x6d61_7474_6973_0007;
+
+/// A SplitMix64 generator. Unlike the generators of `rand`, its output is fixed, so the tables (and with them the
+/// hash keys and the node counts of a search) are the same on every build and every machine.
+struct Prng(u64);
+
+impl Prng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number with about 8 bits set, which makes good magic candidates.
+    fn sparse_u64(&mut self) -> u64 {
+        self.next_u64() & self.next_u64() & self.next_u64()
+    }
+
+    fn fill(&mut self, keys: &mut [u64]) {
+        keys.iter_mut().for_each(|k| *k = self.next_u64());
+    }
+}
+
 pub fn zobrist_piece_keys() -> [[u64; 12]; 64] {
-    let mut rng = rand::thread_rng();
+    let mut rng = Prng::new(ZOBRIST_PIECE_SEED);
     let mut keys = [[0; 12]; 64];
-    keys.iter_mut().for_each(|l| *l = rng.gen());
+    keys.iter_mut().for_each(|k| rng.fill(k));
     keys
 }
 
 pub fn zobrist_color_key() -> u64 {
-    rand::thread_rng().gen()
+    Prng::new(ZOBRIST_COLOR_SEED).next_u64()
 }
 
 pub fn zobrist_castle_keys() -> [u64; 16] {
-    let mut keys: [u64; 16] = rand::thread_rng().gen();
+    let mut keys = [0; 16];
+    Prng::new(ZOBRIST_CASTLE_SEED).fill(&mut keys);
     keys[CastlePerms::NONE.as_u8() as usize] = 0;
     keys
 }
 
 pub fn zobrist_en_passant_keys() -> [u64; 64] {
-    let mut rng = rand::thread_rng();
     let mut keys = [0; 64];
-    keys.iter_mut().for_each(|k| *k = rng.gen());
+    Prng::new(ZOBRIST_EN_PASSANT_SEED).fill(&mut keys);
+    keys
+}
+
+/// The material key XORs the keys of all piece counts up to the number of pieces, indexed by piece and count.
+pub fn zobrist_material_keys() -> [[u64; 16]; 12] {
+    let mut rng = Prng::new(ZOBRIST_MATERIAL_SEED);
+    let mut keys = [[0; 16]; 12];
+    keys.iter_mut().for_each(|k| rng.fill(k));
     keys
 }
 
@@ -46,10 +127,6 @@ pub fn not_file_bitboards() -> [BitBoard; 8] {
         *m = m.complement();
     }
 
-    for board in &boards {
-        println!("{board:x?}");
-    }
-
     boards
 }
 
@@ -403,64 +480,244 @@ pub fn bishop_magic_masks() -> [BitBoard; 64] {
 }
 
 pub fn rook_magics() -> [u64; 64] {
+    let mut rng = Prng::new(ROOK_MAGIC_SEED);
     let mut magics = [0; 64];
 
-    for square in 0..64 {
-        let square = Square::try_from_primitive(square).unwrap();
-
-        let rmagic = loop {
-            if let Some(m) = find_magic(square, rook_magic_bit_count()[square as usize], false) {
-                break m;
-            };
-        };
-
-        magics[square] = rmagic;
+    for (square, magic) in magics.iter_mut().enumerate() {
+        let square = Square::try_from_primitive(square as u8).unwrap();
+        *magic = find_magic(square, rook_magic_bit_count()[square], false, &mut rng);
     }
 
     magics
 }
 
 pub fn bishop_magics() -> [u64; 64] {
+    let mut rng = Prng::new(BISHOP_MAGIC_SEED);
     let mut magics = [0; 64];
 
-    for square in 0..64 {
-        let square = Square::try_from_primitive(square).unwrap();
+    for (square, magic) in magics.iter_mut().enumerate() {
+        let square = Square::try_from_primitive(square as u8).unwrap();
+        *magic = find_magic(square, bishop_magic_bit_count()[square], true, &mut rng);
+    }
 
-        let bmagic = loop {
-            if let Some(m) = find_magic(square, bishop_magic_bit_count()[square as usize], true) {
-                break m;
-            };
-        };
+    magics
+}
 
-        magics[square] = bmagic;
+/// The squares from a square into a direction up to the edge of the board, indexed by direction and square. The
+/// square itself is excluded.
+pub fn ray_bitboards() -> [[BitBoard; 64]; 8] {
+    let mut rays = [[BitBoard::EMPTY; 64]; 8];
+
+    for direction in Direction::ALL {
+        for (square, ray) in rays[direction].iter_mut().enumerate() {
+            let mut square = Square::try_from_primitive(square as u8).unwrap();
+            while let Some(next) = square.step(direction) {
+                ray.set(next);
+                square = next;
+            }
+        }
     }
 
-    magics
+    rays
 }
 
-fn find_magic(square: Square, m: u32, is_bishop: bool) -> Option<u64> {
-    let mut b = [BitBoard::EMPTY; 4096];
-    let mut a = [BitBoard::EMPTY; 4096];
+/// The squares between two squares on a common rank, file or diagonal (both excluded). Empty, if there is no common
+/// line.
+pub fn between_bitboards() -> [[BitBoard; 64]; 64] {
+    let mut between = [[BitBoard::EMPTY; 64]; 64];
+
+    for (from, row) in between.iter_mut().enumerate() {
+        let from = Square::try_from_primitive(from as u8).unwrap();
+
+        for direction in Direction::ALL {
+            let mut squares = BitBoard::EMPTY;
+            let mut square = from;
+            while let Some(next) = square.step(direction) {
+                row[next] = squares;
+                squares.set(next);
+                square = next;
+            }
+        }
+    }
 
-    let mask = if is_bishop {
-        bishop_magic_masks()[square as usize]
-    } else {
-        rook_magic_masks()[square as usize]
+    between
+}
+
+/// The whole rank, file or diagonal through two squares from edge to edge. Empty, if there is no common line or both
+/// squares are the same.
+pub fn line_bitboards() -> [[BitBoard; 64]; 64] {
+    let rays = ray_bitboards();
+    let mut line = [[BitBoard::EMPTY; 64]; 64];
+
+    for (from, row) in line.iter_mut().enumerate() {
+        let from = Square::try_from_primitive(from as u8).unwrap();
+
+        for direction in Direction::ALL {
+            let mut full_line = rays[direction][from].union(rays[direction.flipped()][from]);
+            full_line.set(from);
+
+            for to in rays[direction][from].iter_bit_indices() {
+                row[to] = full_line;
+            }
+        }
+    }
+
+    line
+}
+
+/// The number of king moves between two squares.
+pub fn square_distances() -> [[u8; 64]; 64] {
+    let mut distances = [[0; 64]; 64];
+
+    for (a, row) in distances.iter_mut().enumerate() {
+        for (b, distance) in row.iter_mut().enumerate() {
+            let file_distance = (a % 8).abs_diff(b % 8);
+            let rank_distance = (a / 8).abs_diff(b / 8);
+            *distance = file_distance.max(rank_distance) as u8;
+        }
+    }
+
+    distances
+}
+
+/// The KPK bitbase tells, if white wins a position with a white pawn against the lone black king.
+///
+/// The pawn has to be on the files A to D, other positions are mirrored. A position is found by
+/// `bitbase[color * 24 + file * 6 + (Rank::R7 - rank)][black_king]` and the bit of the white king. Draws and illegal
+/// positions are `0`.
+pub fn kpk_bitbase() -> [[u64; 64]; 48] {
+    const INVALID: u8 = 0;
+    const UNKNOWN: u8 = 1;
+    const DRAW: u8 = 2;
+    const WIN: u8 = 4;
+
+    let index = |color: usize, white_king: u8, black_king: u8, pawn: u8| {
+        let (file, rank) = ((pawn % 8) as usize, (pawn / 8) as usize);
+        (color * 24 + file * 6 + (6 - rank)) * 4096 + black_king as usize * 64 + white_king as usize
     };
 
-    let n = mask.bit_count();
+    let king_moves = king_move_patterns();
+    let pawn_attacks = |pawn: u8| {
+        let (file, mut attacks) = (pawn % 8, 0);
+        if file > 0 {
+            attacks |= 1 << (pawn + 7);
+        }
+        if file < 7 {
+            attacks |= 1 << (pawn + 9);
+        }
+        attacks
+    };
 
-    for i in 0..(1 << n) {
-        b[i] = index_to_bb(i, n, mask);
-        a[i] = if is_bishop {
-            batt(square, b[i])
-        } else {
-            ratt(square, b[i])
-        };
+    // Classify the positions, that are decided right away.
+    let mut results = vec![INVALID; 48 * 4096];
+    for color in 0..2 {
+        for pawn in (8..56).filter(|pawn| pawn % 8 < 4) {
+            for white_king in 0..64 {
+                for black_king in 0..64 {
+                    let white_attacks = king_moves[white_king as usize].to_u64();
+                    let black_moves = king_moves[black_king as usize].to_u64();
+                    let promotion = pawn + 8;
+
+                    results[index(color, white_king, black_king, pawn)] = if white_attacks & (1 << black_king) != 0
+                        || white_king == black_king
+                        || white_king == pawn
+                        || black_king == pawn
+                        || (color == 0 && pawn_attacks(pawn) & (1 << black_king) != 0)
+                    {
+                        INVALID
+                    } else if color == 0
+                        && pawn / 8 == 6
+                        && white_king != promotion
+                        && black_king != promotion
+                        && (black_moves & (1 << promotion) == 0 || white_attacks & (1 << promotion) != 0)
+                    {
+                        // The pawn promotes and the queen can't be captured.
+                        WIN
+                    } else if color == 1
+                        && (black_moves & !(white_attacks | pawn_attacks(pawn)) == 0
+                            || black_moves & !white_attacks & (1 << pawn) != 0)
+                    {
+                        // Black is stalemated or captures the pawn.
+                        DRAW
+                    } else {
+                        UNKNOWN
+                    }
+                }
+            }
+        }
     }
 
-    for _ in 0..100_000_000 {
-        let magic = rand_u64_fewbits();
+    // Look at the moves of the unknown positions, until none changes anymore. A position is a win for white, if
+    // white has a move into a win or black has only moves into wins. Otherwise it's a draw.
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for color in 0..2 {
+            for pawn in (8..56).filter(|pawn| pawn % 8 < 4) {
+                for white_king in 0..64 {
+                    for black_king in 0..64 {
+                        let i = index(color, white_king, black_king, pawn);
+                        if results[i] != UNKNOWN {
+                            continue;
+                        }
+
+                        let mut successors = 0;
+                        if color == 0 {
+                            for square in king_moves[white_king as usize].iter_bit_indices() {
+                                successors |= results[index(1, square.into(), black_king, pawn)];
+                            }
+
+                            let push = pawn + 8;
+                            if pawn / 8 < 6 && push != white_king && push != black_king {
+                                successors |= results[index(1, white_king, black_king, push)];
+
+                                let double_push = pawn + 16;
+                                if pawn / 8 == 1 && double_push != white_king && double_push != black_king {
+                                    successors |= results[index(1, white_king, black_king, double_push)];
+                                }
+                            }
+                        } else {
+                            for square in king_moves[black_king as usize].iter_bit_indices() {
+                                successors |= results[index(0, white_king, square.into(), pawn)];
+                            }
+                        }
+
+                        let (good, bad) = if color == 0 { (WIN, DRAW) } else { (DRAW, WIN) };
+                        let result = if successors & good != 0 {
+                            good
+                        } else if successors & UNKNOWN != 0 {
+                            UNKNOWN
+                        } else {
+                            bad
+                        };
+
+                        if result != UNKNOWN {
+                            results[i] = result;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut bitbase = [[0; 64]; 48];
+    for (i, &result) in results.iter().enumerate() {
+        if result == WIN {
+            bitbase[i / 4096][i / 64 % 64] |= 1 << (i % 64);
+        }
+    }
+
+    bitbase
+}
+
+fn find_magic(square: Square, bits: u32, is_bishop: bool, rng: &mut Prng) -> u64 {
+    let (blockers, attacks) = blockers_and_attacks(square, is_bishop);
+    let mask = magic_mask(square, is_bishop);
+
+    loop {
+        let magic = rng.sparse_u64();
 
         if mask
             .to_u64()
@@ -472,29 +729,53 @@ fn find_magic(square: Square, m: u32, is_bishop: bool) -> Option<u64> {
             continue;
         }
 
-        let mut used = [BitBoard::EMPTY; 4096];
-        let mut fail = false;
+        if is_collision_free(&blockers, &attacks, magic, bits) {
+            return magic;
+        }
+    }
+}
 
-        for i in 0..(1 << n) {
-            if fail {
-                break;
-            }
+fn magic_mask(square: Square, is_bishop: bool) -> BitBoard {
+    if is_bishop {
+        bishop_magic_masks()[square]
+    } else {
+        rook_magic_masks()[square]
+    }
+}
 
-            let j = transform(b[i], magic, m);
+/// All blocker sets on the magic mask of the square together with the attacks for them.
+fn blockers_and_attacks(square: Square, is_bishop: bool) -> (Vec<BitBoard>, Vec<BitBoard>) {
+    let mask = magic_mask(square, is_bishop);
+    let n = mask.bit_count();
 
-            if used[j as usize] == BitBoard::EMPTY {
-                used[j as usize] = a[i];
-            } else if used[j as usize] != a[i] {
-                fail = true;
-            }
-        }
+    (0..1 << n)
+        .map(|i| {
+            let blockers = index_to_bb(i, n, mask);
+            let attacks = if is_bishop {
+                batt(square, blockers)
+            } else {
+                ratt(square, blockers)
+            };
+
+            (blockers, attacks)
+        })
+        .unzip()
+}
 
-        if !fail {
-            return Some(magic);
+fn is_collision_free(blockers: &[BitBoard], attacks: &[BitBoard], magic: u64, bits: u32) -> bool {
+    let mut used = vec![BitBoard::EMPTY; 1 << bits];
+
+    for (&b, &a) in blockers.iter().zip(attacks) {
+        let j = transform(b, magic, bits) as usize;
+
+        if used[j] == BitBoard::EMPTY {
+            used[j] = a;
+        } else if used[j] != a {
+            return false;
         }
     }
 
-    None
+    true
 }
 
 fn index_to_bb(index: usize, bits: u32, mut mask: BitBoard) -> BitBoard {
@@ -588,14 +869,106 @@ fn batt(square: Square, block: BitBoard) -> BitBoard {
     BitBoard::from_u64(result)
 }
 
-fn rand_u64_fewbits() -> u64 {
-    let mut rng = rand::thread_rng();
-    rng.gen::<u64>() & rng.gen::<u64>() & rng.gen::<u64>()
-}
-
 fn transform(b: BitBoard, magic: u64, bits: u32) -> u32 {
     // Faster methods?
     // ((b as i32) * (magic as i32) ^ ((b >> 32) as i32) * ((magic >> 32) as i32)) as u32 >> (32 - bits)
 
     ((b.to_u64().wrapping_mul(magic)) >> (64 - bits)) as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn generation_is_reproducible() {
+        assert_eq!(zobrist_color_key(), 0xd16c_211f_b22e_fd2d);
+        assert_eq!(zobrist_piece_keys(), zobrist_piece_keys());
+        assert_eq!(zobrist_material_keys(), zobrist_material_keys());
+        assert_eq!(bishop_magics(), bishop_magics());
+    }
+
+    #[test]
+    fn zobrist_keys_are_distinct() {
+        let mut keys = vec![zobrist_color_key()];
+        keys.extend(zobrist_piece_keys().iter().flatten());
+        keys.extend(zobrist_castle_keys().iter().filter(|&&k| k != 0));
+        keys.extend(zobrist_en_passant_keys());
+        keys.extend(zobrist_material_keys().iter().flatten());
+
+        assert_eq!(keys.len(), 1 + 64 * 12 + 15 + 64 + 12 * 16);
+        assert_eq!(keys.iter().collect::<HashSet<_>>().len(), keys.len());
+    }
+
+    /// File and rank of a square as signed numbers.
+    fn coordinates(square: usize) -> (i32, i32) {
+        ((square % 8) as i32, (square / 8) as i32)
+    }
+
+    /// Whether `c` is `a` plus a multiple of the step from `a` to `b` (reduced to single squares), if `a` and `b` are
+    /// on a common line. Returns the multiple.
+    fn steps_on_line(a: usize, b: usize, c: usize) -> Option<i32> {
+        let ((af, ar), (bf, br), (cf, cr)) = (coordinates(a), coordinates(b), coordinates(c));
+        let (df, dr) = (bf - af, br - ar);
+        let aligned = a != b && (df == 0 || dr == 0 || df.abs() == dr.abs());
+        let (sf, sr) = (df.signum(), dr.signum());
+        let k = if sf != 0 { (cf - af) * sf } else { (cr - ar) * sr };
+
+        (aligned && (af + k * sf, ar + k * sr) == (cf, cr)).then_some(k)
+    }
+
+    #[test]
+    fn geometry_matches_brute_force() {
+        let (rays, between, line, distance) = (
+            ray_bitboards(),
+            between_bitboards(),
+            line_bitboards(),
+            square_distances(),
+        );
+        let king_moves = king_move_patterns();
+
+        for a in 0..64 {
+            for b in 0..64 {
+                let (square_a, square_b) = (
+                    Square::try_from_primitive(a as u8).unwrap(),
+                    Square::try_from_primitive(b as u8).unwrap(),
+                );
+
+                for c in 0..64 {
+                    let square_c = Square::try_from_primitive(c as u8).unwrap();
+                    let k = steps_on_line(a, b, c);
+                    let length = steps_on_line(a, b, b).unwrap_or(0);
+
+                    assert_eq!(between[a][b].get(square_c), k.is_some_and(|k| 0 < k && k < length));
+                    assert_eq!(line[a][b].get(square_c), k.is_some());
+                }
+
+                // The king distance is the length of the shortest king walk.
+                let mut reached = BitBoard::EMPTY;
+                reached.set(square_a);
+                let mut steps = 0;
+                while !reached.get(square_b) {
+                    for square in reached.iter_bit_indices() {
+                        reached = reached.union(king_moves[square]);
+                    }
+                    steps += 1;
+                }
+                assert_eq!(distance[a][b], steps);
+            }
+
+            for direction in Direction::ALL {
+                let (df, dr) = direction.offset();
+                let (af, ar) = coordinates(a);
+                for c in 0..64 {
+                    let (cf, cr) = coordinates(c);
+                    let on_ray = (1..8).any(|k| (af + k * df as i32, ar + k * dr as i32) == (cf, cr));
+                    assert_eq!(
+                        rays[direction][a].get(Square::try_from_primitive(c as u8).unwrap()),
+                        on_ray
+                    );
+                }
+            }
+        }
+    }
+}
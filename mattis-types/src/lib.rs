@@ -698,6 +698,69 @@ impl Square {
     }
 }
 
+/// The directions, in which the sliding pieces and the king move. `North` points towards the 8th rank.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, IntoPrimitive, UnsafeFromPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl_to_usize!(Direction, u8);
+impl_array_indexing!(Direction, 8);
+
+impl Direction {
+    pub const ALL: [Self; 8] = [
+        Self::North,
+        Self::NorthEast,
+        Self::East,
+        Self::SouthEast,
+        Self::South,
+        Self::SouthWest,
+        Self::West,
+        Self::NorthWest,
+    ];
+
+    #[must_use]
+    pub fn flipped(self) -> Self {
+        let d: u8 = self.into();
+        // Safety: `(d + 4) % 8` is always a valid direction.
+        unsafe { Self::unchecked_transmute_from((d + 4) % 8) }
+    }
+
+    /// The change of the file and the rank of one step into the direction.
+    #[must_use]
+    pub fn offset(self) -> (i8, i8) {
+        match self {
+            Self::North => (0, 1),
+            Self::NorthEast => (1, 1),
+            Self::East => (1, 0),
+            Self::SouthEast => (1, -1),
+            Self::South => (0, -1),
+            Self::SouthWest => (-1, -1),
+            Self::West => (-1, 0),
+            Self::NorthWest => (-1, 1),
+        }
+    }
+}
+
+impl Square {
+    /// The neighbouring square into the direction, if it is on the board.
+    #[must_use]
+    pub fn step(self, direction: Direction) -> Option<Self> {
+        let (file_offset, rank_offset) = direction.offset();
+        let file = File::try_from_primitive(u8::from(self.file()).checked_add_signed(file_offset)?).ok()?;
+        let rank = Rank::try_from_primitive(u8::from(self.rank()).checked_add_signed(rank_offset)?).ok()?;
+        Some(Self::from_file_rank(file, rank))
+    }
+}
+
 impl Add<u8> for Square {
     type Output = Option<Square>;
 
@@ -872,7 +935,7 @@ impl rand::distributions::Distribution<Eval> for rand::distributions::Standard {
 
 #[cfg(test)]
 mod tests {
-    use super::{Color, Piece, PieceType};
+    use super::{Color, Direction, Piece, PieceType, Square};
 
     #[test]
     fn convert_piece_types() {
@@ -884,4 +947,18 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn step_into_directions() {
+        assert_eq!(Square::E4.step(Direction::North), Some(Square::E5));
+        assert_eq!(Square::E4.step(Direction::SouthWest), Some(Square::D3));
+        assert_eq!(Square::H4.step(Direction::East), None);
+        assert_eq!(Square::A1.step(Direction::South), None);
+
+        for direction in Direction::ALL {
+            assert_eq!(direction.flipped().flipped(), direction);
+            let back = Square::D4.step(direction).and_then(|s| s.step(direction.flipped()));
+            assert_eq!(back, Some(Square::D4));
+        }
+    }
 }
@@ -13,6 +13,8 @@ pub enum ParseError {
     PositionInvalid,
     #[error("go command contained an unknown setting")]
     UnknownGoSetting,
+    #[error("setoption must be `setoption name <id> [value <x>]`")]
+    SetoptionInvalid,
 }
 
 #[derive(Debug)]
@@ -20,10 +22,7 @@ pub enum GuiMessage {
     Uci,
     Debug(bool),
     Isready,
-    // TODO: Setoption {
-    //     id: String,
-    //     value: String,
-    // },
+    Setoption { name: String, value: Option<String> },
     // TODO: Register
     Ucinewgame,
     Position { pos: Position, moves: Vec<String> },
@@ -76,6 +75,10 @@ impl GuiMessage {
                 let (pos, moves) = parse_position(rest)?;
                 Ok(Self::Position { pos, moves })
             }
+            "setoption" => {
+                let (name, value) = parse_setoption(rest)?;
+                Ok(Self::Setoption { name, value })
+            }
             _ => Err(ParseError::UnknownCommand),
         }
     }
@@ -104,6 +107,34 @@ fn parse_position(text: &str) -> Result<(Position, Vec<String>), ParseError> {
     Ok((pos, moves))
 }
 
+fn parse_setoption(text: &str) -> Result<(String, Option<String>), ParseError> {
+    // Only the line ending is removed, values like paths might contain any whitespace.
+    let text = text.trim_end_matches(['\r', '\n']);
+
+    let Some(("name", mut rest)) = split_token(text) else {
+        return Err(ParseError::SetoptionInvalid);
+    };
+
+    // Names can contain spaces, so the name is made of all tokens up to the `value` keyword.
+    let mut name = Vec::new();
+    let mut value = None;
+    while let Some((token, tail)) = split_token(rest) {
+        if token == "value" {
+            value = Some(tail.trim_start().to_owned());
+            break;
+        }
+
+        name.push(token);
+        rest = tail;
+    }
+
+    if name.is_empty() {
+        return Err(ParseError::SetoptionInvalid);
+    }
+
+    Ok((name.join(" "), value))
+}
+
 impl Go {
     pub fn parse(text: &str) -> Result<Self, ParseError> {
         let mut go = Go::default();
@@ -176,7 +207,7 @@ pub enum EngineMessage {
     // TODO: Copyprotection
     // TODO: Registration
     Info(Info),
-    // TODO: Option
+    Option(UciOption),
 }
 
 #[derive(Debug, Default)]
@@ -199,6 +230,21 @@ pub struct Info {
     pub currline: Vec<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct UciOption {
+    pub name: String,
+    pub kind: OptionKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum OptionKind {
+    Check { default: bool },
+    Spin { default: i64, min: i64, max: i64 },
+    Combo { default: String, vars: Vec<String> },
+    Button,
+    String { default: String },
+}
+
 #[derive(Debug)]
 pub enum Id {
     Name(String),
@@ -229,6 +275,30 @@ impl Display for EngineMessage {
                 }
                 Ok(())
             }
+            EngineMessage::Option(option) => write!(f, "{option}"),
+        }
+    }
+}
+
+impl Display for UciOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+
+        match &self.kind {
+            OptionKind::Check { default } => write!(f, "check default {default}"),
+            OptionKind::Spin { default, min, max } => write!(f, "spin default {default} min {min} max {max}"),
+            OptionKind::Combo { default, vars } => {
+                write!(f, "combo default {default}")?;
+
+                for var in vars {
+                    write!(f, " var {var}")?;
+                }
+
+                Ok(())
+            }
+            OptionKind::Button => write!(f, "button"),
+            OptionKind::String { default } if default.is_empty() => write!(f, "string default <empty>"),
+            OptionKind::String { default } => write!(f, "string default {default}"),
         }
     }
 }
@@ -236,7 +306,7 @@ impl Display for EngineMessage {
 impl Display for Score {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(ply) = self.0.mate_ply() {
-            let moves = ((ply + 1) / 2) as i16;
+            let moves = ply.div_ceil(2) as i16;
             let moves = if self.0 > Eval::DRAW { moves } else { -moves };
             write!(f, "mate {}", moves)
         } else {
@@ -285,3 +355,110 @@ fn split_whitespace_once(text: &str) -> Option<(&str, &str)> {
     let (first, rest) = text.split_once(char::is_whitespace)?;
     Some((first, rest.trim_start()))
 }
+
+/// Splits off the first token, the rest keeps its whitespace.
+fn split_token(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return None;
+    }
+
+    Some(text.split_at(text.find(char::is_whitespace).unwrap_or(text.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuiMessage, OptionKind, UciOption};
+
+    fn setoption(text: &str) -> (String, Option<String>) {
+        match GuiMessage::parse(text) {
+            Ok(GuiMessage::Setoption { name, value }) => (name, value),
+            other => panic!("{text}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn setoption_keeps_the_value() {
+        let (name, value) = setoption("setoption name A B value C  D\n");
+        assert_eq!(name, "A B");
+        assert_eq!(value.as_deref(), Some("C  D"));
+
+        let (name, value) = setoption("setoption  name EvalFile value /nets/my\tnet  1.nnue\r\n");
+        assert_eq!(name, "EvalFile");
+        assert_eq!(value.as_deref(), Some("/nets/my\tnet  1.nnue"));
+    }
+
+    #[test]
+    fn setoption_without_value() {
+        assert_eq!(
+            setoption("setoption name Clear Hash\n"),
+            ("Clear Hash".to_string(), None)
+        );
+        assert_eq!(
+            setoption("setoption name Ponder value\n"),
+            ("Ponder".to_string(), Some(String::new()))
+        );
+
+        assert!(GuiMessage::parse("setoption name\n").is_err());
+        assert!(GuiMessage::parse("setoption value 1\n").is_err());
+        assert!(GuiMessage::parse("setoption names Hash\n").is_err());
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let options = [
+            (OptionKind::Check { default: true }, "check default true", "false"),
+            (
+                OptionKind::Spin {
+                    default: 16,
+                    min: 1,
+                    max: 1024,
+                },
+                "spin default 16 min 1 max 1024",
+                "64",
+            ),
+            (
+                OptionKind::Combo {
+                    default: "Normal".to_string(),
+                    vars: vec!["Solid".to_string(), "Normal".to_string()],
+                },
+                "combo default Normal var Solid var Normal",
+                "Solid",
+            ),
+            (
+                OptionKind::String { default: String::new() },
+                "string default <empty>",
+                "a  b",
+            ),
+            (
+                OptionKind::String {
+                    default: "book.bin".to_string(),
+                },
+                "string default book.bin",
+                "book.bin",
+            ),
+        ];
+
+        for (kind, text, value) in options {
+            let option = UciOption {
+                name: "Some Option".to_string(),
+                kind,
+            };
+            assert_eq!(option.to_string(), format!("option name Some Option type {text}"));
+
+            let (name, parsed) = setoption(&format!("setoption name {} value {value}\n", option.name));
+            assert_eq!(name, option.name);
+            assert_eq!(parsed.as_deref(), Some(value));
+        }
+
+        let button = UciOption {
+            name: "Clear Hash".to_string(),
+            kind: OptionKind::Button,
+        };
+        assert_eq!(button.to_string(), "option name Clear Hash type button");
+        assert_eq!(
+            setoption(&format!("setoption name {}\n", button.name)),
+            (button.name, None)
+        );
+    }
+}
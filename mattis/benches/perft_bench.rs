@@ -1,10 +1,16 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use mattis::board::{movegen::MoveList, Board};
+use mattis::board::{
+    movegen::{sliders, MoveList},
+    Board,
+};
+use mattis_bitboard::BitBoard;
+use mattis_types::{Square, TryFromPrimitive};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 const MAX_LEAVES: u32 = 999_999;
 
 fn perf_bench(c: &mut Criterion) {
-    let testsuite = std::fs::read_to_string("perftsuite.epd").unwrap();
+    let testsuite = include_str!("../../perftsuite.epd");
     let mut group = c.benchmark_group("perft_group");
 
     for line in testsuite.lines().take(10) {
@@ -36,6 +42,49 @@ fn perf_bench(c: &mut Criterion) {
     group.finish();
 }
 
+/// Compares the slider backends on their own. The perft above uses the backend, that is selected by the features, so
+/// running it with and without `--features pext` compares them in the move generation.
+fn slider_bench(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(7);
+    let blockers: Vec<_> = (0..1024)
+        .map(|_| BitBoard::from_u64(rng.gen::<u64>() & rng.gen::<u64>()))
+        .collect();
+    let squares: Vec<_> = (0..64).map(|i| Square::try_from_primitive(i).unwrap()).collect();
+    let mut group = c.benchmark_group("slider_group");
+
+    group.bench_function("magic", |b| {
+        b.iter(|| {
+            let mut attacks = BitBoard::EMPTY;
+            for (&blockers, &square) in blockers.iter().zip(squares.iter().cycle()) {
+                attacks = attacks
+                    .union(sliders::magic_rook_moves(square, blockers))
+                    .union(sliders::magic_bishop_moves(square, blockers));
+            }
+            attacks
+        });
+    });
+
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("bmi2") {
+        group.bench_function("pext", |b| {
+            b.iter(|| {
+                let mut attacks = BitBoard::EMPTY;
+                for (&blockers, &square) in blockers.iter().zip(squares.iter().cycle()) {
+                    // Safety: We checked, that the CPU supports BMI2.
+                    unsafe {
+                        attacks = attacks
+                            .union(sliders::pext::rook_moves(square, blockers))
+                            .union(sliders::pext::bishop_moves(square, blockers));
+                    }
+                }
+                attacks
+            });
+        });
+    }
+
+    group.finish();
+}
+
 fn perft(board: &mut Board, depth: usize, lists: &mut [MoveList]) -> u32 {
     #[cfg(debug_assertions)]
     board.check_board_integrity();
@@ -47,13 +96,14 @@ fn perft(board: &mut Board, depth: usize, lists: &mut [MoveList]) -> u32 {
     let mut leaves = 0;
     let (first, rest) = lists.split_first_mut().unwrap();
     first.clear();
-    board.generate_all_moves(first);
+    board.generate_legal_moves(first);
 
-    for m in first {
-        if !board.make_move(*m) {
-            continue;
-        }
+    if depth == 1 {
+        return first.len() as u32;
+    }
 
+    for m in first {
+        board.make_move(*m);
         leaves += perft(board, depth - 1, rest);
         board.take_move();
     }
@@ -61,5 +111,5 @@ fn perft(board: &mut Board, depth: usize, lists: &mut [MoveList]) -> u32 {
     leaves
 }
 
-criterion_group!(benches, perf_bench);
+criterion_group!(benches, perf_bench, slider_bench);
 criterion_main!(benches);
@@ -0,0 +1,38 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mattis::{board::Board, hashtable::TranspositionTable, search::search_on_current_thread, time_man::Limits};
+use std::sync::Arc;
+
+const DEPTH: u16 = 7;
+
+const POSITIONS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+fn search(fen: &str) -> u64 {
+    let mut board = Board::from_fen(fen).unwrap();
+    let ttable = Arc::new(TranspositionTable::new(16));
+    let time_man = Limits::new().depth(Some(DEPTH)).start_now();
+
+    search_on_current_thread(&mut board, ttable, time_man).nodes
+}
+
+fn search_bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search_group");
+    group.sample_size(10);
+
+    for fen in POSITIONS {
+        // The search is deterministic, so every iteration visits the same number of nodes.
+        group.throughput(Throughput::Elements(search(fen)));
+
+        let id = BenchmarkId::from_parameter(format!("{fen}: {DEPTH}"));
+        group.bench_with_input(id, fen, |b, fen| b.iter(|| search(fen)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, search_bench);
+criterion_main!(benches);
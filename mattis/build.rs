@@ -1,58 +1,71 @@
-const OUT_DIR: &str = "../target/generated_tables";
+//! Generates the lookup tables of `src/tables.rs` as Rust source into `OUT_DIR`.
+//!
+//! The tables are written as literals, so they don't depend on the endianness or the memory layout of the target.
+//! With the `runtime-tables` feature, nothing is generated and the tables are computed at startup instead.
 
-macro_rules! run_gen {
-    ($name:ident) => {{
-        let path = format!("{OUT_DIR}/{}", stringify!($name));
-        let path = std::path::Path::new(&path);
+use mattis_bitboard::BitBoard;
+use std::fmt::Write;
 
-        if !path.exists() {
-            let result = $name();
-            let bytes = bytemuck::bytes_of(&result);
-            std::fs::write(path, bytes).unwrap();
-        }
-    }};
+/// Formats a value as a Rust expression, that can be used in a constant.
+trait RustLiteral {
+    fn write_literal(&self, source: &mut String);
 }
 
-fn main() {
-    let dir_path = std::path::Path::new(OUT_DIR);
-    if !dir_path.exists() {
-        std::fs::create_dir(dir_path).unwrap();
+impl RustLiteral for u64 {
+    fn write_literal(&self, source: &mut String) {
+        write!(source, "{self:#x}").unwrap();
     }
+}
 
-    {
-        use mattis_tablegen::*;
-        run_gen!(zobrist_piece_keys);
-        run_gen!(zobrist_color_key);
-        run_gen!(zobrist_castle_keys);
-        run_gen!(zobrist_en_passant_keys);
-
-        run_gen!(file_bitboards);
-        run_gen!(not_file_bitboards);
-
-        run_gen!(rank_bitboards);
-        run_gen!(not_rank_bitboards);
+impl RustLiteral for u8 {
+    fn write_literal(&self, source: &mut String) {
+        write!(source, "{self}").unwrap();
+    }
+}
 
-        run_gen!(border);
+impl RustLiteral for u32 {
+    fn write_literal(&self, source: &mut String) {
+        write!(source, "{self}").unwrap();
+    }
+}
 
-        run_gen!(white_pawn_passed_masks);
-        run_gen!(black_pawn_passed_masks);
-        run_gen!(isolated_pawn_masks);
+impl RustLiteral for BitBoard {
+    fn write_literal(&self, source: &mut String) {
+        write!(source, "BitBoard::from_u64({:#x})", self.to_u64()).unwrap();
+    }
+}
 
-        run_gen!(knight_move_patterns);
-        run_gen!(king_move_patterns);
-        run_gen!(rook_move_patterns);
-        run_gen!(bishop_move_patterns);
+impl<T: RustLiteral, const N: usize> RustLiteral for [T; N] {
+    fn write_literal(&self, source: &mut String) {
+        source.push('[');
+        for value in self {
+            value.write_literal(source);
+            source.push(',');
+        }
+        source.push(']');
+    }
+}
 
-        run_gen!(rook_magic_bit_count);
-        run_gen!(bishop_magic_bit_count);
+macro_rules! generate_tables {
+    ($($name:ident: $ty:ty = $generator:ident;)*) => {{
+        let mut source = String::new();
+        $(
+            write!(source, "pub const {}: &{} = &", stringify!($name), stringify!($ty)).unwrap();
+            mattis_tablegen::$generator().write_literal(&mut source);
+            source.push_str(";\n");
+        )*
+        source
+    }};
+}
 
-        run_gen!(rook_magic_masks);
-        run_gen!(bishop_magic_masks);
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
 
-        run_gen!(rook_magics);
-        run_gen!(bishop_magics);
+    if std::env::var_os("CARGO_FEATURE_RUNTIME_TABLES").is_some() {
+        return;
     }
 
-    println!("cargo:rerun-if-changed=../target/generated_tables");
-    println!("cargo:rerun-if-changed=../mattis-tablegen");
+    let source = mattis_tablegen::tables!(generate_tables);
+    let out_dir = std::env::var_os("OUT_DIR").unwrap();
+    std::fs::write(std::path::Path::new(&out_dir).join("tables.rs"), source).unwrap();
 }
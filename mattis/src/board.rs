@@ -1,13 +1,15 @@
 pub mod makemove;
 pub mod movegen;
+pub mod see;
 
-use self::movegen::{magic_bishop_moves, magic_rook_moves, MoveList};
+use self::movegen::{bishop_moves, rook_moves, MoveList};
 use crate::{
     chess_move::ChessMove,
+    nnue::Nnue,
     notation::Notation,
     tables::{
         KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS, ZOBRIST_CASTLE_KEYS, ZOBRIST_COLOR_KEY, ZOBRIST_EN_PASSANT_KEYS,
-        ZOBRIST_PIECE_KEYS,
+        ZOBRIST_MATERIAL_KEYS, ZOBRIST_PIECE_KEYS,
     },
 };
 use mattis_bitboard::BitBoard;
@@ -34,6 +36,9 @@ pub enum FenError {
 
     #[error("fen string does not contain a valid en passant square (use '-' for none)")]
     InvalidEnPassantSquare,
+
+    #[error("fen string does not contain a valid halfmove clock")]
+    InvalidHalfmoveClock,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -56,6 +61,7 @@ pub struct Board {
     pub fifty_move: usize, // the amount of *halfmoves* (triggers the rule at 100) since a fifty-move-rule reset
     pub ply: usize,        // the number of halfmoves since the start of the game (currently unused)
     pub position_key: u64, // the current zobrist position key
+    pub material_key: u64, // the zobrist key of the piece counts, equal for all positions with the same material
 
     pub king_square: [Square; 2],        // the position of the white and black kings
     pub bitboards: [BitBoard; 12],       // bitboards for each piece type
@@ -68,6 +74,7 @@ pub struct Board {
     pub material: [i16; 2],              // the material in centipawns for both sides
 
     pub history: Vec<HistoryEntry>, // stores the board history
+    pub nnue: Option<Box<Nnue>>,    // the nnue accumulators, if an nnue network is used for evaluation
 }
 
 impl Board {
@@ -81,6 +88,7 @@ impl Board {
             castle_perms: CastlePerms::NONE,
             ply: 0,
             position_key: 0,
+            material_key: 0,
             bitboards: [BitBoard::EMPTY; 12],
             bb_all_per_color: [BitBoard::EMPTY; 2],
             bb_all: BitBoard::EMPTY,
@@ -90,6 +98,7 @@ impl Board {
             count_minor_pieces: [0; 2],
             material: [0; 2],
             history: vec![],
+            nnue: None,
         };
 
         this.position_key = this.generate_position_key();
@@ -106,7 +115,7 @@ impl Board {
         }
 
         if self.color == Color::White {
-            key ^= ZOBRIST_COLOR_KEY;
+            key ^= *ZOBRIST_COLOR_KEY;
         }
 
         if let Some(sq) = self.en_passant {
@@ -118,6 +127,19 @@ impl Board {
         key
     }
 
+    /// Generates the material key of the piece counts. It's maintained incrementally by [`Board::make_move`].
+    pub fn generate_material_key(count_pieces: &[usize; 12]) -> u64 {
+        let mut key = 0;
+
+        for (keys, &count) in ZOBRIST_MATERIAL_KEYS.iter().zip(count_pieces) {
+            for count_key in &keys[..count] {
+                key ^= count_key;
+            }
+        }
+
+        key
+    }
+
     pub fn startpos() -> Self {
         const FEN_STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
         Self::from_fen(FEN_STARTPOS).unwrap()
@@ -186,7 +208,11 @@ impl Board {
             board.en_passant = Some(square);
         }
 
-        // TODO: Handle halfmove and fullmove clock from parts 5 and 6
+        if let Some(halfmove_clock) = parts.get(4) {
+            board.fifty_move = halfmove_clock.parse().map_err(|_| FenError::InvalidHalfmoveClock)?;
+        }
+
+        // TODO: Handle fullmove clock from part 6
 
         board.position_key = board.generate_position_key();
         board.update_redundant_data();
@@ -228,6 +254,8 @@ impl Board {
                 self.king_square[color] = square;
             }
         }
+
+        self.material_key = Self::generate_material_key(&self.count_pieces);
     }
 
     pub fn as_fen(&self) -> String {
@@ -289,9 +317,9 @@ impl Board {
             fen.push('-');
         }
 
-        // TODO: halfmove and fullmove clock
+        // TODO: fullmove clock
         fen.push(' ');
-        fen.push('0');
+        fen.push_str(&self.fifty_move.to_string());
         fen.push(' ');
         fen.push('0');
 
@@ -344,7 +372,7 @@ impl Board {
             Color::White => (Piece::WhiteQueen, Piece::WhiteRook),
         };
 
-        let attack_pattern = magic_rook_moves(square, self.bb_all);
+        let attack_pattern = rook_moves(square, self.bb_all);
         let rooks_and_queens = self.bitboards[queen_piece].union(self.bitboards[rook_piece]);
         if !attack_pattern.intersection(rooks_and_queens).is_empty() {
             return true;
@@ -356,7 +384,7 @@ impl Board {
             Color::White => (Piece::WhiteQueen, Piece::WhiteBishop),
         };
 
-        let attack_pattern = magic_bishop_moves(square, self.bb_all);
+        let attack_pattern = bishop_moves(square, self.bb_all);
         let bishops_and_queens = self.bitboards[queen_piece].union(self.bitboards[bishop_piece]);
         if !attack_pattern.intersection(bishops_and_queens).is_empty() {
             return true;
@@ -418,6 +446,7 @@ impl Board {
         assert_eq!(check_count_minor_pieces, self.count_minor_pieces);
         assert_eq!(check_material, self.material);
         assert_eq!(self.position_key, self.generate_position_key());
+        assert_eq!(self.material_key, Self::generate_material_key(&self.count_pieces));
 
         if let Some(sq) = self.en_passant {
             assert!(
@@ -428,6 +457,16 @@ impl Board {
 
         assert_eq!(self.pieces[self.king_square[Color::White]].unwrap(), Piece::WhiteKing);
         assert_eq!(self.pieces[self.king_square[Color::Black]].unwrap(), Piece::BlackKing);
+
+        if let Some(nnue) = &self.nnue {
+            assert!(nnue.is_consistent(&self.bitboards, self.king_square));
+        }
+    }
+
+    /// Makes the current position the root of a search. The ply is counted from here on, so mate scores are relative to
+    /// this position. The history is kept for the repetition detection.
+    pub fn set_search_root(&mut self) {
+        self.ply = 0;
     }
 
     pub fn is_repetition(&self) -> bool {
@@ -445,15 +484,9 @@ impl Board {
         N: Notation,
     {
         let mut movelist = MoveList::new();
-        self.generate_all_moves(&mut movelist);
+        self.generate_legal_moves(&mut movelist);
 
         for cmove in movelist {
-            if !self.make_move(cmove) {
-                continue;
-            }
-
-            self.take_move();
-
             let mut string = String::new();
             N::write(&mut string, cmove, self).unwrap();
 
@@ -560,4 +593,67 @@ mod tests {
         board.generate_all_moves(&mut movelist);
         assert!(movelist.contains(&ep_move16));
     }
+
+    #[test]
+    fn pseudo_legal_moves_match_generator() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPPP/R3K2R b KQkq a3 0 1",
+            "rnbqkbnr/1ppppp1p/8/p4Pp1/8/8/PPPPP1PP/RNBQKBNR w KQkq g6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+
+        let boards: Vec<_> = fens.iter().map(|fen| Board::from_fen(fen).unwrap()).collect();
+
+        // Moves from all the positions, so every position also sees plenty of moves, that are not possible.
+        let mut all_moves = MoveList::new();
+        for board in &boards {
+            board.generate_all_moves(&mut all_moves);
+        }
+
+        for board in &boards {
+            let mut generated = MoveList::new();
+            board.generate_all_moves(&mut generated);
+
+            for m in &all_moves {
+                assert_eq!(
+                    board.is_pseudo_legal(*m),
+                    generated.contains(m),
+                    "{} {}",
+                    board.as_fen(),
+                    m.display_smith()
+                );
+            }
+
+            assert!(!board.is_pseudo_legal(ChessMove::default()));
+        }
+    }
+
+    #[test]
+    fn pseudo_legal_raw_moves() {
+        // Every possible bit pattern, including the unused flag configurations, must be handled.
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/1ppppp1p/8/p4Pp1/8/8/PPPPP1PP/RNBQKBNR w KQkq g6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let mut generated = MoveList::new();
+            board.generate_all_moves(&mut generated);
+
+            let pseudo_legal = (0..=u16::MAX)
+                .map(ChessMove::from_raw)
+                .filter(|m| board.is_pseudo_legal(*m))
+                .collect::<Vec<_>>();
+
+            assert_eq!(pseudo_legal.len(), generated.len(), "{fen}");
+            assert!(pseudo_legal.iter().all(|m| generated.contains(m)), "{fen}");
+        }
+    }
 }
@@ -4,7 +4,9 @@ use super::Board;
 use crate::{
     board::HistoryEntry,
     chess_move::ChessMove,
-    tables::{ZOBRIST_CASTLE_KEYS, ZOBRIST_COLOR_KEY, ZOBRIST_EN_PASSANT_KEYS, ZOBRIST_PIECE_KEYS},
+    tables::{
+        ZOBRIST_CASTLE_KEYS, ZOBRIST_COLOR_KEY, ZOBRIST_EN_PASSANT_KEYS, ZOBRIST_MATERIAL_KEYS, ZOBRIST_PIECE_KEYS,
+    },
 };
 
 impl Board {
@@ -112,7 +114,7 @@ impl Board {
         }
 
         self.color = self.color.flipped();
-        self.position_key ^= ZOBRIST_COLOR_KEY;
+        self.position_key ^= *ZOBRIST_COLOR_KEY;
 
         #[cfg(debug_assertions)]
         self.check_board_integrity();
@@ -155,7 +157,7 @@ impl Board {
         }
 
         self.color = self.color.flipped();
-        self.position_key ^= ZOBRIST_COLOR_KEY;
+        self.position_key ^= *ZOBRIST_COLOR_KEY;
 
         if his.move16.is_en_passant() {
             let enemy_pawn = Piece::new(PieceType::Pawn, self.color.flipped());
@@ -225,7 +227,7 @@ impl Board {
         });
 
         self.color = self.color.flipped();
-        self.position_key ^= ZOBRIST_COLOR_KEY;
+        self.position_key ^= *ZOBRIST_COLOR_KEY;
 
         // remove the en passant square and hash it out if necessary
         if let Some(sq) = self.en_passant.take() {
@@ -256,7 +258,7 @@ impl Board {
         }
 
         self.color = self.color.flipped();
-        self.position_key ^= ZOBRIST_COLOR_KEY;
+        self.position_key ^= *ZOBRIST_COLOR_KEY;
 
         #[cfg(debug_assertions)]
         self.check_board_integrity();
@@ -269,6 +271,7 @@ impl Board {
         self.position_key ^= ZOBRIST_PIECE_KEYS[square][piece];
         self.material[color] -= piece.value();
         self.count_pieces[piece] -= 1;
+        self.material_key ^= ZOBRIST_MATERIAL_KEYS[piece][self.count_pieces[piece]];
         self.bitboards[piece].clear(square);
         self.bb_all_per_color[color].clear(square);
         self.bb_all.clear(square);
@@ -280,6 +283,10 @@ impl Board {
             self.count_big_pieces[color] -= 1;
             self.count_minor_pieces[color] -= 1;
         }
+
+        if let Some(nnue) = &mut self.nnue {
+            nnue.clear_piece(piece, square, self.king_square);
+        }
     }
 
     fn add_piece(&mut self, square: Square, piece: Piece) {
@@ -289,6 +296,7 @@ impl Board {
         self.position_key ^= ZOBRIST_PIECE_KEYS[square][piece];
         self.pieces[square] = Some(piece);
         self.material[color] += piece.value();
+        self.material_key ^= ZOBRIST_MATERIAL_KEYS[piece][self.count_pieces[piece]];
         self.count_pieces[piece] += 1;
         self.bitboards[piece].set(square);
         self.bb_all_per_color[color].set(square);
@@ -301,6 +309,10 @@ impl Board {
             self.count_big_pieces[color] += 1;
             self.count_minor_pieces[color] += 1;
         }
+
+        if let Some(nnue) = &mut self.nnue {
+            nnue.add_piece(piece, square, self.king_square);
+        }
     }
 
     fn move_piece(&mut self, from: Square, to: Square) {
@@ -319,6 +331,19 @@ impl Board {
 
         self.bb_all_per_color[color].set(to);
         self.bb_all.set(to);
+
+        if let Some(nnue) = &mut self.nnue {
+            let op_color = color.flipped();
+            nnue.move_piece(op_color, self.king_square[op_color], piece, from, to);
+
+            // A king move changes every feature of its own perspective, so we refresh it completely.
+            // `self.king_square` is updated by the caller after the move, so we pass the new square directly.
+            if piece.piece_type() == PieceType::King {
+                nnue.refresh(color, to, &self.bitboards);
+            } else {
+                nnue.move_piece(color, self.king_square[color], piece, from, to);
+            }
+        }
     }
 }
 
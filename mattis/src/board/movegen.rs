@@ -1,39 +1,150 @@
 use super::Board;
 use crate::{
     chess_move::{ChessMove, ChessMoveBuilder},
-    tables::{
-        BISHOP_MAGICS, BISHOP_MAGIC_BIT_COUNT, BISHOP_MAGIC_MASKS, KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS,
-        RANK_BITBOARDS, ROOK_MAGICS, ROOK_MAGIC_BIT_COUNT, ROOK_MAGIC_MASKS,
-    },
+    tables::{KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS, RANK_BITBOARDS},
 };
-use ctor::ctor;
 use mattis_bitboard::BitBoard;
-use mattis_types::{CastlePerm, Color, File, Piece, PieceType, Rank, Square, TryFromPrimitive};
+use mattis_types::{CastlePerm, Color, Piece, PieceType, Rank, Square, TryFromPrimitive};
+
+mod legal;
+pub mod sliders;
+
+pub use sliders::{bishop_moves, rook_moves};
 
 pub type MoveList = smallvec::SmallVec<[ChessMove; 128]>;
 
+/// Selects, which kind of moves are generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GenKind {
+    All,
+    Captures,
+    Quiets,
+}
+
+impl GenKind {
+    fn captures(self) -> bool {
+        self != Self::Quiets
+    }
+
+    fn quiets(self) -> bool {
+        self != Self::Captures
+    }
+}
+
 impl Board {
     pub fn generate_capture_moves(&self, list: &mut MoveList) {
         self.generate_pawn_attacks(list);
         self.generate_en_passant(list);
 
-        self.generate_knight_moves(list, true);
-        self.generate_bishop_queen_moves(list, true);
-        self.generate_rook_queen_moves(list, true);
-        self.generate_king_moves(list, true);
+        self.generate_knight_moves(list, GenKind::Captures);
+        self.generate_bishop_queen_moves(list, GenKind::Captures);
+        self.generate_rook_queen_moves(list, GenKind::Captures);
+        self.generate_king_moves(list, GenKind::Captures);
+    }
+
+    /// Generates all moves, that are not generated by `generate_capture_moves`.
+    /// This includes promotions without a capture and castling.
+    pub fn generate_quiet_moves(&self, list: &mut MoveList) {
+        self.generate_pawn_pushes(list);
+        self.generate_knight_moves(list, GenKind::Quiets);
+        self.generate_bishop_queen_moves(list, GenKind::Quiets);
+        self.generate_rook_queen_moves(list, GenKind::Quiets);
+        self.generate_king_moves(list, GenKind::Quiets);
+        self.generate_castling_moves(list);
     }
 
     pub fn generate_all_moves(&self, list: &mut MoveList) {
         self.generate_pawn_attacks(list);
         self.generate_en_passant(list);
         self.generate_pawn_pushes(list);
-        self.generate_knight_moves(list, false);
-        self.generate_bishop_queen_moves(list, false);
-        self.generate_rook_queen_moves(list, false);
-        self.generate_king_moves(list, false);
+        self.generate_knight_moves(list, GenKind::All);
+        self.generate_bishop_queen_moves(list, GenKind::All);
+        self.generate_rook_queen_moves(list, GenKind::All);
+        self.generate_king_moves(list, GenKind::All);
         self.generate_castling_moves(list);
     }
 
+    /// Checks, if `m` is a move, that the move generator could generate in the current position.
+    ///
+    /// This allows to validate moves from outside the move generator (e.g. hash moves or killer moves)
+    /// without generating all moves. Like the generated moves, the move might still leave the king in check.
+    pub fn is_pseudo_legal(&self, m: ChessMove) -> bool {
+        if m.is_nomove() || !m.has_valid_flags() {
+            return false;
+        }
+
+        let start = m.start();
+        let end = m.end();
+
+        let Some(piece) = self.pieces[start] else { return false };
+        let target = self.pieces[end];
+
+        if piece.color() != self.color || target.is_some_and(|t| t.color() == self.color) {
+            return false;
+        }
+
+        // Castling has lots of conditions, so we just ask the move generator. This is still cheap.
+        if m.is_kingside_castle() || m.is_queenside_castle() {
+            let mut list = MoveList::new();
+            self.generate_castling_moves(&mut list);
+            return list.contains(&m);
+        }
+
+        if piece.piece_type() == PieceType::Pawn {
+            return self.is_pseudo_legal_pawn_move(m);
+        }
+
+        if m.is_promotion() || m.is_doube_pawn_push() || m.is_en_passant() || m.is_capture() != target.is_some() {
+            return false;
+        }
+
+        let attacks = match piece.piece_type() {
+            PieceType::Knight => KNIGHT_MOVE_PATTERNS[start],
+            PieceType::Bishop => bishop_moves(start, self.bb_all),
+            PieceType::Rook => rook_moves(start, self.bb_all),
+            PieceType::Queen => bishop_moves(start, self.bb_all).union(rook_moves(start, self.bb_all)),
+            PieceType::King => KING_MOVE_PATTERNS[start],
+            PieceType::Pawn => unreachable!(),
+        };
+
+        attacks.get(end)
+    }
+
+    fn is_pseudo_legal_pawn_move(&self, m: ChessMove) -> bool {
+        let start = m.start();
+        let end = m.end();
+
+        let (forward, start_rank, promotion_rank) = match self.color {
+            Color::White => (8, Rank::R2, Rank::R8),
+            Color::Black => (-8, Rank::R7, Rank::R1),
+        };
+
+        if m.is_en_passant() {
+            return Some(end) == self.en_passant && pawn_attacks(start, self.color).get(end);
+        }
+
+        // Pawns have to promote, when they reach the last rank, and can't promote anywhere else.
+        if m.is_promotion() != (end.rank() == promotion_rank) {
+            return false;
+        }
+
+        if m.is_capture() {
+            return self.pieces[end].is_some() && pawn_attacks(start, self.color).get(end);
+        }
+
+        let single_push = square_offset(start, forward);
+        if single_push.is_none() || self.pieces[single_push.unwrap()].is_some() {
+            return false;
+        }
+
+        if m.is_doube_pawn_push() {
+            let double_push = single_push.and_then(|s| square_offset(s, forward));
+            return start.rank() == start_rank && double_push == Some(end) && self.pieces[end].is_none();
+        }
+
+        single_push == Some(end)
+    }
+
     fn generate_pawn_pushes(&self, list: &mut MoveList) {
         match self.color {
             Color::White => self.generate_white_pawn_pushes(list),
@@ -197,7 +308,7 @@ impl Board {
         }
     }
 
-    fn generate_knight_moves(&self, list: &mut MoveList, captures_only: bool) {
+    fn generate_knight_moves(&self, list: &mut MoveList, kind: GenKind) {
         let knights = match self.color {
             Color::White => self.bitboards[Piece::WhiteKnight],
             Color::Black => self.bitboards[Piece::BlackKnight],
@@ -209,7 +320,7 @@ impl Board {
             for end in targets.iter_bit_indices() {
                 let capture = self.pieces[end];
 
-                if capture.is_none() && captures_only {
+                if (capture.is_none() && !kind.quiets()) || (capture.is_some() && !kind.captures()) {
                     continue;
                 }
 
@@ -220,14 +331,14 @@ impl Board {
         }
     }
 
-    fn generate_king_moves(&self, list: &mut MoveList, captures_only: bool) {
+    fn generate_king_moves(&self, list: &mut MoveList, kind: GenKind) {
         let start = self.king_square[self.color];
         let targets = KING_MOVE_PATTERNS[start].without(self.bb_all_per_color[self.color]);
 
         for end in targets.iter_bit_indices() {
             let capture = self.pieces[end];
 
-            if capture.is_none() && captures_only {
+            if (capture.is_none() && !kind.quiets()) || (capture.is_some() && !kind.captures()) {
                 continue;
             }
 
@@ -238,50 +349,50 @@ impl Board {
         }
     }
 
-    fn generate_rook_queen_moves(&self, list: &mut MoveList, captures_only: bool) {
+    fn generate_rook_queen_moves(&self, list: &mut MoveList, kind: GenKind) {
         let rook_piece = Piece::new(PieceType::Rook, self.color);
         let queen_piece = Piece::new(PieceType::Queen, self.color);
         let rooks_and_queens = self.bitboards[rook_piece].union(self.bitboards[queen_piece]);
 
         for start in rooks_and_queens.iter_bit_indices() {
-            let attack_pattern = magic_rook_moves(start, self.bb_all);
+            let attack_pattern = rook_moves(start, self.bb_all);
             let quiet_moves = attack_pattern.without(self.bb_all);
             let captures = attack_pattern.intersection(self.bb_all_per_color[self.color.flipped()]);
 
-            for end in captures.iter_bit_indices() {
-                list.push(ChessMove::build().start(start).end(end).capture().finish());
-            }
-
-            if captures_only {
-                continue;
+            if kind.captures() {
+                for end in captures.iter_bit_indices() {
+                    list.push(ChessMove::build().start(start).end(end).capture().finish());
+                }
             }
 
-            for end in quiet_moves.iter_bit_indices() {
-                list.push(ChessMove::build().start(start).end(end).finish());
+            if kind.quiets() {
+                for end in quiet_moves.iter_bit_indices() {
+                    list.push(ChessMove::build().start(start).end(end).finish());
+                }
             }
         }
     }
 
-    fn generate_bishop_queen_moves(&self, list: &mut MoveList, captures_only: bool) {
+    fn generate_bishop_queen_moves(&self, list: &mut MoveList, kind: GenKind) {
         let bishop_piece = Piece::new(PieceType::Bishop, self.color);
         let queen_piece = Piece::new(PieceType::Queen, self.color);
         let bishops_and_queens = self.bitboards[bishop_piece].union(self.bitboards[queen_piece]);
 
         for start in bishops_and_queens.iter_bit_indices() {
-            let attack_pattern = magic_bishop_moves(start, self.bb_all);
+            let attack_pattern = bishop_moves(start, self.bb_all);
             let quiet_moves = attack_pattern.without(self.bb_all);
             let captures = attack_pattern.intersection(self.bb_all_per_color[self.color.flipped()]);
 
-            for end in captures.iter_bit_indices() {
-                list.push(ChessMove::build().start(start).end(end).capture().finish());
-            }
-
-            if captures_only {
-                continue;
+            if kind.captures() {
+                for end in captures.iter_bit_indices() {
+                    list.push(ChessMove::build().start(start).end(end).capture().finish());
+                }
             }
 
-            for end in quiet_moves.iter_bit_indices() {
-                list.push(ChessMove::build().start(start).end(end).finish());
+            if kind.quiets() {
+                for end in quiet_moves.iter_bit_indices() {
+                    list.push(ChessMove::build().start(start).end(end).finish());
+                }
             }
         }
     }
@@ -355,179 +466,25 @@ impl Board {
     }
 }
 
-pub fn magic_bishop_moves(square: Square, blockers: BitBoard) -> BitBoard {
-    let blockers = blockers.intersection(BISHOP_MAGIC_MASKS[square]);
-    let key = blockers.to_u64().wrapping_mul(BISHOP_MAGICS[square]);
-    let key = key >> (64 - BISHOP_MAGIC_BIT_COUNT[square]);
-
-    // Safety: `square` is always in a valid range (0-64)
-    let table_row = unsafe { BISHOP_ATTACK_TABLE.get_unchecked(square as u8 as usize) };
-
-    // Safety: `key` is always in a valid range
-    unsafe { *table_row.get_unchecked(key as usize) }
-}
-
-pub fn magic_rook_moves(square: Square, blockers: BitBoard) -> BitBoard {
-    let blockers = blockers.intersection(ROOK_MAGIC_MASKS[square]);
-    let key = blockers.to_u64().wrapping_mul(ROOK_MAGICS[square]);
-    let key = key >> (64 - ROOK_MAGIC_BIT_COUNT[square]);
-
-    // Safety: `square` is always in a valid range (0-64)
-    let table_row = unsafe { ROOK_ATTACK_TABLE.get_unchecked(square as u8 as usize) };
-
-    // Safety: `key` is always in a valid range
-    unsafe { *table_row.get_unchecked(key as usize) }
-}
-
-#[ctor]
-static ROOK_ATTACK_TABLE: Vec<Vec<BitBoard>> = {
-    let mut table = vec![vec![]; 64];
-
-    for (square, square_entry) in table.iter_mut().enumerate() {
-        let square = Square::try_from_primitive(square as u8).unwrap();
-        let mask = ROOK_MAGIC_MASKS[square];
-        let permutations = 1 << mask.bit_count();
-        let file = square.file();
-        let rank = square.rank();
-        square_entry.resize(1 << ROOK_MAGIC_BIT_COUNT[square] as usize, BitBoard::EMPTY);
-
-        for i in 0..permutations {
-            let blockers = blocker_permutation(i, mask);
-            let mut attack = BitBoard::EMPTY;
-
-            if let Some(r) = rank.up() {
-                for r in Rank::range_inclusive(r, Rank::R8) {
-                    attack.set(Square::from_file_rank(file, r));
-                    if blockers.get(Square::from_file_rank(file, r)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some(r) = rank.down() {
-                for r in Rank::range_inclusive(Rank::R1, r).rev() {
-                    attack.set(Square::from_file_rank(file, r));
-                    if blockers.get(Square::from_file_rank(file, r)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some(f) = file.up() {
-                for f in File::range_inclusive(f, File::H) {
-                    attack.set(Square::from_file_rank(f, rank));
-                    if blockers.get(Square::from_file_rank(f, rank)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some(f) = file.down() {
-                for f in File::range_inclusive(File::A, f).rev() {
-                    attack.set(Square::from_file_rank(f, rank));
-                    if blockers.get(Square::from_file_rank(f, rank)) {
-                        break;
-                    }
-                }
-            }
-
-            let key = blockers.to_u64().wrapping_mul(ROOK_MAGICS[square]) >> (64 - ROOK_MAGIC_BIT_COUNT[square]);
-            square_entry[key as usize] = attack;
-        }
-    }
-
-    table
-};
-
-#[ctor]
-static BISHOP_ATTACK_TABLE: Vec<Vec<BitBoard>> = {
-    let mut table = vec![vec![]; 64];
-
-    for (square, square_entry) in table.iter_mut().enumerate() {
-        let square = Square::try_from_primitive(square as u8).unwrap();
-        let mask = BISHOP_MAGIC_MASKS[square];
-        let permutations = 1 << mask.bit_count();
-        let file = square.file();
-        let rank = square.rank();
-        square_entry.resize(1 << BISHOP_MAGIC_BIT_COUNT[square] as usize, BitBoard::EMPTY);
-
-        for i in 0..permutations {
-            let blockers = blocker_permutation(i, mask);
-            let mut attack = BitBoard::EMPTY;
-
-            if let Some((r, f)) = rank.up().zip(file.up()) {
-                for (r, f) in std::iter::zip(Rank::range_inclusive(r, Rank::R8), File::range_inclusive(f, File::H)) {
-                    attack.set(Square::from_file_rank(f, r));
-                    if blockers.get(Square::from_file_rank(f, r)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some((r, f)) = rank.up().zip(file.down()) {
-                for (r, f) in std::iter::zip(
-                    Rank::range_inclusive(r, Rank::R8),
-                    File::range_inclusive(File::A, f).rev(),
-                ) {
-                    attack.set(Square::from_file_rank(f, r));
-                    if blockers.get(Square::from_file_rank(f, r)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some((r, f)) = rank.down().zip(file.up()) {
-                for (r, f) in std::iter::zip(
-                    Rank::range_inclusive(Rank::R1, r).rev(),
-                    File::range_inclusive(f, File::H),
-                ) {
-                    attack.set(Square::from_file_rank(f, r));
-                    if blockers.get(Square::from_file_rank(f, r)) {
-                        break;
-                    }
-                }
-            }
-
-            if let Some((r, f)) = rank.down().zip(file.down()) {
-                for (r, f) in std::iter::zip(
-                    Rank::range_inclusive(Rank::R1, r).rev(),
-                    File::range_inclusive(File::A, f).rev(),
-                ) {
-                    attack.set(Square::from_file_rank(f, r));
-                    if blockers.get(Square::from_file_rank(f, r)) {
-                        break;
-                    }
-                }
-            }
-
-            let key = blockers.to_u64().wrapping_mul(BISHOP_MAGICS[square]) >> (64 - BISHOP_MAGIC_BIT_COUNT[square]);
-            square_entry[key as usize] = attack;
-        }
-    }
-
-    table
-};
-
 // ---------------------------------------------------------------------------------------------------------------------
 // ---------------------------------------------------------------------------------------------------------------------
 // UTILITY -------------------------------------------------------------------------------------------------------------
 // ---------------------------------------------------------------------------------------------------------------------
 // ---------------------------------------------------------------------------------------------------------------------
 
-fn blocker_permutation(mut i: usize, mut mask: BitBoard) -> BitBoard {
-    let mut blockers = BitBoard::EMPTY;
+fn pawn_attacks(square: Square, color: Color) -> BitBoard {
+    let mut bb = BitBoard::EMPTY;
+    bb.set(square);
 
-    while i != 0 {
-        if (i & 1) != 0 {
-            let idx = Square::try_from_primitive(mask.to_u64().trailing_zeros() as u8).unwrap();
-            blockers.set(idx);
-        }
-
-        i >>= 1;
-        mask.silent_pop();
+    match color {
+        Color::White => bb.shifted_northeast().union(bb.shifted_northwest()),
+        Color::Black => bb.shifted_southeast().union(bb.shifted_southwest()),
     }
+}
 
-    blockers
+fn square_offset(square: Square, offset: i8) -> Option<Square> {
+    let index = u8::from(square) as i8 + offset;
+    Square::try_from_primitive(u8::try_from(index).ok()?).ok()
 }
 
 fn insert_promotions(list: &mut MoveList, builder: ChessMoveBuilder, color: Color) {
@@ -0,0 +1,283 @@
+//! Legal move generation.
+//!
+//! The moves are generated directly from the checkers and the pinned pieces of the current position: In check, only
+//! the evasions are generated and pinned pieces only move along their pin. So no move has to be made and taken back
+//! to test its legality. Only the moves of the king and en passant captures are tested one by one.
+
+use super::{bishop_moves, insert_promotions, pawn_attacks, rook_moves, square_offset, MoveList};
+use crate::{
+    board::Board,
+    chess_move::{ChessMove, ChessMoveBuilder},
+    tables::{BETWEEN, BISHOP_MOVE_PATTERNS, KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS, ROOK_MOVE_PATTERNS},
+};
+use mattis_bitboard::BitBoard;
+use mattis_types::{Color, Piece, PieceType, Rank, Square};
+use smallvec::SmallVec;
+
+/// A pinned piece together with the squares it may still move to (the ray between the king and the pinner,
+/// including the pinner).
+type Pins = SmallVec<[(Square, BitBoard); 8]>;
+
+impl Board {
+    /// Returns all pieces, that give check to the king of the side to move.
+    pub fn checkers(&self) -> BitBoard {
+        let king_square = self.king_square[self.color];
+
+        self.attackers_to(king_square, self.bb_all)
+            .intersection(self.bb_all_per_color[self.color.flipped()])
+    }
+
+    /// Returns all pieces of the side to move, that can't leave the line between their king and an enemy slider.
+    pub fn pinned(&self) -> BitBoard {
+        let mut pinned = BitBoard::EMPTY;
+
+        for (square, _) in self.pins() {
+            pinned.set(square);
+        }
+
+        pinned
+    }
+
+    /// Generates all legal moves.
+    ///
+    /// In check, only evasions are generated and in double check only king moves. Pinned pieces only move along the
+    /// line to their pinner.
+    pub fn generate_legal_moves(&self, list: &mut MoveList) {
+        let checkers = self.checkers();
+        let king_square = self.king_square[self.color];
+        let own_pieces = self.bb_all_per_color[self.color];
+
+        for end in KING_MOVE_PATTERNS[king_square].without(own_pieces).iter_bit_indices() {
+            let m = self.build_move(king_square, end).finish();
+            if self.is_legal_king_move(m) {
+                list.push(m);
+            }
+        }
+
+        // In double check, the king has to move.
+        if checkers.bit_count() > 1 {
+            return;
+        }
+
+        // In single check, the other pieces have to capture the checker or block the check.
+        let targets = match { checkers }.pop() {
+            Some(checker) => {
+                let mut targets = BETWEEN[king_square][checker];
+                targets.set(checker);
+                targets
+            }
+            None => {
+                let mut castles = MoveList::new();
+                self.generate_castling_moves(&mut castles);
+                list.extend(castles.into_iter().filter(|m| self.is_legal_king_move(*m)));
+                BitBoard::FULL.without(own_pieces)
+            }
+        };
+
+        let pins = self.pins();
+        let targets_from = |start: Square| match pins.iter().find(|(square, _)| *square == start) {
+            Some((_, ray)) => targets.intersection(*ray),
+            None => targets,
+        };
+
+        for piece_type in [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen] {
+            for start in self.bitboards[Piece::new(piece_type, self.color)].iter_bit_indices() {
+                let attacks = match piece_type {
+                    PieceType::Knight => KNIGHT_MOVE_PATTERNS[start],
+                    PieceType::Bishop => bishop_moves(start, self.bb_all),
+                    PieceType::Rook => rook_moves(start, self.bb_all),
+                    _ => bishop_moves(start, self.bb_all).union(rook_moves(start, self.bb_all)),
+                };
+
+                for end in attacks.intersection(targets_from(start)).iter_bit_indices() {
+                    list.push(self.build_move(start, end).finish());
+                }
+            }
+        }
+
+        let (forward, double_push_rank, promotion_rank) = match self.color {
+            Color::White => (8, Rank::R2, Rank::R8),
+            Color::Black => (-8, Rank::R7, Rank::R1),
+        };
+        let push_pawn_move = |list: &mut MoveList, m: ChessMoveBuilder, end: Square| {
+            if end.rank() == promotion_rank {
+                insert_promotions(list, m, self.color);
+            } else {
+                list.push(m.finish());
+            }
+        };
+
+        for start in self.bitboards[Piece::new(PieceType::Pawn, self.color)].iter_bit_indices() {
+            let targets = targets_from(start);
+            let captures = pawn_attacks(start, self.color).intersection(self.bb_all_per_color[self.color.flipped()]);
+
+            for end in captures.intersection(targets).iter_bit_indices() {
+                push_pawn_move(list, self.build_move(start, end), end);
+            }
+
+            let Some(single_push) = square_offset(start, forward).filter(|&end| self.pieces[end].is_none()) else {
+                continue;
+            };
+
+            if targets.get(single_push) {
+                push_pawn_move(list, self.build_move(start, single_push), single_push);
+            }
+
+            // The double push can block a check, even if the single push can't.
+            if start.rank() == double_push_rank {
+                let double_push = square_offset(single_push, forward).unwrap();
+                if self.pieces[double_push].is_none() && targets.get(double_push) {
+                    let m = ChessMove::build().start(start).end(double_push).double_pawn_push();
+                    list.push(m.finish());
+                }
+            }
+        }
+
+        // En passant removes two pieces from their squares, so we just check the resulting position.
+        if let Some(end) = self.en_passant {
+            let pawns = self.bitboards[Piece::new(PieceType::Pawn, self.color)];
+            let starts = pawn_attacks(end, self.color.flipped()).intersection(pawns);
+
+            for start in starts.iter_bit_indices() {
+                let m = ChessMove::build().start(start).end(end).en_passant().finish();
+                if self.is_legal_en_passant(m) {
+                    list.push(m);
+                }
+            }
+        }
+    }
+
+    /// Checks, if the side to move has any legal move (i.e. it is not checkmate or stalemate).
+    pub fn has_legal_moves(&self) -> bool {
+        let mut moves = MoveList::new();
+        self.generate_legal_moves(&mut moves);
+        !moves.is_empty()
+    }
+
+    fn pins(&self) -> Pins {
+        let king_square = self.king_square[self.color];
+        let op_color = self.color.flipped();
+        let mut pins = Pins::new();
+
+        let rooks_and_queens = self.bitboards[Piece::new(PieceType::Rook, op_color)]
+            .union(self.bitboards[Piece::new(PieceType::Queen, op_color)]);
+        let bishops_and_queens = self.bitboards[Piece::new(PieceType::Bishop, op_color)]
+            .union(self.bitboards[Piece::new(PieceType::Queen, op_color)]);
+
+        // Enemy sliders, that would attack the king on an empty board.
+        let snipers = ROOK_MOVE_PATTERNS[king_square]
+            .intersection(rooks_and_queens)
+            .union(BISHOP_MOVE_PATTERNS[king_square].intersection(bishops_and_queens));
+
+        for sniper in snipers.iter_bit_indices() {
+            let ray = BETWEEN[king_square][sniper];
+            let mut blockers = ray.intersection(self.bb_all);
+
+            // A piece is pinned, if it is the only piece between the king and the sniper.
+            if blockers.bit_count() == 1 {
+                let blocker = blockers.pop().unwrap();
+
+                if self.bb_all_per_color[self.color].get(blocker) {
+                    let mut ray = ray;
+                    ray.set(sniper);
+                    pins.push((blocker, ray));
+                }
+            }
+        }
+
+        pins
+    }
+
+    /// A quiet move or a capture, depending on the target square.
+    fn build_move(&self, start: Square, end: Square) -> ChessMoveBuilder {
+        let m = ChessMove::build().start(start).end(end);
+
+        match self.pieces[end] {
+            Some(_) => m.capture(),
+            None => m,
+        }
+    }
+
+    fn is_legal_king_move(&self, m: ChessMove) -> bool {
+        // The king must not be on the board, when checking the target square.
+        // Otherwise it could hide an attack along the line of a slider, that is giving check right now.
+        let occupancy = self.bb_all.without(square_bitboard(m.start()));
+
+        self.attackers_to(m.end(), occupancy)
+            .intersection(self.bb_all_per_color[self.color.flipped()])
+            .without(square_bitboard(m.end()))
+            .is_empty()
+    }
+
+    fn is_legal_en_passant(&self, m: ChessMove) -> bool {
+        let captured_square = Square::from_file_rank(m.end().file(), m.start().rank());
+
+        let mut occupancy = self.bb_all;
+        occupancy.clear(m.start());
+        occupancy.clear(captured_square);
+        occupancy.set(m.end());
+
+        // `attackers_to` only returns pieces within the occupancy, so the captured pawn can't give check anymore.
+        self.attackers_to(self.king_square[self.color], occupancy)
+            .intersection(self.bb_all_per_color[self.color.flipped()])
+            .is_empty()
+    }
+}
+
+fn square_bitboard(square: Square) -> BitBoard {
+    let mut bb = BitBoard::EMPTY;
+    bb.set(square);
+    bb
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::{movegen::MoveList, Board};
+
+    #[test]
+    fn legal_moves_match_make_move() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+            "4k3/8/8/8/1b6/8/3P4/4K3 w - - 0 1",
+            "4k3/4r3/8/8/1b6/8/3P4/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/4q3/4K3 w - - 0 1",
+            "7k/5Q2/8/8/8/8/8/K7 b - - 0 1",
+        ];
+
+        for fen in fens {
+            let mut board = Board::from_fen(fen).unwrap();
+
+            let mut legal = MoveList::new();
+            board.generate_legal_moves(&mut legal);
+
+            let mut pseudo_legal = MoveList::new();
+            board.generate_all_moves(&mut pseudo_legal);
+            pseudo_legal.retain(|m| {
+                let legal = board.make_move(*m);
+                if legal {
+                    board.take_move();
+                }
+                legal
+            });
+
+            assert_eq!(legal.len(), pseudo_legal.len(), "{fen}");
+            assert!(pseudo_legal.iter().all(|m| legal.contains(m)), "{fen}");
+        }
+    }
+
+    #[test]
+    fn stalemate_and_checkmate() {
+        let stalemate = Board::from_fen("7k/5Q2/8/8/8/8/8/K7 b - - 0 1").unwrap();
+        assert!(!stalemate.in_check());
+        assert!(!stalemate.has_legal_moves());
+
+        let checkmate = Board::from_fen("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(checkmate.in_check());
+        assert!(!checkmate.has_legal_moves());
+
+        assert!(Board::startpos().has_legal_moves());
+    }
+}
@@ -0,0 +1,405 @@
+//! Attacks of the sliding pieces.
+//!
+//! The attacks of rooks and bishops on all squares are stored in one table. Each square has its own row with
+//! `1 << bit_count` entries (fancy magics), and the rows follow each other without overlapping. A row is indexed by
+//! the blockers on the square's mask. The backends only differ in how they map the blockers to that index:
+//! - The magic backend multiplies the blockers with a magic number and keeps the highest bits.
+//! - The PEXT backend (`pext` feature, x86-64 only) gathers the blocker bits with the BMI2 `pext` instruction. It is
+//!   only used, if the CPU supports BMI2, otherwise the magic backend is used. The lookups can only be inlined, if
+//!   BMI2 is enabled at compile time (e.g. `RUSTFLAGS="-C target-cpu=native"`), so without that the function calls
+//!   eat up most of the gain. (`pext` is slow on AMD CPUs before Zen 3, so the magic backend may be faster there.)
+
+use crate::tables::{
+    BISHOP_MAGICS, BISHOP_MAGIC_BIT_COUNT, BISHOP_MAGIC_MASKS, ROOK_MAGICS, ROOK_MAGIC_BIT_COUNT, ROOK_MAGIC_MASKS,
+};
+use ctor::ctor;
+use mattis_bitboard::BitBoard;
+use mattis_types::{File, PieceType, Rank, Square, TryFromPrimitive};
+
+/// The squares attacked by a bishop on `square`, using the fastest backend available.
+#[inline]
+pub fn bishop_moves(square: Square, blockers: BitBoard) -> BitBoard {
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("bmi2") {
+        // Safety: We checked, that the CPU supports BMI2.
+        return unsafe { pext::bishop_moves(square, blockers) };
+    }
+
+    magic_bishop_moves(square, blockers)
+}
+
+/// The squares attacked by a rook on `square`, using the fastest backend available.
+#[inline]
+pub fn rook_moves(square: Square, blockers: BitBoard) -> BitBoard {
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    if is_x86_feature_detected!("bmi2") {
+        // Safety: We checked, that the CPU supports BMI2.
+        return unsafe { pext::rook_moves(square, blockers) };
+    }
+
+    magic_rook_moves(square, blockers)
+}
+
+pub fn magic_bishop_moves(square: Square, blockers: BitBoard) -> BitBoard {
+    let magic = &MAGIC_TABLE.bishop[square];
+    MAGIC_TABLE.lookup(magic, magic.magic_index(blockers))
+}
+
+pub fn magic_rook_moves(square: Square, blockers: BitBoard) -> BitBoard {
+    let magic = &MAGIC_TABLE.rook[square];
+    MAGIC_TABLE.lookup(magic, magic.magic_index(blockers))
+}
+
+#[ctor]
+static MAGIC_TABLE: SliderTable = SliderTable::new(|magic, blockers, _| magic.magic_index(blockers));
+
+/// A rook or bishop magic of `tables.rs`, that maps two blocker sets with different attacks to the same index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MagicCollision {
+    pub piece_type: PieceType,
+    pub square: Square,
+}
+
+/// Finds all magics, that aren't collision-free. The attack table is only correct, if there are none.
+pub fn magic_collisions() -> Vec<MagicCollision> {
+    let sliders = [
+        (
+            PieceType::Rook,
+            MAGIC_TABLE.rook,
+            rook_attacks as fn(Square, BitBoard) -> BitBoard,
+        ),
+        (PieceType::Bishop, MAGIC_TABLE.bishop, bishop_attacks),
+    ];
+    let mut collisions = Vec::new();
+
+    for (piece_type, magics, attacks) in sliders {
+        for (square, magic) in magics.iter().enumerate() {
+            let square = Square::try_from_primitive(square as u8).unwrap();
+            let mut row = vec![None; 1 << (64 - magic.shift)];
+
+            let collision_free = (0..1 << magic.mask.bit_count()).all(|i| {
+                let blockers = blocker_permutation(i, magic.mask);
+                let attacks = attacks(square, blockers);
+                *row[magic.magic_index(blockers)].get_or_insert(attacks) == attacks
+            });
+
+            if !collision_free {
+                collisions.push(MagicCollision { piece_type, square });
+            }
+        }
+    }
+
+    collisions
+}
+
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+pub mod pext {
+    use super::SliderTable;
+    use ctor::ctor;
+    use mattis_bitboard::BitBoard;
+    use mattis_types::Square;
+    use std::arch::x86_64::_pext_u64;
+
+    /// # Safety
+    /// The CPU must support BMI2.
+    #[inline]
+    #[target_feature(enable = "bmi2")]
+    pub unsafe fn bishop_moves(square: Square, blockers: BitBoard) -> BitBoard {
+        let magic = &PEXT_TABLE.bishop[square];
+        PEXT_TABLE.lookup(magic, _pext_u64(blockers.to_u64(), magic.mask.to_u64()) as usize)
+    }
+
+    /// # Safety
+    /// The CPU must support BMI2.
+    #[inline]
+    #[target_feature(enable = "bmi2")]
+    pub unsafe fn rook_moves(square: Square, blockers: BitBoard) -> BitBoard {
+        let magic = &PEXT_TABLE.rook[square];
+        PEXT_TABLE.lookup(magic, _pext_u64(blockers.to_u64(), magic.mask.to_u64()) as usize)
+    }
+
+    // `pext` of the i-th blocker permutation with its mask is `i` itself, so the table doesn't need BMI2 to be built.
+    // It stays empty on CPUs without BMI2, where it is never used.
+    #[ctor]
+    static PEXT_TABLE: SliderTable = if is_x86_feature_detected!("bmi2") {
+        SliderTable::new(|_, _, i| i)
+    } else {
+        SliderTable::empty()
+    };
+}
+
+/// The mask of a square, its magic and where its row starts in the attacks of the `SliderTable`.
+#[derive(Debug, Clone, Copy)]
+struct SquareMagic {
+    mask: BitBoard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl SquareMagic {
+    const EMPTY: Self = Self {
+        mask: BitBoard::EMPTY,
+        magic: 0,
+        shift: 64,
+        offset: 0,
+    };
+
+    #[inline]
+    fn magic_index(&self, blockers: BitBoard) -> usize {
+        (blockers.intersection(self.mask).to_u64().wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+/// The rook and bishop attacks of a backend in one allocation.
+#[derive(Debug)]
+struct SliderTable {
+    rook: [SquareMagic; 64],
+    bishop: [SquareMagic; 64],
+    attacks: Vec<BitBoard>,
+}
+
+impl SliderTable {
+    /// Builds the table of a backend. `index` maps a square's magic, the blockers on its mask and the number of the
+    /// blocker permutation to the blockers' entry in the square's row.
+    fn new(index: fn(&SquareMagic, BitBoard, usize) -> usize) -> Self {
+        let mut table = Self::empty();
+
+        for square in 0..64 {
+            let square = Square::try_from_primitive(square).unwrap();
+            table.rook[square] = table.add_row(
+                square,
+                ROOK_MAGIC_MASKS[square],
+                ROOK_MAGICS[square],
+                ROOK_MAGIC_BIT_COUNT[square],
+                rook_attacks,
+                index,
+            );
+            table.bishop[square] = table.add_row(
+                square,
+                BISHOP_MAGIC_MASKS[square],
+                BISHOP_MAGICS[square],
+                BISHOP_MAGIC_BIT_COUNT[square],
+                bishop_attacks,
+                index,
+            );
+        }
+
+        table
+    }
+
+    fn empty() -> Self {
+        Self {
+            rook: [SquareMagic::EMPTY; 64],
+            bishop: [SquareMagic::EMPTY; 64],
+            attacks: Vec::new(),
+        }
+    }
+
+    fn add_row(
+        &mut self,
+        square: Square,
+        mask: BitBoard,
+        magic: u64,
+        bit_count: u32,
+        attacks: fn(Square, BitBoard) -> BitBoard,
+        index: fn(&SquareMagic, BitBoard, usize) -> usize,
+    ) -> SquareMagic {
+        let magic = SquareMagic {
+            mask,
+            magic,
+            shift: 64 - bit_count,
+            offset: self.attacks.len(),
+        };
+        self.attacks.resize(magic.offset + (1 << bit_count), BitBoard::EMPTY);
+
+        for i in 0..1 << mask.bit_count() {
+            let blockers = blocker_permutation(i, mask);
+            self.attacks[magic.offset + index(&magic, blockers, i)] = attacks(square, blockers);
+        }
+
+        magic
+    }
+
+    /// The entry `index` of the square's row.
+    #[inline(always)]
+    fn lookup(&self, magic: &SquareMagic, index: usize) -> BitBoard {
+        // Safety: Every index is smaller than `1 << bit_count`, so it stays in the square's row.
+        unsafe { *self.attacks.get_unchecked(magic.offset + index) }
+    }
+}
+
+/// Calculates the rook attacks ray by ray. Only used to fill the attack tables.
+fn rook_attacks(square: Square, blockers: BitBoard) -> BitBoard {
+    let file = square.file();
+    let rank = square.rank();
+    let mut attack = BitBoard::EMPTY;
+
+    if let Some(r) = rank.up() {
+        for r in Rank::range_inclusive(r, Rank::R8) {
+            attack.set(Square::from_file_rank(file, r));
+            if blockers.get(Square::from_file_rank(file, r)) {
+                break;
+            }
+        }
+    }
+
+    if let Some(r) = rank.down() {
+        for r in Rank::range_inclusive(Rank::R1, r).rev() {
+            attack.set(Square::from_file_rank(file, r));
+            if blockers.get(Square::from_file_rank(file, r)) {
+                break;
+            }
+        }
+    }
+
+    if let Some(f) = file.up() {
+        for f in File::range_inclusive(f, File::H) {
+            attack.set(Square::from_file_rank(f, rank));
+            if blockers.get(Square::from_file_rank(f, rank)) {
+                break;
+            }
+        }
+    }
+
+    if let Some(f) = file.down() {
+        for f in File::range_inclusive(File::A, f).rev() {
+            attack.set(Square::from_file_rank(f, rank));
+            if blockers.get(Square::from_file_rank(f, rank)) {
+                break;
+            }
+        }
+    }
+
+    attack
+}
+
+/// Calculates the bishop attacks ray by ray. Only used to fill the attack tables.
+fn bishop_attacks(square: Square, blockers: BitBoard) -> BitBoard {
+    let file = square.file();
+    let rank = square.rank();
+    let mut attack = BitBoard::EMPTY;
+
+    if let Some((r, f)) = rank.up().zip(file.up()) {
+        for (r, f) in std::iter::zip(Rank::range_inclusive(r, Rank::R8), File::range_inclusive(f, File::H)) {
+            attack.set(Square::from_file_rank(f, r));
+            if blockers.get(Square::from_file_rank(f, r)) {
+                break;
+            }
+        }
+    }
+
+    if let Some((r, f)) = rank.up().zip(file.down()) {
+        for (r, f) in std::iter::zip(
+            Rank::range_inclusive(r, Rank::R8),
+            File::range_inclusive(File::A, f).rev(),
+        ) {
+            attack.set(Square::from_file_rank(f, r));
+            if blockers.get(Square::from_file_rank(f, r)) {
+                break;
+            }
+        }
+    }
+
+    if let Some((r, f)) = rank.down().zip(file.up()) {
+        for (r, f) in std::iter::zip(
+            Rank::range_inclusive(Rank::R1, r).rev(),
+            File::range_inclusive(f, File::H),
+        ) {
+            attack.set(Square::from_file_rank(f, r));
+            if blockers.get(Square::from_file_rank(f, r)) {
+                break;
+            }
+        }
+    }
+
+    if let Some((r, f)) = rank.down().zip(file.down()) {
+        for (r, f) in std::iter::zip(
+            Rank::range_inclusive(Rank::R1, r).rev(),
+            File::range_inclusive(File::A, f).rev(),
+        ) {
+            attack.set(Square::from_file_rank(f, r));
+            if blockers.get(Square::from_file_rank(f, r)) {
+                break;
+            }
+        }
+    }
+
+    attack
+}
+
+fn blocker_permutation(mut i: usize, mut mask: BitBoard) -> BitBoard {
+    let mut blockers = BitBoard::EMPTY;
+
+    while i != 0 {
+        if (i & 1) != 0 {
+            let idx = Square::try_from_primitive(mask.to_u64().trailing_zeros() as u8).unwrap();
+            blockers.set(idx);
+        }
+
+        i >>= 1;
+        mask.silent_pop();
+    }
+
+    blockers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bishop_attacks, magic_bishop_moves, magic_collisions, magic_rook_moves, rook_attacks};
+    use mattis_bitboard::BitBoard;
+    use mattis_types::{Square, TryFromPrimitive};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Random blockers with every density from empty to almost full boards.
+    fn random_blockers() -> Vec<BitBoard> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..2000)
+            .map(|i| {
+                let bits = (0..=i % 4).fold(u64::MAX, |bits, _| bits & rng.gen::<u64>());
+                BitBoard::from_u64(if i % 8 == 0 { !bits } else { bits })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn magics_are_collision_free() {
+        assert_eq!(magic_collisions(), []);
+    }
+
+    #[test]
+    fn magic_moves_match_ray_attacks() {
+        for blockers in random_blockers() {
+            for square in 0..64 {
+                let square = Square::try_from_primitive(square).unwrap();
+                assert_eq!(magic_rook_moves(square, blockers), rook_attacks(square, blockers));
+                assert_eq!(magic_bishop_moves(square, blockers), bishop_attacks(square, blockers));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "pext", target_arch = "x86_64"))]
+    fn pext_moves_match_magic_moves() {
+        if !is_x86_feature_detected!("bmi2") {
+            return;
+        }
+
+        for blockers in random_blockers() {
+            for square in 0..64 {
+                let square = Square::try_from_primitive(square).unwrap();
+                // Safety: We checked, that the CPU supports BMI2.
+                unsafe {
+                    assert_eq!(
+                        super::pext::rook_moves(square, blockers),
+                        magic_rook_moves(square, blockers)
+                    );
+                    assert_eq!(
+                        super::pext::bishop_moves(square, blockers),
+                        magic_bishop_moves(square, blockers)
+                    );
+                }
+            }
+        }
+    }
+}
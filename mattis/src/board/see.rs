@@ -0,0 +1,255 @@
+use super::{
+    movegen::{bishop_moves, rook_moves},
+    Board,
+};
+use crate::{
+    chess_move::ChessMove,
+    tables::{KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS},
+};
+use mattis_bitboard::BitBoard;
+use mattis_types::{Color, Piece, PieceType, Square};
+
+impl Board {
+    /// Returns all pieces of both colors, that attack `square`, if only the pieces in `occupancy` were on the board.
+    ///
+    /// Sliding pieces are found through the given occupancy, so removing a piece from it reveals the
+    /// x-ray attackers behind it.
+    pub fn attackers_to(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        let mut bb_square = BitBoard::EMPTY;
+        bb_square.set(square);
+
+        // A white pawn attacks the square, if it stands on a square, that a black pawn on `square` would attack.
+        let white_pawns = bb_square
+            .shifted_southeast()
+            .union(bb_square.shifted_southwest())
+            .intersection(self.bitboards[Piece::WhitePawn]);
+
+        let black_pawns = bb_square
+            .shifted_northeast()
+            .union(bb_square.shifted_northwest())
+            .intersection(self.bitboards[Piece::BlackPawn]);
+
+        let knights = KNIGHT_MOVE_PATTERNS[square]
+            .intersection(self.bitboards[Piece::WhiteKnight].union(self.bitboards[Piece::BlackKnight]));
+
+        let kings = KING_MOVE_PATTERNS[square]
+            .intersection(self.bitboards[Piece::WhiteKing].union(self.bitboards[Piece::BlackKing]));
+
+        let bishops = bishop_moves(square, occupancy).intersection(self.diagonal_sliders());
+        let rooks = rook_moves(square, occupancy).intersection(self.straight_sliders());
+
+        white_pawns
+            .union(black_pawns)
+            .union(knights)
+            .union(kings)
+            .union(bishops)
+            .union(rooks)
+            .intersection(occupancy)
+    }
+
+    /// Static exchange evaluation: Returns true, if the material balance after the exchange sequence
+    /// on the target square of `m` is at least `threshold` for the moving side.
+    ///
+    /// Both sides always recapture with their least valuable attacker and may stop capturing at any time.
+    /// Pins and checks are ignored.
+    pub fn see(&self, m: ChessMove, threshold: i16) -> bool {
+        if m.is_kingside_castle() || m.is_queenside_castle() {
+            return threshold <= 0;
+        }
+
+        let start = m.start();
+        let end = m.end();
+
+        // Safety: A chess move always moves a piece.
+        let mover = unsafe { self.pieces[start].unwrap_unchecked().piece_type() };
+
+        let captured = if m.is_en_passant() {
+            Some(PieceType::Pawn)
+        } else {
+            self.pieces[end].map(Piece::piece_type)
+        };
+
+        // The first capture is forced, so we already know the balance after it.
+        let mut swap = captured.map(PieceType::value).unwrap_or(0) - threshold;
+        let mut next_victim = mover;
+
+        if let Some(promoted) = m.promoted() {
+            swap += promoted.value() - PieceType::Pawn.value();
+            next_victim = promoted;
+        }
+
+        if swap < 0 {
+            return false;
+        }
+
+        // Even if the opponent recaptures, we are still above the threshold.
+        swap = next_victim.value() - swap;
+        if swap <= 0 {
+            return true;
+        }
+
+        let mut occupancy = self.bb_all;
+        occupancy.clear(start);
+        occupancy.set(end);
+
+        if m.is_en_passant() {
+            let captured_square = Square::from_file_rank(end.file(), start.rank());
+            occupancy.clear(captured_square);
+        }
+
+        let mut attackers = self.attackers_to(end, occupancy);
+        let mut color = self.color;
+        let mut result = true;
+
+        loop {
+            color = color.flipped();
+            attackers = attackers.intersection(occupancy);
+
+            let own_attackers = attackers.intersection(self.bb_all_per_color[color]);
+            if own_attackers.is_empty() {
+                break;
+            }
+
+            result = !result;
+
+            let Some((attacker, square)) = least_valuable_attacker(self, own_attackers, color) else {
+                break;
+            };
+
+            // The king may only capture, if the opponent has no attackers left.
+            if attacker == PieceType::King {
+                let opponent_attackers = attackers.intersection(self.bb_all_per_color[color.flipped()]);
+                return if opponent_attackers.is_empty() { result } else { !result };
+            }
+
+            swap = attacker.value() - swap;
+            if swap < result as i16 {
+                break;
+            }
+
+            occupancy.clear(square);
+
+            // Add the x-ray attackers, that were hidden behind the capturing piece.
+            if matches!(attacker, PieceType::Pawn | PieceType::Bishop | PieceType::Queen) {
+                attackers = attackers.union(bishop_moves(end, occupancy).intersection(self.diagonal_sliders()));
+            }
+
+            if matches!(attacker, PieceType::Rook | PieceType::Queen) {
+                attackers = attackers.union(rook_moves(end, occupancy).intersection(self.straight_sliders()));
+            }
+        }
+
+        result
+    }
+
+    fn diagonal_sliders(&self) -> BitBoard {
+        self.bitboards[Piece::WhiteBishop]
+            .union(self.bitboards[Piece::BlackBishop])
+            .union(self.bitboards[Piece::WhiteQueen])
+            .union(self.bitboards[Piece::BlackQueen])
+    }
+
+    fn straight_sliders(&self) -> BitBoard {
+        self.bitboards[Piece::WhiteRook]
+            .union(self.bitboards[Piece::BlackRook])
+            .union(self.bitboards[Piece::WhiteQueen])
+            .union(self.bitboards[Piece::BlackQueen])
+    }
+}
+
+fn least_valuable_attacker(board: &Board, attackers: BitBoard, color: Color) -> Option<(PieceType, Square)> {
+    PieceType::ALL.into_iter().find_map(|piece_type| {
+        let mut candidates = attackers.intersection(board.bitboards[Piece::new(piece_type, color)]);
+        candidates.pop().map(|square| (piece_type, square))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{board::Board, notation::SmithNotation};
+    use mattis_bitboard::BitBoard;
+    use mattis_types::{Color, PieceType, Square};
+
+    fn see_value(fen: &str, move_str: &str) -> i16 {
+        let mut board = Board::from_fen(fen).unwrap();
+        let m = board.find_move::<SmithNotation>(move_str).unwrap();
+
+        // Binary search the exact exchange value through the threshold interface.
+        let (mut low, mut high) = (-2 * PieceType::Queen.value(), 2 * PieceType::Queen.value());
+        while low < high {
+            let mid = (low + high + 1).div_euclid(2);
+            if board.see(m, mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        low
+    }
+
+    #[test]
+    fn attackers_with_x_rays() {
+        let board = Board::from_fen("3q3k/8/8/3r4/8/3R4/3Q4/K7 w - - 0 1").unwrap();
+        let mut occupancy = board.bb_all;
+
+        let mut expected = BitBoard::EMPTY;
+        expected.set(Square::D3);
+        assert_eq!(
+            board
+                .attackers_to(Square::D4, occupancy)
+                .intersection(board.bb_all_per_color[Color::White]),
+            expected
+        );
+
+        // Removing the rook reveals the queen behind it.
+        occupancy.clear(Square::D3);
+        expected.clear(Square::D3);
+        expected.set(Square::D2);
+        assert_eq!(
+            board
+                .attackers_to(Square::D4, occupancy)
+                .intersection(board.bb_all_per_color[Color::White]),
+            expected
+        );
+    }
+
+    #[test]
+    fn static_exchange_evaluation() {
+        let pawn = PieceType::Pawn.value();
+        let knight = PieceType::Knight.value();
+        let rook = PieceType::Rook.value();
+        let queen = PieceType::Queen.value();
+
+        // Undefended pawn
+        assert_eq!(
+            see_value("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            pawn
+        );
+        // Queen takes a pawn defended by a pawn
+        assert_eq!(see_value("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"), pawn - queen);
+        // Knight takes a pawn defended by a knight, with a rook x-ray on both sides
+        assert_eq!(
+            see_value("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5"),
+            pawn - knight
+        );
+        // Rook takes a rook defended by a rook, but we have a second rook behind
+        assert_eq!(see_value("3r3k/3r4/8/8/8/8/3R4/3R3K w - - 0 1", "d2d7"), rook);
+        // Quiet move to an attacked square
+        assert_eq!(see_value("4k3/8/8/8/8/4p3/8/3N3K w - - 0 1", "d1f2"), -knight);
+        // Quiet move to a safe square
+        assert_eq!(see_value("4k3/8/8/8/8/8/8/3N3K w - - 0 1", "d1f2"), 0);
+        // Capture with promotion, followed by the king recapturing
+        assert_eq!(see_value("4k3/8/8/8/8/8/1K1p4/2R5 b - - 0 1", "d2c1q"), rook - pawn);
+        // The king can't recapture, because the square is defended by an x-ray through the promoted queen
+        assert_eq!(
+            see_value("2r1k3/8/8/8/8/8/1K1p4/2R5 b - - 0 1", "d2c1q"),
+            rook + queen - pawn
+        );
+        // En passant
+        assert_eq!(see_value("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), pawn);
+        // The king can only recapture, if the square isn't defended
+        assert_eq!(see_value("4k3/4q3/8/8/8/8/4R3/4K3 w - - 0 1", "e2e7"), queen - rook);
+        assert_eq!(see_value("4k3/4q3/8/8/8/8/4R3/4RK2 w - - 0 1", "e2e7"), queen);
+    }
+}
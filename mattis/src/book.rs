@@ -0,0 +1,379 @@
+//! Opening books in the Polyglot `.bin` format.
+//!
+//! # Binary Format
+//! A book is a sequence of entries of 16 bytes, sorted by the key of the position. Values are big endian.
+//! ```text
+//! // offset   type    content
+//! // 0        u64     polyglot key of the position
+//! // 8        u16     move (bits 0-5: end square, 6-11: start square, 12-14: promotion, 1 = knight ... 4 = queen)
+//! // 10       u16     weight of the move, relative to the other moves of the position
+//! // 12       u32     learn value (unused)
+//! ```
+//! Castling moves are stored as the king capturing its own rook. The keys are computed with the random numbers
+//! of the Polyglot specification, so they differ from our own zobrist keys.
+
+pub mod builder;
+mod keys;
+
+use self::keys::POLYGLOT_RANDOM;
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+};
+use mattis_types::{CastlePerm, Color, Piece, PieceType};
+use rand::Rng;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BookError {
+    #[error("could not read or write the book file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("book file has a size of {0} bytes, which is not a multiple of 16")]
+    InvalidSize(usize),
+}
+
+/// How a move is picked, if the book contains several moves for a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BookSelection {
+    /// Randomly, with a probability proportional to the weight of the move
+    #[default]
+    Weighted,
+    /// Always the move with the highest weight
+    Best,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct BookEntry {
+    pub key: u64,
+    pub move_: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let [k0, k1, k2, k3, k4, k5, k6, k7, m0, m1, w0, w1, l0, l1, l2, l3] = bytes;
+
+        Self {
+            key: u64::from_be_bytes([k0, k1, k2, k3, k4, k5, k6, k7]),
+            move_: u16::from_be_bytes([m0, m1]),
+            weight: u16::from_be_bytes([w0, w1]),
+            learn: u32::from_be_bytes([l0, l1, l2, l3]),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.key.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.move_.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.weight.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.learn.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>, // Sorted by key
+}
+
+impl PolyglotBook {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BookError> {
+        if !bytes.len().is_multiple_of(BookEntry::SIZE) {
+            return Err(BookError::InvalidSize(bytes.len()));
+        }
+
+        let mut entries: Vec<_> = bytes
+            .chunks_exact(BookEntry::SIZE)
+            .map(|chunk| BookEntry::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        // Books should already be sorted, but we don't rely on other tools for the binary search.
+        // The sort is stable, so the moves of a position keep their order.
+        entries.sort_by_key(|entry| entry.key);
+
+        Ok(Self { entries })
+    }
+
+    /// Writes the book in the binary format.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in &self.entries {
+            writer.write_all(&entry.to_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    fn from_sorted_entries(entries: Vec<BookEntry>) -> Self {
+        debug_assert!(entries.windows(2).all(|pair| pair[0].key <= pair[1].key));
+        Self { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns all entries with the given key, using a binary search.
+    pub fn entries(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let len = self.entries[start..].partition_point(|entry| entry.key == key);
+        &self.entries[start..start + len]
+    }
+
+    /// Returns the book moves of the position with their weights. Entries with illegal moves are ignored.
+    pub fn moves(&self, board: &Board) -> Vec<(ChessMove, u16)> {
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        self.entries(polyglot_key(board))
+            .iter()
+            .filter_map(|entry| {
+                let m = legal_moves.iter().find(|&&m| encode_move(m) == entry.move_)?;
+                Some((*m, entry.weight))
+            })
+            .collect()
+    }
+
+    /// Picks a book move for the position. Moves with a weight of 0 are never picked.
+    pub fn pick(&self, board: &Board, selection: BookSelection, rng: &mut impl Rng) -> Option<ChessMove> {
+        let moves = self.moves(board);
+
+        match selection {
+            BookSelection::Best => moves
+                .into_iter()
+                .filter(|&(_, weight)| weight > 0)
+                .max_by_key(|&(_, weight)| weight)
+                .map(|(m, _)| m),
+            BookSelection::Weighted => {
+                let total: u32 = moves.iter().map(|&(_, weight)| weight as u32).sum();
+                if total == 0 {
+                    return None;
+                }
+
+                let mut target = rng.gen_range(0..total);
+                moves
+                    .into_iter()
+                    .find(|&(_, weight)| {
+                        let found = target < weight as u32;
+                        target = target.saturating_sub(weight as u32);
+                        found
+                    })
+                    .map(|(m, _)| m)
+            }
+        }
+    }
+}
+
+/// Computes the Polyglot key of the position.
+pub fn polyglot_key(board: &Board) -> u64 {
+    let mut key = 0;
+
+    for (square, piece) in board.pieces.iter().enumerate() {
+        if let Some(piece) = piece {
+            // Our pieces start with white, while Polyglot starts with black.
+            key ^= POLYGLOT_RANDOM[64 * (*piece as usize ^ 1) + square];
+        }
+    }
+
+    let castle_perms = [
+        CastlePerm::WhiteKingside,
+        CastlePerm::WhiteQueenside,
+        CastlePerm::BlackKingside,
+        CastlePerm::BlackQueenside,
+    ];
+    for (i, perm) in castle_perms.into_iter().enumerate() {
+        if board.castle_perms.get(perm) {
+            key ^= POLYGLOT_RANDOM[768 + i];
+        }
+    }
+
+    // The en passant square only counts, if a pawn can actually capture on it (ignoring pins).
+    if let Some(square) = board.en_passant {
+        let file = usize::from(square.file());
+        let pushed_pawn = match board.color {
+            Color::White => square as usize - 8,
+            Color::Black => square as usize + 8,
+        };
+        let pawn = Piece::new(PieceType::Pawn, board.color);
+        let can_capture = [file.checked_sub(1), (file < 7).then_some(file + 1)]
+            .into_iter()
+            .flatten()
+            .any(|capture_file| board.pieces[pushed_pawn - file + capture_file] == Some(pawn));
+
+        if can_capture {
+            key ^= POLYGLOT_RANDOM[772 + file];
+        }
+    }
+
+    if board.color == Color::White {
+        key ^= POLYGLOT_RANDOM[780];
+    }
+
+    key
+}
+
+/// Encodes the move in the Polyglot format.
+pub fn encode_move(m: ChessMove) -> u16 {
+    let start = m.start() as u16;
+    // Castling is encoded as the king capturing the rook.
+    let end = if m.is_kingside_castle() {
+        m.end() as u16 + 1
+    } else if m.is_queenside_castle() {
+        m.end() as u16 - 2
+    } else {
+        m.end() as u16
+    };
+    // `PieceType` has the same order as Polyglot: knight = 1 ... queen = 4
+    let promotion = m.promoted().map_or(0, |piece_type| piece_type as u16);
+
+    (promotion << 12) | (start << 6) | end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_move, polyglot_key, BookEntry, BookError, BookSelection, PolyglotBook};
+    use crate::{board::Board, notation::SmithNotation};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn polyglot_keys() {
+        // Test positions from the Polyglot specification
+        let positions = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0x463b96181691fc9c,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                0x823c9b50fd114196,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+                0x0756b94461c50fb0,
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+                0x662fafb965db29d4,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                0x22a48b5a8e47ff78,
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3",
+                0x652a607ca3f242c1,
+            ),
+            (
+                "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+                0x00fdd303c946bdd9,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3",
+                0x3c8123ea7b067637,
+            ),
+            (
+                "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+                0x5c3f9b829b279560,
+            ),
+        ];
+
+        for (fen, key) in positions {
+            assert_eq!(polyglot_key(&Board::from_fen(fen).unwrap()), key, "{fen}");
+        }
+    }
+
+    #[test]
+    fn read_book_file() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/4P3/R3K2R w KQkq - 0 1").unwrap();
+        let key = polyglot_key(&board);
+        let mut entry = |move_str: &str, weight| BookEntry {
+            key,
+            move_: encode_move(board.find_move::<SmithNotation>(move_str).unwrap()),
+            weight,
+            learn: 0,
+        };
+
+        let entries = [
+            BookEntry {
+                key: key + 1,
+                move_: 0,
+                weight: 1000,
+                learn: 0,
+            },
+            entry("e1g1", 30),
+            entry("e2e4", 10),
+            entry("e1c1", 0),
+            BookEntry {
+                key: key - 1,
+                move_: 0,
+                weight: 1000,
+                learn: 0,
+            },
+            BookEntry {
+                key,
+                move_: 0x0FFF,
+                weight: 1000,
+                learn: 0,
+            }, // Illegal move
+        ];
+        assert_eq!(entries[1].move_, 0x0107); // e1h1
+
+        let path = std::env::temp_dir().join(format!("mattis-book-test-{}.bin", std::process::id()));
+        std::fs::write(&path, entries.iter().flat_map(|e| e.to_bytes()).collect::<Vec<_>>()).unwrap();
+        let book = PolyglotBook::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(book.len(), entries.len());
+        assert_eq!(book.entries(key).len(), 4);
+
+        let moves: Vec<_> = book
+            .moves(&board)
+            .into_iter()
+            .map(|(m, weight)| (format!("{}", m.display_smith()), weight))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                ("e1g1".to_string(), 30),
+                ("e2e4".to_string(), 10),
+                ("e1c1".to_string(), 0)
+            ]
+        );
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let castle = board.find_move::<SmithNotation>("e1g1");
+        assert_eq!(book.pick(&board, BookSelection::Best, &mut rng), castle);
+
+        let picks: Vec<_> = (0..400)
+            .map(|_| book.pick(&board, BookSelection::Weighted, &mut rng).unwrap())
+            .collect();
+        let castles = picks.iter().filter(|&&m| Some(m) == castle).count();
+        assert!((250..350).contains(&castles), "{castles}");
+        assert!(picks.iter().all(|m| format!("{}", m.display_smith()) != "e1c1"));
+
+        // Out of book
+        assert_eq!(book.pick(&Board::startpos(), BookSelection::Weighted, &mut rng), None);
+
+        assert!(matches!(
+            PolyglotBook::from_bytes(&[0; 17]),
+            Err(BookError::InvalidSize(17))
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! Building Polyglot books from PGN games.
+//!
+//! Every game is replayed up to a maximum number of plies. For each position and move, the number of games
+//! and their results from the point of view of the moving side are collected. Moves, that were played in too
+//! few games or scored too badly, are left out. The weight of a move is `2 * wins + draws`, as in Polyglot.
+
+use super::{encode_move, polyglot_key, BookEntry, PolyglotBook};
+use crate::pgn::{GameResult, PgnError, PgnGame, PgnReader};
+use mattis_types::Color;
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookBuilderConfig {
+    pub max_plies: usize, // Only moves up to this ply of each game are added
+    pub min_games: u32,   // Minimum number of games, in which a move has to be played
+    pub min_score: f64,   // Minimum score of a move (between 0.0 and 1.0), from the view of the moving side
+}
+
+impl Default for BookBuilderConfig {
+    fn default() -> Self {
+        Self {
+            max_plies: 16,
+            min_games: 1,
+            min_score: 0.0,
+        }
+    }
+}
+
+/// The results of all games, in which a move was played in a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MoveStats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl MoveStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    fn weight(&self) -> u64 {
+        2 * self.wins as u64 + self.draws as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BuildSummary {
+    pub games: u64,         // Games, that were added to the book
+    pub skipped_games: u64, // Games without result or with an illegal move
+    pub entries: usize,     // Entries in the finished book
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BookBuilder {
+    config: BookBuilderConfig,
+    moves: HashMap<(u64, u16), MoveStats>, // Indexed by the polyglot key and the polyglot move
+    summary: BuildSummary,
+}
+
+impl BookBuilder {
+    pub fn new(config: BookBuilderConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the moves of the game. Games without a result are skipped.
+    ///
+    /// If the game contains an illegal move, only the moves before it are added.
+    pub fn add_game(&mut self, game: &PgnGame) -> Result<(), PgnError> {
+        if game.result == GameResult::Unknown {
+            self.summary.skipped_games += 1;
+            return Ok(());
+        }
+
+        let mut moves = Vec::with_capacity(self.config.max_plies);
+        let replay = game.replay(|board, m| {
+            if moves.len() < self.config.max_plies {
+                moves.push((polyglot_key(board), encode_move(m), board.color));
+            }
+        });
+
+        for (key, m, color) in moves {
+            let stats = self.moves.entry((key, m)).or_default();
+            match (game.result, color) {
+                (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => stats.wins += 1,
+                (GameResult::WhiteWins, Color::Black) | (GameResult::BlackWins, Color::White) => stats.losses += 1,
+                _ => stats.draws += 1,
+            }
+        }
+
+        match replay {
+            Ok(()) => self.summary.games += 1,
+            Err(_) => self.summary.skipped_games += 1,
+        }
+
+        replay
+    }
+
+    /// Returns the collected stats of a move.
+    pub fn move_stats(&self, key: u64, m: u16) -> Option<MoveStats> {
+        self.moves.get(&(key, m)).copied()
+    }
+
+    /// Builds the book from all moves, that pass the filters.
+    pub fn finish(self) -> (PolyglotBook, BuildSummary) {
+        let moves: Vec<_> = self
+            .moves
+            .into_iter()
+            .filter(|(_, stats)| stats.games() >= self.config.min_games && stats.score() >= self.config.min_score)
+            .collect();
+
+        // Weights are scaled down, if they don't fit into 16 bits.
+        let max_weight = moves.iter().map(|(_, stats)| stats.weight()).max().unwrap_or(0);
+        let scale = (u16::MAX as u64).max(max_weight);
+
+        let mut entries: Vec<_> = moves
+            .into_iter()
+            .map(|((key, move_), stats)| BookEntry {
+                key,
+                move_,
+                weight: (stats.weight() * u16::MAX as u64 / scale) as u16,
+                learn: 0,
+            })
+            .collect();
+
+        // Within a position, the moves are sorted from best to worst.
+        entries.sort_by_key(|entry| (entry.key, std::cmp::Reverse(entry.weight), entry.move_));
+
+        let summary = BuildSummary {
+            entries: entries.len(),
+            ..self.summary
+        };
+        (PolyglotBook::from_sorted_entries(entries), summary)
+    }
+}
+
+/// Builds a book from all games of the PGN files and writes it to `output`.
+///
+/// Games with illegal moves are counted as skipped, instead of failing the whole build.
+pub fn build(config: BookBuilderConfig, inputs: &[impl AsRef<Path>], output: &Path) -> Result<BuildSummary, PgnError> {
+    let mut builder = BookBuilder::new(config);
+
+    for input in inputs {
+        let reader = PgnReader::new(BufReader::new(File::open(input)?));
+
+        for game in reader {
+            match builder.add_game(&game?) {
+                Ok(()) | Err(PgnError::IllegalMove { .. } | PgnError::InvalidFen(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    let (book, summary) = builder.finish();
+    book.save(output)?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BookBuilder, BookBuilderConfig, MoveStats};
+    use crate::{
+        board::Board,
+        book::{encode_move, polyglot_key, BookSelection, PolyglotBook},
+        notation::SmithNotation,
+        pgn::PgnReader,
+    };
+
+    const PGN: &str = "
+1. e4 e5 2. Nf3 Nc6 1-0
+1. e4 e5 2. Nf3 Nf6 1/2-1/2
+1. e4 c5 0-1
+1. d4 d5 2. c4 1-0
+1. e4 e5 *
+";
+
+    fn build(config: BookBuilderConfig) -> (BookBuilder, PolyglotBook) {
+        let mut builder = BookBuilder::new(config);
+        for game in PgnReader::new(PGN.as_bytes()) {
+            builder.add_game(&game.unwrap()).unwrap();
+        }
+
+        let (book, summary) = builder.clone().finish();
+        assert_eq!(summary.games, 4);
+        assert_eq!(summary.skipped_games, 1);
+        assert_eq!(summary.entries, book.len());
+
+        // The book survives writing and reading it again.
+        let bytes: Vec<_> = book.entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+        assert_eq!(PolyglotBook::from_bytes(&bytes).unwrap(), book);
+
+        (builder, book)
+    }
+
+    #[test]
+    fn collect_move_stats() {
+        let (builder, book) = build(BookBuilderConfig::default());
+        let mut board = Board::startpos();
+        let e4 = board.find_move::<SmithNotation>("e2e4").unwrap();
+
+        let stats = builder.move_stats(polyglot_key(&board), encode_move(e4));
+        assert_eq!(
+            stats,
+            Some(MoveStats {
+                wins: 1,
+                draws: 1,
+                losses: 1
+            })
+        );
+
+        let moves: Vec<_> = book
+            .moves(&board)
+            .into_iter()
+            .map(|(m, weight)| (format!("{}", m.display_smith()), weight))
+            .collect();
+        assert_eq!(moves, [("e2e4".to_string(), 3), ("d2d4".to_string(), 2)]);
+
+        board.make_move(e4);
+        let reply = book.pick(&board, BookSelection::Best, &mut rand::thread_rng());
+        assert_eq!(reply, board.find_move::<SmithNotation>("c7c5"));
+    }
+
+    #[test]
+    fn filter_moves() {
+        let config = BookBuilderConfig {
+            max_plies: 2,
+            min_games: 2,
+            min_score: 0.5,
+        };
+        let (_, book) = build(config);
+        let board = Board::startpos();
+
+        // Only 1. e4 passes: 1... e5 was played in two games as well, but only scores 0.25 for black.
+        let moves = book.moves(&board);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(book.len(), 1);
+    }
+}
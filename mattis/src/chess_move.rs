@@ -42,6 +42,23 @@ impl ChessMove {
         ChessMoveBuilder(0)
     }
 
+    /// Creates a move from its raw 16 bit representation.
+    ///
+    /// The bits are not validated. Moves from untrusted sources should be checked with `Board::is_pseudo_legal`.
+    pub fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    pub fn to_raw(self) -> u16 {
+        self.0
+    }
+
+    /// Returns false, if the move uses one of the unused flag configurations.
+    /// Such a move can't be created by the builder, but might come from corrupted data.
+    pub fn has_valid_flags(self) -> bool {
+        !matches!(self.0 & 0xF000, 0x6000 | 0x7000)
+    }
+
     pub fn is_nomove(self) -> bool {
         self.0 == 0
     }
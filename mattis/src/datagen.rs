@@ -0,0 +1,448 @@
+//! Training data generation through fast self-play.
+//!
+//! Every thread plays games with a fixed node limit per move, starting from randomized openings.
+//! Quiet positions are recorded together with the search score and the final result of the game.
+//! Positions in check, positions with a capture as best move and positions with a mate score are skipped,
+//! because their static evaluation is meaningless.
+//!
+//! # Binary Format
+//! Each position is stored as a record of 32 bytes. Multi-byte values are little endian.
+//! ```text
+//! // offset   type        content
+//! // 0        u64         occupancy bitboard
+//! // 8        [u8; 16]    pieces of the occupied squares in ascending square order, one nibble each
+//! //                      (low nibble first, values as in `Piece`)
+//! // 24       u8          side to move (0 = white, 1 = black)
+//! // 25       u8          en passant square (255 = none)
+//! // 26       u8          castle permissions (as in `CastlePerms`)
+//! // 27       u8          fifty move counter
+//! // 28       i16         search score in centipawns from white's point of view
+//! // 30       u8          game result (0 = black wins, 1 = draw, 2 = white wins)
+//! // 31       u8          reserved (always 0)
+//! ```
+//!
+//! # Text Format
+//! One position per line: `<fen> | <score> | <result>`, with the score from white's point of view and the
+//! result as `1.0`, `0.5` or `0.0` from white's point of view.
+
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+    eval::is_draw_by_material,
+    hashtable::TranspositionTable,
+    search::search_on_current_thread,
+    time_man::Limits,
+};
+use mattis_bitboard::BitBoard;
+use mattis_types::{CastlePerms, Color, Eval, Piece, Square, TryFromPrimitive};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    time::Instant,
+};
+
+pub const RECORD_SIZE: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct DatagenConfig {
+    pub threads: usize,                   // Number of threads playing games in parallel
+    pub games: u64,                       // Total number of games to play
+    pub nodes: u64,                       // Node limit per move
+    pub random_plies: usize,              // Number of random plies at the start of each game
+    pub max_opening_eval: i16,            // Openings with a higher absolute evaluation are discarded
+    pub seed: u64,                        // Base seed for the random openings
+    pub hash_size_mb: usize,              // Transposition table size per thread
+    pub win_adjudication: (i16, usize),   // Adjudicate a win after this many plies with at least this score
+    pub draw_adjudication: (i16, usize),  // Adjudicate a draw after this many plies with at most this score
+    pub draw_adjudication_min_ply: usize, // Draws are never adjudicated before this game ply
+    pub max_game_plies: usize,            // Games, that reach this length, count as draws
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        Self {
+            threads: 1,
+            games: 100,
+            nodes: 5000,
+            random_plies: 8,
+            max_opening_eval: 300,
+            seed: 0,
+            hash_size_mb: 16,
+            win_adjudication: (1000, 4),
+            draw_adjudication: (10, 10),
+            draw_adjudication_min_ply: 60,
+            max_game_plies: 400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    BlackWins,
+    Draw,
+    WhiteWins,
+}
+
+impl GameResult {
+    fn win_for(color: Color) -> Self {
+        match color {
+            Color::White => Self::WhiteWins,
+            Color::Black => Self::BlackWins,
+        }
+    }
+
+    pub fn as_text(self) -> &'static str {
+        match self {
+            Self::BlackWins => "0.0",
+            Self::Draw => "0.5",
+            Self::WhiteWins => "1.0",
+        }
+    }
+}
+
+/// A position with its score and game result, packed into `RECORD_SIZE` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedPosition([u8; RECORD_SIZE]);
+
+impl PackedPosition {
+    /// Packs the board. The result is set to a draw, until it is known.
+    pub fn new(board: &Board, white_score: i16) -> Self {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&board.bb_all.to_u64().to_le_bytes());
+
+        for (i, square) in board.bb_all.iter_bit_indices().enumerate() {
+            let piece = u8::from(board.pieces[square].unwrap());
+            bytes[8 + i / 2] |= piece << (4 * (i % 2));
+        }
+
+        bytes[24] = u8::from(board.color);
+        bytes[25] = board.en_passant.map(u8::from).unwrap_or(255);
+        bytes[26] = board.castle_perms.as_u8();
+        bytes[27] = board.fifty_move.min(255) as u8;
+        bytes[28..30].copy_from_slice(&white_score.to_le_bytes());
+
+        let mut this = Self(bytes);
+        this.set_result(GameResult::Draw);
+        this
+    }
+
+    pub fn from_bytes(bytes: [u8; RECORD_SIZE]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        self.0
+    }
+
+    pub fn set_result(&mut self, result: GameResult) {
+        self.0[30] = result as u8;
+    }
+
+    pub fn result(&self) -> GameResult {
+        match self.0[30] {
+            0 => GameResult::BlackWins,
+            2 => GameResult::WhiteWins,
+            _ => GameResult::Draw,
+        }
+    }
+
+    pub fn white_score(&self) -> i16 {
+        i16::from_le_bytes([self.0[28], self.0[29]])
+    }
+
+    pub fn to_board(&self) -> Board {
+        let mut board = Board::new();
+        let occupancy = BitBoard::from_u64(u64::from_le_bytes(self.0[0..8].try_into().unwrap()));
+
+        for (i, square) in occupancy.iter_bit_indices().enumerate() {
+            let piece = (self.0[8 + i / 2] >> (4 * (i % 2))) & 0x0F;
+            board.pieces[square] = Piece::try_from_primitive(piece).ok();
+        }
+
+        board.color = Color::try_from_primitive(self.0[24]).unwrap_or(Color::White);
+        board.en_passant = Square::try_from_primitive(self.0[25]).ok();
+        board.castle_perms = CastlePerms::from_u8(self.0[26]);
+        board.fifty_move = self.0[27] as usize;
+
+        board.position_key = board.generate_position_key();
+        board.update_redundant_data();
+        board
+    }
+
+    /// Formats the position as `<fen> | <score> | <result>`.
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} | {} | {}",
+            self.to_board().as_fen(),
+            self.white_score(),
+            self.result().as_text()
+        )
+    }
+}
+
+/// Plays self-play games on `config.threads` threads and writes the recorded positions to `output`
+/// (and optionally as text to `text_output`). Returns the number of written positions.
+pub fn run(config: &DatagenConfig, output: &Path, text_output: Option<&Path>) -> std::io::Result<u64> {
+    assert!(config.threads > 0, "At least 1 datagen thread is necessary.");
+
+    let mut output = BufWriter::new(std::fs::File::create(output)?);
+    let mut text_output = text_output
+        .map(|path| std::fs::File::create(path).map(BufWriter::new))
+        .transpose()?;
+
+    let next_game = AtomicU64::new(0);
+    let (tx, rx) = mpsc::channel::<Vec<PackedPosition>>();
+    let start = Instant::now();
+
+    std::thread::scope(|scope| {
+        for thread in 0..config.threads {
+            let tx = tx.clone();
+            let next_game = &next_game;
+
+            scope.spawn(move || {
+                let ttable = Arc::new(TranspositionTable::new(config.hash_size_mb));
+                let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(thread as u64));
+
+                while next_game.fetch_add(1, Ordering::Relaxed) < config.games {
+                    let positions = loop {
+                        if let Some(positions) = play_game(config, &mut rng, &ttable) {
+                            break positions;
+                        }
+                    };
+
+                    if tx.send(positions).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // Only the worker threads should hold senders, so the loop ends when all of them are done.
+        drop(tx);
+
+        let mut games = 0;
+        let mut total_positions = 0;
+
+        for positions in rx {
+            for p in &positions {
+                output.write_all(&p.to_bytes())?;
+
+                if let Some(text) = &mut text_output {
+                    writeln!(text, "{}", p.to_text())?;
+                }
+            }
+
+            games += 1;
+            total_positions += positions.len() as u64;
+
+            if games % 10 == 0 || games == config.games {
+                let seconds = start.elapsed().as_secs_f64();
+                println!(
+                    "games: {games}/{}, positions: {total_positions}, positions/s: {:.0}",
+                    config.games,
+                    total_positions as f64 / seconds
+                );
+            }
+        }
+
+        output.flush()?;
+        if let Some(text) = &mut text_output {
+            text.flush()?;
+        }
+
+        Ok(total_positions)
+    })
+}
+
+/// Plays a single game from a random opening.
+///
+/// Returns `None`, if no suitable opening was found.
+pub fn play_game(
+    config: &DatagenConfig,
+    rng: &mut StdRng,
+    ttable: &Arc<TranspositionTable>,
+) -> Option<Vec<PackedPosition>> {
+    let mut board = random_opening(config, rng, ttable)?;
+    ttable.reset();
+
+    let mut positions = vec![];
+    let mut win_plies = 0;
+    let mut last_white_score = Eval::DRAW;
+    let mut draw_plies = 0;
+    let mut game_ply = 0;
+
+    let result = loop {
+        let legal_moves = legal_moves(&board);
+
+        if legal_moves.is_empty() {
+            break if board.in_check() {
+                GameResult::win_for(board.color.flipped())
+            } else {
+                GameResult::Draw
+            };
+        }
+
+        if board.fifty_move >= 100
+            || board.is_repetition()
+            || is_draw_by_material(&board)
+            || game_ply >= config.max_game_plies
+        {
+            break GameResult::Draw;
+        }
+
+        let (score, bestmove) = search(&mut board, config.nodes, ttable, &legal_moves);
+        let white_score = if board.color == Color::White { score } else { -score };
+
+        // Adjudicate games, that are clearly decided.
+        let (win_score, win_plies_needed) = config.win_adjudication;
+        let (draw_score, draw_plies_needed) = config.draw_adjudication;
+
+        // The winning plies only add up, as long as the same side is winning.
+        win_plies = if score.abs() < Eval::from(win_score) {
+            0
+        } else if (white_score > Eval::DRAW) == (last_white_score > Eval::DRAW) {
+            win_plies + 1
+        } else {
+            1
+        };
+        last_white_score = white_score;
+        draw_plies = if game_ply >= config.draw_adjudication_min_ply && score.abs() <= Eval::from(draw_score) {
+            draw_plies + 1
+        } else {
+            0
+        };
+
+        if win_plies >= win_plies_needed {
+            break if white_score > Eval::DRAW {
+                GameResult::WhiteWins
+            } else {
+                GameResult::BlackWins
+            };
+        }
+
+        if draw_plies >= draw_plies_needed {
+            break GameResult::Draw;
+        }
+
+        if !board.in_check() && !bestmove.is_capture() && !score.is_mate() {
+            positions.push(PackedPosition::new(&board, white_score.inner()));
+        }
+
+        board.make_move(bestmove);
+        game_ply += 1;
+    };
+
+    positions.iter_mut().for_each(|p| p.set_result(result));
+    Some(positions)
+}
+
+fn random_opening(config: &DatagenConfig, rng: &mut StdRng, ttable: &Arc<TranspositionTable>) -> Option<Board> {
+    let mut board = Board::startpos();
+
+    for _ in 0..config.random_plies {
+        let moves = legal_moves(&board);
+
+        if moves.is_empty() {
+            return None;
+        }
+
+        board.make_move(moves[rng.gen_range(0..moves.len())]);
+    }
+
+    let moves = legal_moves(&board);
+    if moves.is_empty() {
+        return None;
+    }
+
+    // Discard openings, which are already decided.
+    ttable.reset();
+    let (score, _) = search(&mut board, config.nodes, ttable, &moves);
+    (score.abs() <= Eval::from(config.max_opening_eval)).then_some(board)
+}
+
+/// Searches the position and returns the score (from the side to move) and the best move.
+fn search(
+    board: &mut Board,
+    nodes: u64,
+    ttable: &Arc<TranspositionTable>,
+    legal_moves: &MoveList,
+) -> (Eval, ChessMove) {
+    board.set_search_root();
+
+    let time_man = Limits::new().nodes(Some(nodes)).start_now();
+    let stats = search_on_current_thread(board, Arc::clone(ttable), time_man);
+
+    let bestmove = if legal_moves.contains(&stats.bestmove) {
+        stats.bestmove
+    } else {
+        legal_moves[0]
+    };
+
+    (stats.score, bestmove)
+}
+
+fn legal_moves(board: &Board) -> MoveList {
+    let mut moves = MoveList::new();
+    board.generate_legal_moves(&mut moves);
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{play_game, DatagenConfig, GameResult, PackedPosition};
+    use crate::{board::Board, hashtable::TranspositionTable};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::Arc;
+
+    #[test]
+    fn pack_and_unpack_positions() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/1ppppp1p/8/p4Pp1/8/8/PPPPP1PP/RNBQKBNR w Kq g6 0 3",
+            "8/8/8/8/8/8/6k1/4K3 b - - 0 1",
+        ];
+
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let mut packed = PackedPosition::new(&board, -123);
+            packed.set_result(GameResult::BlackWins);
+
+            let unpacked = PackedPosition::from_bytes(packed.to_bytes());
+            assert_eq!(unpacked.to_board(), board);
+            assert_eq!(unpacked.white_score(), -123);
+            assert_eq!(unpacked.result(), GameResult::BlackWins);
+            assert_eq!(unpacked.to_text(), format!("{} | -123 | 0.0", board.as_fen()));
+        }
+    }
+
+    #[test]
+    fn play_single_game() {
+        let config = DatagenConfig {
+            nodes: 300,
+            max_game_plies: 60,
+            ..Default::default()
+        };
+
+        let ttable = Arc::new(TranspositionTable::new(1));
+        let mut rng = StdRng::seed_from_u64(0);
+        let positions = loop {
+            if let Some(positions) = play_game(&config, &mut rng, &ttable) {
+                break positions;
+            }
+        };
+
+        assert!(!positions.is_empty());
+        let result = positions[0].result();
+
+        for p in positions {
+            assert_eq!(p.result(), result);
+            assert!(!p.to_board().in_check());
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! A chess engine, that can be embedded into other programs.
+//!
+//! The engine owns the search threads, the current position and all settings. Searches report their progress to a
+//! [`SearchListener`] and return a [`SearchHandle`], which can be waited on for the final [`SearchResult`].
+//! The UCI protocol of the binary is just a thin adapter over this API.
+
+use crate::{
+    board::{Board, FenError},
+    book::{BookError, BookSelection, PolyglotBook},
+    nnue::{Network, NnueError},
+    notation::SmithNotation,
+    search::{
+        lazy_smp::{AlreadyRunning, LazySMP, LazySMPSetup, SearchConfig, SearchOutcome},
+        params::SearchParams,
+        skill::{Strength, MAX_SKILL_LEVEL},
+        SearchListener, SearchResult,
+    },
+    syzygy::{Syzygy, SyzygyError},
+    tablebase::{TablebaseError, Tablebases},
+};
+use mattis_uci::{self as uci, OptionKind, UciOption};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum OptionError {
+    #[error("unknown option `{0}`")]
+    Unknown(String),
+
+    #[error("invalid value `{value}` for option `{name}`")]
+    InvalidValue { name: String, value: String },
+
+    #[error("could not load nnue network: {0}")]
+    Network(#[from] NnueError),
+
+    #[error("could not load opening book: {0}")]
+    Book(#[from] BookError),
+
+    #[error("could not load tablebases: {0}")]
+    Tablebase(#[from] TablebaseError),
+
+    #[error("could not load syzygy tablebases: {0}")]
+    Syzygy(#[from] SyzygyError),
+}
+
+#[derive(Debug, Error)]
+pub enum PositionError {
+    #[error("invalid fen: {0}")]
+    Fen(#[from] FenError),
+
+    #[error("invalid move `{0}`")]
+    InvalidMove(String),
+}
+
+pub struct Engine {
+    lazy_smp: LazySMP,
+    board: Board,
+    network: Option<Arc<Network>>, // The network loaded from `EvalFile`
+    use_nnue: bool,                // Whether `UseNNUE` is enabled
+    params: SearchParams,
+    strength: Strength,
+    allow_null_pruning: bool,
+    book: Option<Arc<PolyglotBook>>, // The book loaded from `BookFile`
+    own_book: bool,                  // Whether `OwnBook` is enabled
+    book_selection: BookSelection,
+    book_seed: Option<u64>,              // Makes the weighted book moves reproducible
+    tablebases: Option<Arc<Tablebases>>, // The tables loaded from `TablebasePath`
+    syzygy: Option<Arc<Syzygy>>,         // The tables found in `SyzygyPath`
+    syzygy_probe_depth: u16,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(&LazySMPSetup::default())
+    }
+}
+
+impl Engine {
+    const DEFAULT_SYZYGY_PROBE_DEPTH: u16 = 1;
+    const MAX_SYZYGY_PROBE_DEPTH: u16 = 100;
+
+    /// Creates the engine with the search threads and the transposition table of the setup.
+    pub fn new(setup: &LazySMPSetup) -> Self {
+        Self {
+            lazy_smp: setup.create(),
+            board: Board::startpos(),
+            network: None,
+            use_nnue: false,
+            params: SearchParams::default(),
+            strength: Strength::default(),
+            allow_null_pruning: true,
+            book: None,
+            own_book: false,
+            book_selection: BookSelection::default(),
+            book_seed: None,
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: Self::DEFAULT_SYZYGY_PROBE_DEPTH,
+        }
+    }
+
+    /// Returns all options, that can be changed with [`Engine::set_option`].
+    pub fn uci_options() -> Vec<UciOption> {
+        let mut options = vec![
+            UciOption {
+                name: "EvalFile".to_string(),
+                kind: OptionKind::String { default: String::new() },
+            },
+            UciOption {
+                name: "UseNNUE".to_string(),
+                kind: OptionKind::Check { default: false },
+            },
+            UciOption {
+                name: "Skill Level".to_string(),
+                kind: OptionKind::Spin {
+                    default: MAX_SKILL_LEVEL as i64,
+                    min: 0,
+                    max: MAX_SKILL_LEVEL as i64,
+                },
+            },
+            UciOption {
+                name: "UCI_LimitStrength".to_string(),
+                kind: OptionKind::Check { default: false },
+            },
+            UciOption {
+                name: "UCI_Elo".to_string(),
+                kind: OptionKind::Spin {
+                    default: Strength::default().elo as i64,
+                    min: Strength::MIN_ELO as i64,
+                    max: Strength::MAX_ELO as i64,
+                },
+            },
+            UciOption {
+                name: "OwnBook".to_string(),
+                kind: OptionKind::Check { default: false },
+            },
+            UciOption {
+                name: "BookFile".to_string(),
+                kind: OptionKind::String { default: String::new() },
+            },
+            UciOption {
+                name: "BestBookMove".to_string(),
+                kind: OptionKind::Check { default: false },
+            },
+            UciOption {
+                name: "BookSeed".to_string(),
+                kind: OptionKind::Spin {
+                    default: 0,
+                    min: 0,
+                    max: u32::MAX as i64,
+                },
+            },
+            UciOption {
+                name: "TablebasePath".to_string(),
+                kind: OptionKind::String { default: String::new() },
+            },
+            UciOption {
+                name: "SyzygyPath".to_string(),
+                kind: OptionKind::String { default: String::new() },
+            },
+            UciOption {
+                name: "SyzygyProbeDepth".to_string(),
+                kind: OptionKind::Spin {
+                    default: Self::DEFAULT_SYZYGY_PROBE_DEPTH as i64,
+                    min: 1,
+                    max: Self::MAX_SYZYGY_PROBE_DEPTH as i64,
+                },
+            },
+        ];
+
+        // The search parameters are only interesting for tuning, so we don't show them to every gui.
+        if cfg!(feature = "tune") {
+            options.extend(SearchParams::uci_options());
+        }
+
+        options
+    }
+
+    /// Sets an option by its (case insensitive) name. The search parameters can always be set,
+    /// even if they are not listed without the `tune` feature.
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), OptionError> {
+        let invalid = || OptionError::InvalidValue {
+            name: name.to_string(),
+            value: value.unwrap_or_default().to_string(),
+        };
+        let parse_check = |value: &str| value.parse::<bool>().map_err(|_| invalid());
+
+        let known = Self::uci_options()
+            .into_iter()
+            .chain(SearchParams::uci_options())
+            .any(|option| option.name.eq_ignore_ascii_case(name));
+        if !known {
+            return Err(OptionError::Unknown(name.to_string()));
+        }
+
+        match (name.to_lowercase().as_str(), value) {
+            // An empty path unloads the network.
+            ("evalfile", None | Some("")) => self.network = None,
+            ("evalfile", Some(path)) => self.network = Some(Arc::new(Network::load(path)?)),
+            ("usennue", Some(value)) => self.use_nnue = parse_check(value)?,
+            ("skill level", Some(value)) => {
+                self.strength.skill_level = value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|&level| level <= MAX_SKILL_LEVEL)
+                    .ok_or_else(invalid)?;
+            }
+            ("uci_limitstrength", Some(value)) => self.strength.limit_strength = parse_check(value)?,
+            ("uci_elo", Some(value)) => {
+                let elo = value.parse::<u16>().map_err(|_| invalid())?;
+                self.strength.elo = elo.clamp(Strength::MIN_ELO, Strength::MAX_ELO);
+            }
+            ("ownbook", Some(value)) => self.own_book = parse_check(value)?,
+            // An empty path unloads the book.
+            ("bookfile", None | Some("")) => self.book = None,
+            ("bookfile", Some(path)) => self.book = Some(Arc::new(PolyglotBook::load(path)?)),
+            ("bestbookmove", Some(value)) => {
+                self.book_selection = match parse_check(value)? {
+                    true => BookSelection::Best,
+                    false => BookSelection::Weighted,
+                };
+            }
+            // Zero picks the book moves randomly.
+            ("bookseed", Some(value)) => {
+                let seed = value.parse::<u32>().map_err(|_| invalid())?;
+                self.book_seed = (seed != 0).then_some(seed as u64);
+            }
+            // An empty path unloads the tables.
+            ("tablebasepath", None | Some("")) => self.tablebases = None,
+            ("tablebasepath", Some(path)) => self.tablebases = Some(Arc::new(Tablebases::load_dir(path)?)),
+            // Several directories are separated like in the `PATH` variable.
+            ("syzygypath", None | Some("")) => self.syzygy = None,
+            ("syzygypath", Some(paths)) => self.syzygy = Some(Arc::new(Syzygy::load(paths)?)),
+            ("syzygyprobedepth", Some(value)) => {
+                self.syzygy_probe_depth = value
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|depth| (1..=Self::MAX_SYZYGY_PROBE_DEPTH).contains(depth))
+                    .ok_or_else(invalid)?;
+            }
+            (_, Some(value)) if value.parse().is_ok_and(|value| self.params.set(name, value)) => {}
+            _ => return Err(invalid()),
+        }
+
+        // The network could have changed.
+        self.update_board(self.board.clone());
+        Ok(())
+    }
+
+    /// Stops the search and forgets everything about the previous game.
+    pub fn new_game(&mut self) {
+        self.lazy_smp.stop_search();
+        self.lazy_smp.reset_ttable();
+        self.lazy_smp.clear_histories();
+        self.update_board(Board::startpos());
+    }
+
+    /// Sets up the position after playing the moves (in smith notation) from the fen.
+    ///
+    /// The position stays unchanged, if the fen or one of the moves is invalid.
+    pub fn set_position(&mut self, fen: &str, moves: &[impl AsRef<str>]) -> Result<(), PositionError> {
+        let mut board = Board::from_fen(fen)?;
+
+        for move_str in moves {
+            let move_str = move_str.as_ref();
+            let chess_move = board
+                .find_move::<SmithNotation>(move_str)
+                .ok_or_else(|| PositionError::InvalidMove(move_str.to_string()))?;
+            board.make_move(chess_move);
+        }
+
+        board.set_search_root();
+        self.update_board(board);
+        Ok(())
+    }
+
+    pub fn set_board(&mut self, board: Board) {
+        self.update_board(board);
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Sets the network and enables it, or disables nnue evaluation with `None`.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.use_nnue = network.is_some();
+        self.network = network;
+        self.update_board(self.board.clone());
+    }
+
+    pub fn params_mut(&mut self) -> &mut SearchParams {
+        &mut self.params
+    }
+
+    pub fn strength_mut(&mut self) -> &mut Strength {
+        &mut self.strength
+    }
+
+    /// Sets the opening book and enables it, or disables it with `None`.
+    pub fn set_book(&mut self, book: Option<Arc<PolyglotBook>>, selection: BookSelection) {
+        self.own_book = book.is_some();
+        self.book = book;
+        self.book_selection = selection;
+    }
+
+    /// Makes the weighted choice of book moves reproducible, or random again with `None`.
+    pub fn set_book_seed(&mut self, seed: Option<u64>) {
+        self.book_seed = seed;
+    }
+
+    /// Sets the tablebases, which are probed during the search, or disables them with `None`.
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    /// Sets the Syzygy tablebases, or disables them with `None`.
+    pub fn set_syzygy(&mut self, syzygy: Option<Arc<Syzygy>>) {
+        self.syzygy = syzygy;
+    }
+
+    pub fn set_null_pruning(&mut self, allow_null_pruning: bool) {
+        self.allow_null_pruning = allow_null_pruning;
+    }
+
+    /// Starts a search of the current position in the background.
+    ///
+    /// The listener receives the progress and the final stats of the search on the main search thread.
+    /// Fails, if a search is already running.
+    pub fn search(
+        &mut self,
+        go: uci::Go,
+        listener: impl SearchListener + 'static,
+    ) -> Result<SearchHandle, AlreadyRunning> {
+        let outcome = self.lazy_smp.start_search(SearchConfig {
+            listener: Arc::new(listener),
+            allow_null_pruning: self.allow_null_pruning,
+            params: self.params,
+            skill: self.strength.skill(),
+            book: self.book.clone().filter(|_| self.own_book),
+            book_selection: self.book_selection,
+            book_seed: self.book_seed,
+            tablebases: self.tablebases.clone(),
+            syzygy: self.syzygy.clone(),
+            syzygy_probe_depth: self.syzygy_probe_depth,
+            go,
+        })?;
+
+        Ok(SearchHandle { outcome })
+    }
+
+    /// Stops the search, if it is running. The result is still reported.
+    pub fn stop(&mut self) {
+        self.lazy_smp.stop_search();
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.lazy_smp.is_search_running()
+    }
+
+    fn update_board(&mut self, mut board: Board) {
+        board.set_network(self.network.clone().filter(|_| self.use_nnue));
+        self.board = board.clone();
+        self.lazy_smp.set_board(board);
+    }
+}
+
+/// The result of a running search.
+#[derive(Debug)]
+pub struct SearchHandle {
+    outcome: Arc<SearchOutcome>,
+}
+
+impl SearchHandle {
+    /// Blocks until the search is finished.
+    pub fn wait(self) -> SearchResult {
+        SearchResult::from(&self.outcome.wait())
+    }
+
+    /// Blocks until the search is finished, but at most for the timeout.
+    ///
+    /// Returns `None` on a timeout. The search keeps running in this case and can be waited for again.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<SearchResult> {
+        self.outcome.wait_timeout(timeout).as_ref().map(SearchResult::from)
+    }
+
+    /// Returns the result, if the search is already finished.
+    pub fn try_result(&self) -> Option<SearchResult> {
+        self.outcome.try_stats().as_ref().map(SearchResult::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Engine, OptionError, PositionError};
+    use crate::{
+        board::Board,
+        book::{encode_move, polyglot_key, BookEntry, BookSelection, PolyglotBook},
+        notation::SmithNotation,
+        search::{lazy_smp::LazySMPSetup, SearchUpdate},
+    };
+    use mattis_uci as uci;
+    use std::{
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    fn engine() -> Engine {
+        Engine::new(LazySMPSetup::default().thread_count(2).ttable_size(1))
+    }
+
+    #[test]
+    fn search_reports_to_listener() {
+        let mut engine = engine();
+        engine
+            .set_position(
+                "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+                &["g1f1", "g8f8", "f1g1", "f8g8"],
+            )
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let go = uci::Go {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let result = engine.search(go.clone(), sender).unwrap().wait();
+
+        assert_eq!(format!("{}", result.bestmove.display_smith()), "a1a8");
+        assert!(result.score.is_mate());
+        assert_eq!(result.pv.first(), Some(&result.bestmove));
+
+        let updates: Vec<_> = receiver.try_iter().collect();
+        assert!(matches!(updates.last(), Some(SearchUpdate::Finished(stats)) if stats.bestmove == result.bestmove));
+        assert!(updates[..updates.len() - 1]
+            .iter()
+            .all(|update| matches!(update, SearchUpdate::Depth(_))));
+
+        // The next search can start right away.
+        assert!(engine.search(go, ()).is_ok());
+    }
+
+    #[test]
+    fn play_book_moves() {
+        let mut board = Board::startpos();
+        let entry = BookEntry {
+            key: polyglot_key(&board),
+            move_: encode_move(board.find_move::<SmithNotation>("a2a3").unwrap()),
+            weight: 1,
+            learn: 0,
+        };
+        let book = PolyglotBook::from_bytes(&entry.to_bytes()).unwrap();
+
+        let mut engine = engine();
+        engine.set_book(Some(Arc::new(book)), BookSelection::Best);
+        let go = uci::Go {
+            depth: Some(3),
+            ..Default::default()
+        };
+
+        let result = engine.search(go.clone(), ()).unwrap().wait();
+        assert_eq!(format!("{}", result.bestmove.display_smith()), "a2a3");
+
+        // Out of book, the engine searches again.
+        engine.set_position(&board.as_fen(), &["a2a3"]).unwrap();
+        let result = engine.search(go, ()).unwrap().wait();
+        assert_eq!(result.depth, 3);
+    }
+
+    #[test]
+    fn wait_for_stopped_search() {
+        let mut engine = engine();
+
+        // An unlimited search only finishes after it has been stopped.
+        let handle = engine.search(uci::Go::default(), ()).unwrap();
+        assert!(handle.wait_timeout(Duration::from_millis(50)).is_none());
+        assert!(engine.is_searching());
+
+        engine.stop();
+        let result = handle.wait_timeout(Duration::from_secs(10)).unwrap();
+        assert!(!result.bestmove.is_nomove());
+        assert!(!engine.is_searching());
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let mut engine = engine();
+
+        assert!(matches!(
+            engine.set_position("8/8/8 w - - 0 1", &[] as &[&str]),
+            Err(PositionError::Fen(_))
+        ));
+        assert!(matches!(
+            engine.set_position("6k1/8/8/8/8/8/8/6K1 w - - 0 1", &["g1g3"]),
+            Err(PositionError::InvalidMove(m)) if m == "g1g3"
+        ));
+        assert_eq!(engine.board(), &crate::board::Board::startpos());
+
+        assert!(engine.set_option("Skill Level", Some("10")).is_ok());
+        assert!(matches!(
+            engine.set_option("Skill Level", Some("21")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            engine.set_option("UseNNUE", Some("yes")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            engine.set_option("Hash", Some("64")),
+            Err(OptionError::Unknown(_))
+        ));
+        assert!(engine.set_option("rfp_margin", Some("100")).is_ok());
+        assert!(matches!(
+            engine.set_option("rfp_margin", Some("100000")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            engine.set_option("EvalFile", Some("/nonexistent.nnue")),
+            Err(OptionError::Network(_))
+        ));
+        assert!(engine.set_option("BookSeed", Some("7")).is_ok());
+        assert!(matches!(
+            engine.set_option("BookSeed", Some("-1")),
+            Err(OptionError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            engine.set_option("BookFile", Some("/nonexistent.bin")),
+            Err(OptionError::Book(_))
+        ));
+    }
+}
@@ -1,7 +1,11 @@
+mod endgame;
+mod material;
+
 use crate::{
     board::Board,
     tables::{BLACK_PAWN_PASSED_MASKS, FILE_BITBOARDS, ISOLATED_PAWN_MASKS, WHITE_PAWN_PASSED_MASKS},
 };
+use material::{MaterialEntry, MAX_PHASE};
 use mattis_types::{Color, Eval, Piece, PieceType, Square};
 
 // First and last entry should never be used, because pawns cant be on the first or last rank
@@ -12,39 +16,65 @@ const ROOK_ON_OPEN_FILE_BONUS: i16 = 15;
 const ROOK_ON_SEMI_OPEN_FILE_BONUS: i16 = 10;
 const QUEEN_ON_OPEN_FILE_BONUS: i16 = 10;
 const QUEEN_ON_SEMI_OPEN_FILE_BONUS: i16 = 5;
-const BISHOP_PAIR_BONUS: i16 = 30;
 
+/// Evaluates the position from the point of view of the side to move.
+///
+/// Uses the NNUE network, if one is attached to the board, and the handcrafted evaluation otherwise. Known endgames
+/// like KPK are evaluated by specialized functions instead. Drawish material scales the evaluation down.
 pub fn evaluation(board: &Board) -> Eval {
-    if is_draw_by_material(board) {
+    let material = material::probe(board);
+    if material.draw {
         return Eval::DRAW;
     }
 
+    // Known endgames are evaluated on their own or scale the generic evaluation.
+    if let Some(eval) = material.endgame.and_then(|endgame| endgame.value(board)) {
+        return eval;
+    }
+
+    let eval = match &board.nnue {
+        Some(nnue) => nnue.evaluate(board.color),
+        None => handcrafted(board, &material),
+    };
+
+    let eval = match material.endgame {
+        Some(endgame) => endgame.scale(board, eval),
+        None => eval,
+    };
+
+    material.scale(board.color, eval)
+}
+
+pub fn handcrafted_evaluation(board: &Board) -> Eval {
+    handcrafted(board, &material::probe(board))
+}
+
+fn handcrafted(board: &Board, material: &MaterialEntry) -> Eval {
     let my_color = board.color;
     let op_color = board.color.flipped();
 
-    // STEP 1: Just use the material value for both sides
+    // STEP 1: Just use the material value for both sides, with the bonuses for combinations of pieces
     let mut eval = board.material[my_color] - board.material[op_color];
+    eval += match my_color {
+        Color::White => material.imbalance,
+        Color::Black => -material.imbalance,
+    };
 
     // STEP 2: Use piece-square tables for each and add the results to the eval
     // - the current color uses the just the plain tables, the other sides uses them mirrored
-    // - the king uses a different table in the endgame
+    // - the king tables are interpolated between midgame and endgame by the game phase
 
     let (my_fn, op_fn): (PieceSquareFn, PieceSquareFn) = match my_color {
         Color::White => (piece_square, piece_square_mirrored),
         Color::Black => (piece_square_mirrored, piece_square),
     };
 
-    // TODO: interpolate between midgame and endgame?
-    let my_king_table = if is_endgame_for_color(my_color, board) {
-        &KING_ENDGAME_SQUARE_TABLE
-    } else {
-        &KING_SQUARE_TABLE
-    };
-
-    let op_king_table = if is_endgame_for_color(op_color, board) {
-        &KING_ENDGAME_SQUARE_TABLE
-    } else {
-        &KING_SQUARE_TABLE
+    let king_table = |color| {
+        let piece_square_fn = if color == my_color { my_fn } else { op_fn };
+        let king = Piece::new(PieceType::King, color);
+        let midgame = piece_square_fn(king, board, &KING_SQUARE_TABLE);
+        let endgame = piece_square_fn(king, board, &KING_ENDGAME_SQUARE_TABLE);
+        (midgame * material.phase + endgame * (MAX_PHASE - material.phase)) / MAX_PHASE
     };
 
     eval += my_fn(Piece::new(PieceType::Pawn, my_color), board, &PAWN_SQUARE_TABLE);
@@ -52,20 +82,20 @@ pub fn evaluation(board: &Board) -> Eval {
     eval += my_fn(Piece::new(PieceType::Bishop, my_color), board, &BISHOP_SQUARE_TABLE);
     eval += my_fn(Piece::new(PieceType::Rook, my_color), board, &ROOK_SQUARE_TABLE);
     eval += my_fn(Piece::new(PieceType::Queen, my_color), board, &QUEEN_SQUARE_TABLE);
-    eval += my_fn(Piece::new(PieceType::King, my_color), board, my_king_table);
+    eval += king_table(my_color);
 
     eval -= op_fn(Piece::new(PieceType::Pawn, op_color), board, &PAWN_SQUARE_TABLE);
     eval -= op_fn(Piece::new(PieceType::Knight, op_color), board, &KNIGHT_SQUARE_TABLE);
     eval -= op_fn(Piece::new(PieceType::Bishop, op_color), board, &BISHOP_SQUARE_TABLE);
     eval -= op_fn(Piece::new(PieceType::Rook, op_color), board, &ROOK_SQUARE_TABLE);
     eval -= op_fn(Piece::new(PieceType::Queen, op_color), board, &QUEEN_SQUARE_TABLE);
-    eval -= op_fn(Piece::new(PieceType::King, op_color), board, op_king_table);
+    eval -= king_table(op_color);
 
     // STEP 3: Apply penalties for isolated pawns & passed pawns
 
     let (my_passed_masks, op_passed_masks) = match my_color {
-        Color::White => (WHITE_PAWN_PASSED_MASKS, BLACK_PAWN_PASSED_MASKS),
-        Color::Black => (BLACK_PAWN_PASSED_MASKS, WHITE_PAWN_PASSED_MASKS),
+        Color::White => (*WHITE_PAWN_PASSED_MASKS, *BLACK_PAWN_PASSED_MASKS),
+        Color::Black => (*BLACK_PAWN_PASSED_MASKS, *WHITE_PAWN_PASSED_MASKS),
     };
 
     let bb_my_pawns = board.bitboards[Piece::new(PieceType::Pawn, my_color)];
@@ -130,15 +160,6 @@ pub fn evaluation(board: &Board) -> Eval {
         }
     }
 
-    // STEP 5: Apply a bonus for the bishop pair
-    if board.bitboards[Piece::new(PieceType::Bishop, my_color)].bit_count() >= 2 {
-        eval += BISHOP_PAIR_BONUS;
-    }
-
-    if board.bitboards[Piece::new(PieceType::Bishop, op_color)].bit_count() >= 2 {
-        eval -= BISHOP_PAIR_BONUS;
-    }
-
     eval.into()
 }
 
@@ -158,29 +179,25 @@ fn piece_square_mirrored(piece: Piece, board: &Board, table: &[i16; 64]) -> i16
         .sum()
 }
 
-fn is_endgame_for_color(color: Color, board: &Board) -> bool {
-    const MATERIAL_THRESHOLD: i16 = Piece::WhiteRook.value()
-        + 2 * Piece::WhiteKnight.value()
-        + 4 * Piece::WhitePawn.value()
-        + Piece::WhiteKing.value();
-
-    board.material[color.flipped()] < MATERIAL_THRESHOLD
+/// Neither side has enough material to win. The result is cached in the material table.
+pub fn is_draw_by_material(board: &Board) -> bool {
+    material::probe(board).draw
 }
 
-fn is_draw_by_material(board: &Board) -> bool {
-    let white_queens = board.count_pieces[Piece::WhiteQueen];
-    let white_rooks = board.count_pieces[Piece::WhiteRook];
-    let white_knights = board.count_pieces[Piece::WhiteKnight];
-    let white_bishops = board.count_pieces[Piece::WhiteBishop];
-    let white_pawns = board.count_pieces[Piece::WhitePawn];
-    let white_minors = board.count_minor_pieces[Color::White]; // Knights + Bishops
-
-    let black_queens = board.count_pieces[Piece::BlackQueen];
-    let black_rooks = board.count_pieces[Piece::BlackRook];
-    let black_knights = board.count_pieces[Piece::BlackKnight];
-    let black_bishops = board.count_pieces[Piece::BlackBishop];
-    let black_pawns = board.count_pieces[Piece::BlackPawn];
-    let black_minors = board.count_minor_pieces[Color::Black]; // Knights + Bishops
+fn is_insufficient_material(count_pieces: &[usize; 12]) -> bool {
+    let white_queens = count_pieces[Piece::WhiteQueen];
+    let white_rooks = count_pieces[Piece::WhiteRook];
+    let white_knights = count_pieces[Piece::WhiteKnight];
+    let white_bishops = count_pieces[Piece::WhiteBishop];
+    let white_pawns = count_pieces[Piece::WhitePawn];
+    let white_minors = white_knights + white_bishops;
+
+    let black_queens = count_pieces[Piece::BlackQueen];
+    let black_rooks = count_pieces[Piece::BlackRook];
+    let black_knights = count_pieces[Piece::BlackKnight];
+    let black_bishops = count_pieces[Piece::BlackBishop];
+    let black_pawns = count_pieces[Piece::BlackPawn];
+    let black_minors = black_knights + black_bishops;
 
     // Any Queens or Pawns on Board --> no draw
     if white_queens + black_queens + white_pawns + black_pawns != 0 {
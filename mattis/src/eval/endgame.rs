@@ -0,0 +1,320 @@
+//! Specialized evaluation of endgames, that the generic evaluation misplays.
+//!
+//! The endgames are registered by the material key of their signature. An endgame either evaluates the position on its
+//! own (like KPK with the bitbase), or scales the generic evaluation towards a draw (like opposite colored bishops).
+
+use crate::{
+    board::Board,
+    tables::{DISTANCE, FILE_BITBOARDS, KPK_BITBASE},
+};
+use ctor::ctor;
+use mattis_types::{Color, Eval, File, Piece, PieceType, Rank, Square};
+use std::collections::HashMap;
+
+/// The value of a won endgame, that still has to be converted. It's well below the mate scores.
+const KNOWN_WIN: i16 = 10_000;
+
+/// Scale factors are given in 64ths of the evaluation.
+pub(super) const SCALE_NORMAL: u8 = 64;
+
+/// Evaluates the position from the view of the strong side.
+type ValueFn = fn(&Board, Color) -> i16;
+
+/// Returns the scale factor for the evaluation, if the position is drawish.
+type ScaleFn = fn(&Board, Color) -> Option<u8>;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Value(ValueFn),
+    Scale(ScaleFn),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Endgame {
+    strong: Color,
+    kind: Kind,
+}
+
+impl Endgame {
+    /// Looks up the endgame of a material key.
+    pub(super) fn find(material_key: u64) -> Option<Self> {
+        ENDGAMES.get(&material_key).copied()
+    }
+
+    /// The evaluation from the view of the side to move, if the endgame evaluates positions on its own.
+    pub(super) fn value(self, board: &Board) -> Option<Eval> {
+        let Kind::Value(value) = self.kind else {
+            return None;
+        };
+
+        let value = value(board, self.strong);
+        Some(Eval::from(if board.color == self.strong { value } else { -value }))
+    }
+
+    /// Scales the generic evaluation towards a draw, if the position is drawish.
+    pub(super) fn scale(self, board: &Board, eval: Eval) -> Eval {
+        let Kind::Scale(scale) = self.kind else {
+            return eval;
+        };
+
+        match scale(board, self.strong) {
+            Some(scale) => scale_eval(eval, scale),
+            None => eval,
+        }
+    }
+}
+
+/// Multiplies the evaluation with a scale factor.
+pub(super) fn scale_eval(eval: Eval, scale: u8) -> Eval {
+    Eval::from((eval.inner() as i32 * scale as i32 / SCALE_NORMAL as i32) as i16)
+}
+
+#[ctor]
+static ENDGAMES: HashMap<u64, Endgame> = {
+    let mut endgames = HashMap::new();
+    let mut add = |code: &str, kind: Kind| {
+        for strong in [Color::White, Color::Black] {
+            endgames.insert(material_key(code, strong), Endgame { strong, kind });
+        }
+    };
+
+    add("KPK", Kind::Value(kpk));
+    add("KBNK", Kind::Value(kbnk));
+    add("KRK", Kind::Value(kxk));
+    add("KQK", Kind::Value(kxk));
+
+    for pawns in 1..=8 {
+        add(&format!("KB{}K", "P".repeat(pawns)), Kind::Scale(kbpsk));
+    }
+
+    for strong_pawns in 1..=8 {
+        for weak_pawns in 0..=strong_pawns {
+            let code = format!("KB{}KB{}", "P".repeat(strong_pawns), "P".repeat(weak_pawns));
+            add(&code, Kind::Scale(opposite_bishops));
+        }
+    }
+
+    endgames
+};
+
+/// The material key of a code like `KBNK`: The pieces of the strong side starting with its king, followed by the
+/// pieces of the weak side.
+fn material_key(code: &str, strong: Color) -> u64 {
+    let (strong_pieces, weak_pieces) = code[1..].split_once('K').unwrap();
+
+    let mut count_pieces = [0; 12];
+    count_pieces[Piece::WhiteKing] = 1;
+    count_pieces[Piece::BlackKing] = 1;
+    for (pieces, color) in [(strong_pieces, strong), (weak_pieces, strong.flipped())] {
+        for c in pieces.chars() {
+            let piece_type = Piece::from_char(c).unwrap().piece_type();
+            count_pieces[Piece::new(piece_type, color)] += 1;
+        }
+    }
+
+    Board::generate_material_key(&count_pieces)
+}
+
+/// KPK: The bitbase knows, if the pawn can be promoted safely.
+fn kpk(board: &Board, strong: Color) -> i16 {
+    let pawn = first_square(board, Piece::new(PieceType::Pawn, strong));
+
+    // The bitbase is stored for a white pawn on the files A to D.
+    let flip_rank = if strong == Color::White { 0 } else { 56 };
+    let flip_file = if pawn.file() >= File::E { 7 } else { 0 };
+    let normalize = |square: Square| (u8::from(square) ^ flip_rank ^ flip_file) as usize;
+
+    let pawn = normalize(pawn);
+    let strong_king = normalize(board.king_square[strong]);
+    let weak_king = normalize(board.king_square[strong.flipped()]);
+    let color = (board.color != strong) as usize;
+    let (file, rank) = (pawn % 8, pawn / 8);
+
+    match KPK_BITBASE[color * 24 + file * 6 + (6 - rank)][weak_king] >> strong_king & 1 {
+        0 => 0,
+        _ => KNOWN_WIN + PieceType::Pawn.value() + rank as i16,
+    }
+}
+
+/// KBNK: Mate is only possible in a corner of the bishop's color, so the weak king is driven there.
+fn kbnk(board: &Board, strong: Color) -> i16 {
+    let bishop = first_square(board, Piece::new(PieceType::Bishop, strong));
+    let weak_king = board.king_square[strong.flipped()];
+
+    let corners = match is_light_square(bishop) {
+        true => [Square::A8, Square::H1],
+        false => [Square::A1, Square::H8],
+    };
+    let corner_distance = corners
+        .map(|corner| distance(weak_king, corner))
+        .into_iter()
+        .min()
+        .unwrap();
+
+    KNOWN_WIN + material_difference(board, strong) + 30 * (7 - corner_distance) + push_close(board)
+}
+
+/// KRK and KQK: The weak king is driven to the edge, where it can be mated.
+fn kxk(board: &Board, strong: Color) -> i16 {
+    let weak_king = board.king_square[strong.flipped()];
+    let center_distance = |x: u8| (2 * x as i16 - 7).abs() / 2;
+    let edge_bonus = 20 * (center_distance(weak_king.file().into()) + center_distance(weak_king.rank().into()));
+
+    KNOWN_WIN + material_difference(board, strong) + edge_bonus + push_close(board)
+}
+
+/// KB and pawns on a rook file against K: A bishop, that doesn't control the promotion square, can't drive the
+/// weak king out of the corner.
+fn kbpsk(board: &Board, strong: Color) -> Option<u8> {
+    let pawns = board.bitboards[Piece::new(PieceType::Pawn, strong)];
+    let bishop = first_square(board, Piece::new(PieceType::Bishop, strong));
+    let weak_king = board.king_square[strong.flipped()];
+    let promotion_rank = if strong == Color::White { Rank::R8 } else { Rank::R1 };
+
+    [File::A, File::H]
+        .into_iter()
+        .filter(|&file| pawns.without(FILE_BITBOARDS[file]).is_empty())
+        .map(|file| Square::from_file_rank(file, promotion_rank))
+        .any(|promotion| is_light_square(promotion) != is_light_square(bishop) && distance(weak_king, promotion) <= 1)
+        .then_some(0)
+}
+
+/// Bishops of opposite colors and pawns: The weak side can blockade the pawns on the squares of its bishop, so
+/// even two extra pawns often don't win.
+fn opposite_bishops(board: &Board, strong: Color) -> Option<u8> {
+    let strong_bishop = first_square(board, Piece::new(PieceType::Bishop, strong));
+    let weak_bishop = first_square(board, Piece::new(PieceType::Bishop, strong.flipped()));
+    if is_light_square(strong_bishop) == is_light_square(weak_bishop) {
+        return None;
+    }
+
+    let pawns = |color| board.count_pieces[Piece::new(PieceType::Pawn, color)];
+    match pawns(strong).abs_diff(pawns(strong.flipped())) {
+        0 | 1 => Some(16),
+        _ => Some(32),
+    }
+}
+
+fn first_square(board: &Board, piece: Piece) -> Square {
+    board.bitboards[piece].iter_bit_indices().next().unwrap()
+}
+
+fn material_difference(board: &Board, strong: Color) -> i16 {
+    board.material[strong] - board.material[strong.flipped()]
+}
+
+/// A bonus for the kings being close to each other, so the strong king helps with the mate.
+fn push_close(board: &Board) -> i16 {
+    140 - 20 * distance(board.king_square[Color::White], board.king_square[Color::Black])
+}
+
+fn distance(a: Square, b: Square) -> i16 {
+    DISTANCE[a][b] as i16
+}
+
+fn is_light_square(square: Square) -> bool {
+    (u8::from(square.file()) + u8::from(square.rank())) % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Endgame;
+    use crate::{
+        board::Board,
+        eval::evaluation,
+        tablebase::{Tablebases, TbValue},
+    };
+    use mattis_types::{Color, Eval, Piece};
+
+    #[test]
+    fn kpk_bitbase_matches_the_tablebase() {
+        let mut tablebases = Tablebases::new();
+        for material in ["KQK", "KRK", "KPK"] {
+            tablebases
+                .generate(&material.parse().unwrap(), &mut |_| Ok(()))
+                .unwrap();
+        }
+
+        for pawn in 8..56 {
+            for strong_king in 0..64 {
+                for weak_king in 0..64 {
+                    for color in [Color::White, Color::Black] {
+                        if pawn == strong_king || pawn == weak_king || strong_king == weak_king {
+                            continue;
+                        }
+
+                        let mut board = Board::new();
+                        board.pieces[pawn] = Some(Piece::WhitePawn);
+                        board.pieces[strong_king] = Some(Piece::WhiteKing);
+                        board.pieces[weak_king] = Some(Piece::BlackKing);
+                        board.color = color;
+                        board.update_redundant_data();
+
+                        let Some(value) = tablebases.probe(&board) else {
+                            continue;
+                        };
+                        let eval = Endgame::find(board.material_key).unwrap().value(&board).unwrap();
+                        assert_eq!(eval == Eval::DRAW, value == TbValue::Draw, "{}", board.as_fen());
+
+                        // The same position with a black pawn.
+                        let mut mirrored = Board::new();
+                        mirrored.pieces[pawn ^ 56] = Some(Piece::BlackPawn);
+                        mirrored.pieces[strong_king ^ 56] = Some(Piece::BlackKing);
+                        mirrored.pieces[weak_king ^ 56] = Some(Piece::WhiteKing);
+                        mirrored.color = color.flipped();
+                        mirrored.update_redundant_data();
+                        assert_eq!(
+                            Endgame::find(mirrored.material_key).unwrap().value(&mirrored).unwrap(),
+                            eval
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kbnk_drives_to_the_bishops_corner() {
+        // The light squared bishop can mate in a8, but not in a1.
+        let right_corner = Board::from_fen("k7/2K5/8/8/8/8/8/4NB2 w - - 0 1").unwrap();
+        let wrong_corner = Board::from_fen("8/8/8/8/8/8/2K5/k3NB2 w - - 0 1").unwrap();
+        assert!(evaluation(&right_corner) > evaluation(&wrong_corner));
+        assert!(evaluation(&wrong_corner) > Eval::from(super::KNOWN_WIN));
+    }
+
+    #[test]
+    fn mop_up_drives_the_king_to_the_edge() {
+        let center = Board::from_fen("8/8/8/8/4k3/8/4K3/R7 b - - 0 1").unwrap();
+        let edge = Board::from_fen("8/8/8/8/k7/8/K7/7R b - - 0 1").unwrap();
+        assert!(evaluation(&edge) < evaluation(&center));
+        assert!(evaluation(&center) < Eval::from(-super::KNOWN_WIN));
+    }
+
+    #[test]
+    fn wrong_bishop_draws() {
+        let board = Board::from_fen("k7/8/8/8/8/8/P7/2B1K3 w - - 0 1").unwrap();
+        assert_eq!(evaluation(&board), Eval::DRAW);
+
+        // The king is too far away from the corner.
+        let board = Board::from_fen("8/8/8/8/3k4/8/P7/2B1K3 w - - 0 1").unwrap();
+        assert!(evaluation(&board) > Eval::DRAW);
+
+        // The bishop controls the promotion square.
+        let board = Board::from_fen("k7/8/8/8/8/8/P7/3BK3 w - - 0 1").unwrap();
+        assert!(evaluation(&board) > Eval::DRAW);
+    }
+
+    #[test]
+    fn opposite_bishops_scale_down() {
+        let board = Board::from_fen("4k3/5pp1/8/4b3/8/3B4/4PPP1/4K3 w - - 0 1").unwrap();
+        let endgame = Endgame::find(board.material_key).unwrap();
+        assert_eq!(endgame.scale(&board, Eval::from(400)), Eval::from(100));
+
+        let board = Board::from_fen("4k3/5pp1/8/3b4/8/3B4/4PPP1/4K3 w - - 0 1").unwrap();
+        let endgame = Endgame::find(board.material_key).unwrap();
+        assert_eq!(endgame.scale(&board, Eval::from(400)), Eval::from(400));
+
+        let board = Board::from_fen("4k3/5pp1/8/4b3/8/3B4/4PPP1/3QK3 w - - 0 1").unwrap();
+        assert!(Endgame::find(board.material_key).is_none());
+    }
+}
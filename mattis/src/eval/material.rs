@@ -0,0 +1,182 @@
+//! Caches everything, that only depends on the material of a position, by its material key.
+//!
+//! The entries are computed on the first lookup of a material signature, so every later evaluation with the same
+//! material only needs a table lookup. Each thread has its own table, because the evaluation has no search context.
+
+use super::{
+    endgame::{scale_eval, Endgame, SCALE_NORMAL},
+    is_insufficient_material,
+};
+use crate::board::Board;
+use mattis_types::{Color, Eval, Piece, PieceType};
+use std::cell::RefCell;
+
+const TABLE_SIZE: usize = 1 << 13;
+
+/// The game phase with all pieces on the board. It goes down to `0`, when only kings and pawns are left.
+pub(super) const MAX_PHASE: i16 = 24;
+
+const BISHOP_PAIR_BONUS: i16 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct MaterialEntry {
+    key: u64,
+    pub phase: i16,               // From `MAX_PHASE` in the opening to `0` in a pawn endgame
+    pub imbalance: i16,           // Bonuses for combinations of pieces from white's view
+    pub scale_factors: [u8; 2],   // Scale the evaluation, when white or black is ahead
+    pub draw: bool,               // Neither side has enough material to mate
+    pub endgame: Option<Endgame>, // The specialized evaluation of the material, if there is one
+}
+
+impl MaterialEntry {
+    fn new(key: u64, count_pieces: &[usize; 12]) -> Self {
+        let count = |piece_type, color| count_pieces[Piece::new(piece_type, color)] as i16;
+
+        let phase = [Color::White, Color::Black]
+            .into_iter()
+            .map(|color| {
+                count(PieceType::Knight, color)
+                    + count(PieceType::Bishop, color)
+                    + 2 * count(PieceType::Rook, color)
+                    + 4 * count(PieceType::Queen, color)
+            })
+            .sum::<i16>()
+            .min(MAX_PHASE);
+
+        let imbalance = |color| {
+            if count(PieceType::Bishop, color) >= 2 {
+                BISHOP_PAIR_BONUS
+            } else {
+                0
+            }
+        };
+
+        let non_pawn_material = |color| {
+            [PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen]
+                .into_iter()
+                .map(|piece_type| count(piece_type, color) * piece_type.value())
+                .sum::<i16>()
+        };
+        // Without pawns, a small material advantage is hard to convert, and less than a rook can't mate at all.
+        let scale = |color: Color| {
+            let (strong, weak) = (non_pawn_material(color), non_pawn_material(color.flipped()));
+
+            if count(PieceType::Pawn, color) > 0 || strong - weak > PieceType::Bishop.value() {
+                SCALE_NORMAL
+            } else if strong < PieceType::Rook.value() {
+                0
+            } else if weak <= PieceType::Bishop.value() {
+                4
+            } else {
+                14
+            }
+        };
+
+        Self {
+            key,
+            phase,
+            imbalance: imbalance(Color::White) - imbalance(Color::Black),
+            scale_factors: [scale(Color::White), scale(Color::Black)],
+            draw: is_insufficient_material(count_pieces),
+            endgame: Endgame::find(key),
+        }
+    }
+
+    /// Scales the evaluation from the view of `color` by the scale factor of the side, that is ahead.
+    pub fn scale(&self, color: Color, eval: Eval) -> Eval {
+        let strong = if eval > Eval::DRAW { color } else { color.flipped() };
+        scale_eval(eval, self.scale_factors[strong])
+    }
+}
+
+struct MaterialTable {
+    entries: Vec<Option<MaterialEntry>>,
+}
+
+thread_local! {
+    static MATERIAL_TABLE: RefCell<MaterialTable> = RefCell::new(MaterialTable {
+        entries: vec![None; TABLE_SIZE],
+    });
+}
+
+/// Looks up the entry of the board's material and computes it, if it isn't cached yet.
+pub(super) fn probe(board: &Board) -> MaterialEntry {
+    let key = board.material_key;
+
+    MATERIAL_TABLE.with_borrow_mut(|table| {
+        let entry = &mut table.entries[key as usize % TABLE_SIZE];
+        match entry {
+            Some(entry) if entry.key == key => *entry,
+            _ => *entry.insert(MaterialEntry::new(key, &board.count_pieces)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probe, MAX_PHASE, SCALE_NORMAL};
+    use crate::{board::Board, eval::evaluation};
+    use mattis_types::Eval;
+
+    #[test]
+    fn material_entries() {
+        let startpos = probe(&Board::startpos());
+        assert_eq!(startpos.phase, MAX_PHASE);
+        assert_eq!(startpos.imbalance, 0);
+        assert_eq!(startpos.scale_factors, [SCALE_NORMAL; 2]);
+        assert!(!startpos.draw);
+        assert!(startpos.endgame.is_none());
+
+        // The same material is found again, no matter where the pieces are.
+        let board = Board::from_fen("4k3/8/8/8/8/8/3BB3/4K3 w - - 0 1").unwrap();
+        let entry = probe(&board);
+        assert_eq!(entry.phase, 2);
+        assert_eq!(entry.imbalance, 30);
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/1B2K1B1 b - - 0 1").unwrap();
+        assert_eq!(probe(&board).key, entry.key);
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4KN2 w - - 0 1").unwrap();
+        assert!(probe(&board).draw);
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3BKN2 w - - 0 1").unwrap();
+        let entry = probe(&board);
+        assert!(!entry.draw);
+        assert!(entry.endgame.is_some());
+    }
+
+    #[test]
+    fn scale_factors() {
+        // A rook against a minor piece is hard to win, and the minor piece can't win at all.
+        let board = Board::from_fen("4k3/8/8/3b4/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(probe(&board).scale_factors, [4, 0]);
+        assert_eq!(probe(&board).scale(board.color, Eval::from(320)), Eval::from(20));
+        assert!(evaluation(&board) < Eval::from(100));
+
+        // Pawns or a bigger advantage can win.
+        let board = Board::from_fen("4k3/8/8/3b4/8/8/P7/R3K3 w - - 0 1").unwrap();
+        assert_eq!(probe(&board).scale_factors, [SCALE_NORMAL, 0]);
+        let board = Board::from_fen("4k3/8/8/3b4/8/8/8/R2QK3 w - - 0 1").unwrap();
+        assert_eq!(probe(&board).scale_factors, [SCALE_NORMAL, 0]);
+
+        // Two rooks against a rook and a minor piece
+        let board = Board::from_fen("r3k3/8/8/3n4/8/8/8/R3K2R b - - 0 1").unwrap();
+        assert_eq!(probe(&board).scale_factors, [14, 14]);
+    }
+
+    #[test]
+    fn material_key_is_updated_incrementally() {
+        let mut board = Board::from_fen("r3k2r/1P6/8/8/3pP3/8/8/R3K2R b KQkq e3 0 1").unwrap();
+        let key = board.material_key;
+
+        for m in ["d4e3", "b7a8q", "e8e7"] {
+            let m = board.find_move::<crate::notation::SmithNotation>(m).unwrap();
+            board.make_move(m);
+            assert_eq!(board.material_key, Board::generate_material_key(&board.count_pieces));
+        }
+
+        for _ in 0..3 {
+            board.take_move();
+        }
+        assert_eq!(board.material_key, key);
+    }
+}
@@ -19,26 +19,96 @@ pub enum Probe {
     CutOff(Eval),  // We have a successful hit, that was exact or causes a branch cutoff
 }
 
+// Note: Moves from the table are not guaranteed to be playable in the probed position. A different position
+// might have the same key (a key collision) and entries might have been stored without a move.
+// Always check them with `Board::is_pseudo_legal` before using them.
+
 #[derive(Debug, Default)]
 struct Entry {
     key: AtomicU64,
     data: AtomicU64,
 }
 
+/// The data of a table entry.
+///
+/// It is packed into 64 bits: score (16 bits), static evaluation (16 bits), move (16 bits), depth (8 bits),
+/// entry type (2 bits) and age (6 bits).
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Data {
     pub score: Eval,
+    pub static_eval: Option<Eval>, // Positions in check have no static evaluation
     pub cmove: ChessMove,
-    pub depth: u16,
+    pub depth: u8,
     pub kind: EntryType,
-    pub age: u8,
+    pub age: u8, // Only the lowest 6 bits are stored
+}
+
+impl Data {
+    const AGE_MASK: u8 = 0x3F;
+    const NO_STATIC_EVAL: i16 = i16::MIN;
+
+    fn pack(self) -> u64 {
+        let static_eval = self.static_eval.map_or(Self::NO_STATIC_EVAL, Eval::inner);
+        let kind = match self.kind {
+            EntryType::Exact => 0,
+            EntryType::Alpha => 1,
+            EntryType::Beta => 2,
+        };
+
+        let kind_and_age = kind << 6 | self.age & Self::AGE_MASK;
+
+        (self.score.inner() as u16 as u64)
+            | (static_eval as u16 as u64) << 16
+            | (self.cmove.to_raw() as u64) << 32
+            | (self.depth as u64) << 48
+            | (kind_and_age as u64) << 56
+    }
+
+    /// Unpacks the data. Returns `None` for an invalid entry type.
+    fn unpack(data: u64) -> Option<Self> {
+        let static_eval = (data >> 16) as u16 as i16;
+        let kind_and_age = (data >> 56) as u8;
+        let kind = match kind_and_age >> 6 {
+            0 => EntryType::Exact,
+            1 => EntryType::Alpha,
+            2 => EntryType::Beta,
+            _ => return None,
+        };
+
+        Some(Self {
+            score: Eval::from(data as u16 as i16),
+            static_eval: (static_eval != Self::NO_STATIC_EVAL).then_some(Eval::from(static_eval)),
+            cmove: ChessMove::from_raw((data >> 32) as u16),
+            depth: (data >> 48) as u8,
+            kind,
+            age: kind_and_age & Self::AGE_MASK,
+        })
+    }
+
+    /// Checks, if the data allows a branch cutoff at the given depth and search window.
+    /// The score has to be adjusted to the current ply already.
+    pub fn probe(&self, alpha: Eval, beta: Eval, depth: u16) -> Probe {
+        // If the stored data is from a lower depth than we are requesting, it cannot be used for a branch-cutoff.
+        // Just return the move as a pv move for move ordering.
+        if (self.depth as u16) < depth {
+            return Probe::Pv(self.cmove);
+        }
+
+        // Depending on the entry kind, we return a pv move or a cutoff. Exact entrys can always yield a cutoff.
+        // Alpha and beta entries only yield cutoffs, if the score is outside the corresponding bound.
+        match self.kind {
+            EntryType::Alpha if self.score <= alpha => Probe::CutOff(alpha),
+            EntryType::Beta if self.score >= beta => Probe::CutOff(beta),
+            EntryType::Exact => Probe::CutOff(self.score),
+            _ => Probe::Pv(self.cmove),
+        }
+    }
 }
 
 impl Entry {
     /// Stores the given data in the the table entry.
     fn store(&self, key: u64, data: Data) {
-        // Safety: Transmuting to a u64 is fine, because `Data` has a size of exactly 64 bits.
-        let data: u64 = unsafe { std::mem::transmute(data) };
+        let data = data.pack();
         let key = data ^ key;
 
         self.key.store(key, Ordering::Relaxed);
@@ -56,10 +126,7 @@ impl Entry {
         let decoded_key = encoded_key ^ data;
 
         if decoded_key == key {
-            // Safety: We only transmute after checking the decoded key.
-            // if the key doesn't match, we do not transmute and return `None` instead.
-            let data: Data = unsafe { std::mem::transmute(data) };
-            Some(data)
+            Data::unpack(data)
         } else {
             None
         }
@@ -129,11 +196,20 @@ impl TranspositionTable {
         self.load(key).map(|data| data.cmove)
     }
 
-    pub fn store(&self, board: &Board, score: Eval, cmove: ChessMove, depth: u16, kind: EntryType) {
+    pub fn store(
+        &self,
+        board: &Board,
+        score: Eval,
+        static_eval: Option<Eval>,
+        cmove: ChessMove,
+        depth: u16,
+        kind: EntryType,
+    ) {
         // Load currently stored data
         let table_entry = self.entry(board.position_key);
         let entry_data = table_entry.load(board.position_key);
-        let current_table_age = self.current_age.load(Ordering::Relaxed);
+        let current_table_age = self.current_age.load(Ordering::Relaxed) & Data::AGE_MASK;
+        let depth = depth.min(u8::MAX as u16) as u8;
 
         // Its possible, that we encounter hash collisions. We do not override the existing entry if:
         // - the existing entry contains valid data (i.e. it is not corrupted)
@@ -160,6 +236,7 @@ impl TranspositionTable {
 
         let new_data = Data {
             score,
+            static_eval,
             cmove,
             depth,
             kind,
@@ -169,58 +246,62 @@ impl TranspositionTable {
         table_entry.store(board.position_key, new_data);
     }
 
-    pub fn probe(&self, board: &Board, alpha: Eval, beta: Eval, depth: u16) -> Probe {
-        // Try to load data from the table.
+    /// Loads the entry of the given position, with a mate score adjusted to the current ply.
+    pub fn load_entry(&self, board: &Board) -> Option<Data> {
         // Loading `None` means, either there is no data or the data has been corrupted.
-        // Either way, we just return `NoHit`.
-        let Some(data) = self.load(board.position_key) else { return Probe::NoHit };
-
-        // If the stored data is from a lower depth than we are requesting, it cannot be used for a branch-cutoff.
-        // Just return the move as a pv move for move ordering.
-        if data.depth < depth {
-            return Probe::Pv(data.cmove);
-        }
+        let mut data = self.load(board.position_key)?;
 
         // Adjust the score, if its a mate score.
         // See the corresponding comment in the `store`-function for an explanation.
-        let score = if data.score.is_mate() {
-            data.score - board.ply as i16 * data.score.inner().signum()
-        } else {
-            data.score
-        };
-
-        // Depending on the entry kind, we return a pv move or a cutoff. Exact entrys can always yield a cutoff.
-        // Alpha and beta entries only yield cutoffs, if the score is outside the corresponding bound.
-        match data.kind {
-            EntryType::Alpha if score <= alpha => Probe::CutOff(alpha),
-            EntryType::Beta if score >= beta => Probe::CutOff(beta),
-            EntryType::Exact => Probe::CutOff(score),
-            _ => Probe::Pv(data.cmove),
+        if data.score.is_mate() {
+            data.score = data.score - board.ply as i16 * data.score.inner().signum();
         }
+
+        Some(data)
+    }
+
+    pub fn probe(&self, board: &Board, alpha: Eval, beta: Eval, depth: u16) -> Probe {
+        self.load_entry(board)
+            .map_or(Probe::NoHit, |data| data.probe(alpha, beta, depth))
     }
 
     pub fn next_age(&self) {
         self.current_age.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn pv(&self, board: &mut Board, depth: usize, first: Option<ChessMove>) -> PrincipalVariation {
+    /// Returns the principal variation, that starts with the given line and continues by following the stored moves.
+    ///
+    /// Following the stored moves is only a fallback: Entries might have been overwritten, so the result can be
+    /// shorter than `depth` or lead into a different line than the search actually found. An invalid move in `line`
+    /// ends the principal variation before that move.
+    pub fn pv(&self, board: &mut Board, depth: usize, line: &[ChessMove]) -> PrincipalVariation {
         let mut pv = PrincipalVariation::new();
 
-        if depth == 0 {
-            return pv;
-        }
+        for &m in line {
+            let valid = board.is_pseudo_legal(m) && board.make_move(m);
+            debug_assert!(valid, "Invalid move in pv line");
 
-        if let Some(first) = first {
-            pv.push(first);
-            assert!(board.make_move(first), "Invalid first move in pv line");
-        }
+            if !valid {
+                break;
+            }
 
-        while pv.len() < depth {
-            let Some(m) = self.load_move(board.position_key) else { break };
-            board.make_move(m);
             pv.push(m);
         }
 
+        // The stored moves can only continue a valid line.
+        if pv.len() == line.len() {
+            while pv.len() < depth {
+                // The stored move might not even be possible in this position after a key collision.
+                let Some(m) = self.load_move(board.position_key) else { break };
+
+                if !board.is_pseudo_legal(m) || !board.make_move(m) {
+                    break;
+                }
+
+                pv.push(m);
+            }
+        }
+
         for _ in 0..pv.len() {
             board.take_move();
         }
@@ -237,18 +318,58 @@ mod test {
         chess_move::ChessMove,
         hashtable::{Data, Entry, TranspositionTable},
     };
-    use mattis_types::Eval;
+    use mattis_types::{Eval, Square};
+    use rand::Rng;
+
+    fn random_data() -> Data {
+        Data {
+            score: Eval::from(rand::thread_rng().gen_range(-Eval::MAX.inner()..=Eval::MAX.inner())),
+            static_eval: Some(Eval::from(
+                rand::thread_rng().gen_range(-Eval::MAX.inner()..=Eval::MAX.inner()),
+            )),
+            cmove: ChessMove::from_raw(rand::random()),
+            depth: rand::random(),
+            kind: EntryType::Alpha,
+            age: rand::random::<u8>() & Data::AGE_MASK,
+        }
+    }
 
     #[test]
     fn size_of_entry() {
         let entry = Entry::default();
-        let data = Data::default();
 
-        assert_eq!(std::mem::size_of_val(&data), 8);
         assert_eq!(std::mem::size_of_val(&entry), 16);
         // assert_eq!(std::mem::align_of_val(&entry), 8);
     }
 
+    #[test]
+    fn pack_and_unpack_data() {
+        for kind in [EntryType::Exact, EntryType::Alpha, EntryType::Beta] {
+            for static_eval in [None, Some(-Eval::MAX), Some(Eval::DRAW), Some(Eval::MAX)] {
+                let data = Data {
+                    score: -Eval::mate_in(3),
+                    static_eval,
+                    cmove: ChessMove::from_raw(0xFFFF),
+                    depth: u8::MAX,
+                    kind,
+                    age: Data::AGE_MASK,
+                };
+
+                assert_eq!(Data::unpack(data.pack()), Some(data));
+            }
+        }
+
+        // Only the lowest bits of the age are stored.
+        let data = Data {
+            age: Data::AGE_MASK + 2,
+            ..Default::default()
+        };
+        assert_eq!(Data::unpack(data.pack()).map(|data| data.age), Some(1));
+
+        // The unused entry type
+        assert_eq!(Data::unpack(0xC0 << 56), None);
+    }
+
     #[test]
     fn size_of_new_table() {
         for size_mb in [2, 8, 32, 128, 512] {
@@ -277,6 +398,7 @@ mod test {
                 table.store(
                     &board,
                     Eval::default(),
+                    None,
                     ChessMove::default(),
                     u16::default(),
                     EntryType::default(),
@@ -285,16 +407,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn pv_ignores_impossible_moves() {
+        let table = TranspositionTable::new(1);
+        let mut board = Board::startpos();
+
+        // A move from a different position, as it could be stored after a key collision.
+        let m = ChessMove::build().start(Square::E4).end(Square::E5).finish();
+        table.store(&board, Eval::DRAW, None, m, 1, EntryType::Exact);
+        assert!(table.pv(&mut board, 5, &[]).is_empty());
+
+        let m = ChessMove::build()
+            .start(Square::E2)
+            .end(Square::E4)
+            .double_pawn_push()
+            .finish();
+        table.store(&board, Eval::DRAW, None, m, 1, EntryType::Exact);
+        assert_eq!(table.pv(&mut board, 5, &[]).as_slice(), &[m]);
+        assert_eq!(board, Board::startpos());
+    }
+
     #[test]
     fn encode_decode_entry() {
         let key: u64 = rand::random();
-        let data = Data {
-            score: rand::random(),
-            cmove: ChessMove::default(),
-            depth: rand::random(),
-            kind: EntryType::Alpha,
-            age: rand::random(),
-        };
+        let data = random_data();
 
         let entry = Entry::default();
         entry.store(key, data);
@@ -307,13 +443,7 @@ mod test {
         let key1: u64 = rand::random();
         let key2: u64 = rand::random();
 
-        let data = Data {
-            score: rand::random(),
-            cmove: ChessMove::default(),
-            depth: rand::random(),
-            kind: EntryType::Alpha,
-            age: rand::random(),
-        };
+        let data = random_data();
 
         let entry = Entry::default();
         entry.store(key1, data);
@@ -3,11 +3,18 @@
 #![warn(clippy::undocumented_unsafe_blocks)]
 
 pub mod board;
+pub mod book;
 pub mod chess_move;
+pub mod datagen;
+pub mod engine;
 pub mod eval;
 pub mod hashtable;
+pub mod nnue;
 pub mod notation;
 pub mod perft;
+pub mod pgn;
 pub mod search;
+pub mod syzygy;
+pub mod tablebase;
 pub mod tables;
 pub mod time_man;
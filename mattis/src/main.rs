@@ -1,17 +1,23 @@
 use std::{
     io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
 
 use clap::{Parser, Subcommand};
 use mattis::{
-    board::Board,
-    notation::SmithNotation,
-    perft::perft_full,
-    search::{
-        lazy_smp::{LazySMPSetup, SearchConfig},
-        ReportMode,
+    board::{
+        movegen::sliders::{magic_collisions, MagicCollision},
+        Board,
     },
+    book::{builder::BookBuilderConfig, polyglot_key, PolyglotBook},
+    datagen::DatagenConfig,
+    engine::Engine,
+    nnue::Network,
+    perft::perft_full,
+    search::ReportMode,
+    tablebase::{Material, Tablebases},
 };
 use mattis_uci::{self as uci, EngineMessage, GuiMessage, Id};
 
@@ -48,6 +54,104 @@ enum Command {
         /// Disable null pruning
         #[arg(long)]
         no_null_pruning: bool,
+
+        /// Evaluate positions with this NNUE network instead of the handcrafted evaluation.
+        #[arg(long)]
+        nnue: Option<PathBuf>,
+    },
+
+    /// Generates training data from fixed-node self-play games.
+    Datagen {
+        /// Output file for the binary training data.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Additionally export the positions as text (`<fen> | <score> | <result>`) to this file.
+        #[arg(long)]
+        text: Option<PathBuf>,
+        /// Number of threads playing games in parallel.
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+        /// Number of games to play.
+        #[arg(long, default_value_t = 100)]
+        games: u64,
+        /// Node limit per move.
+        #[arg(long, default_value_t = 5000)]
+        nodes: u64,
+        /// Number of random plies at the start of each game.
+        #[arg(long, default_value_t = 8)]
+        random_plies: usize,
+        /// Seed for the random openings.
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+
+    /// Builds or inspects Polyglot opening books.
+    Book {
+        #[command(subcommand)]
+        command: BookCommand,
+    },
+
+    /// Generates endgame tablebases.
+    Tb {
+        #[command(subcommand)]
+        command: TbCommand,
+    },
+
+    /// Checks the lookup tables, that were generated by `mattis-tablegen`.
+    Tablegen {
+        #[command(subcommand)]
+        command: TablegenCommand,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum TablegenCommand {
+    /// Checks, that all rook and bishop magics are collision-free.
+    Verify,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum TbCommand {
+    /// Generates the tables of the materials and of all endgames, they can turn into.
+    ///
+    /// Tables, which already exist in the output directory, are not generated again.
+    Gen {
+        /// Material signatures like `KRKP`, with up to 4 pieces. (Default: all 3 piece endgames)
+        material: Vec<Material>,
+        /// Output directory for the tables.
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum BookCommand {
+    /// Builds a book from the games of PGN files.
+    Build {
+        /// PGN files with the games.
+        #[arg(required = true)]
+        input: Vec<PathBuf>,
+        /// Output file for the book.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Number of plies of each game, that are added to the book.
+        #[arg(long, default_value_t = 16)]
+        plies: usize,
+        /// Minimum number of games, in which a move has to be played.
+        #[arg(long, default_value_t = 1)]
+        min_games: u32,
+        /// Minimum score (between 0.0 and 1.0) of a move, from the view of the moving side.
+        #[arg(long, default_value_t = 0.0)]
+        min_score: f64,
+    },
+
+    /// Shows the book moves of a position.
+    Show {
+        /// The book file.
+        book: PathBuf,
+        /// Position in FEN format.
+        #[arg(long, short, default_value_t = FEN_STARTPOS.to_string())]
+        fen: String,
     },
 }
 
@@ -61,33 +165,147 @@ fn main() {
         Command::Search {
             startpos,
             no_null_pruning,
-        } => single_search(&startpos, !no_null_pruning),
+            nnue,
+        } => single_search(&startpos, !no_null_pruning, nnue),
+        Command::Datagen {
+            output,
+            text,
+            threads,
+            games,
+            nodes,
+            random_plies,
+            seed,
+        } => {
+            let config = DatagenConfig {
+                threads,
+                games,
+                nodes,
+                random_plies,
+                seed,
+                ..Default::default()
+            };
+
+            let positions = mattis::datagen::run(&config, &output, text.as_deref()).expect("Datagen failed");
+            println!("Wrote {positions} positions to `{}`", output.display());
+        }
+        Command::Book {
+            command:
+                BookCommand::Build {
+                    input,
+                    output,
+                    plies,
+                    min_games,
+                    min_score,
+                },
+        } => {
+            let config = BookBuilderConfig {
+                max_plies: plies,
+                min_games,
+                min_score,
+            };
+
+            let summary = mattis::book::builder::build(config, &input, &output).expect("Building the book failed");
+            println!(
+                "Wrote {} entries from {} games to `{}` ({} games skipped)",
+                summary.entries,
+                summary.games,
+                output.display(),
+                summary.skipped_games
+            );
+        }
+        Command::Book {
+            command: BookCommand::Show { book, fen },
+        } => show_book(&book, &fen),
+        Command::Tb {
+            command: TbCommand::Gen { material, output },
+        } => generate_tablebases(&material, &output),
+        Command::Tablegen {
+            command: TablegenCommand::Verify,
+        } => verify_tables(),
     }
 }
 
-fn single_search(pos: &str, null_pruning: bool) {
+fn single_search(pos: &str, null_pruning: bool, nnue: Option<PathBuf>) {
     let go = uci::Go {
         depth: Some(13),
         ..Default::default()
     };
 
-    let search_config = SearchConfig {
-        report_mode: ReportMode::Full,
-        allow_null_pruning: null_pruning,
-        go,
+    let network = nnue.map(|path| Arc::new(Network::load(path).expect("Must be able to load the nnue network")));
+
+    let mut engine = Engine::default();
+    engine.set_position(pos, &[] as &[&str]).unwrap();
+    engine.set_network(network);
+    engine.set_null_pruning(null_pruning);
+    engine.search(go, ReportMode::Full).unwrap().wait();
+}
+
+fn generate_tablebases(materials: &[Material], output: &Path) {
+    let default_materials = ["KQK", "KRK", "KBK", "KNK", "KPK"].map(|material| material.parse().unwrap());
+    let materials = match materials.is_empty() {
+        true => &default_materials,
+        false => materials,
     };
-    let config = search_config;
 
-    let mut lazysmp = LazySMPSetup::default().create();
-    let board = Board::from_fen(pos).unwrap();
-    lazysmp.set_board(board);
-    lazysmp.start_search(config).unwrap();
-    while lazysmp.is_search_running() {}
+    std::fs::create_dir_all(output).expect("Must be able to create the output directory");
+    let mut tablebases = Tablebases::load_dir(output).expect("Must be able to load the existing tables");
+
+    for material in materials {
+        let mut start = Instant::now();
+        tablebases
+            .generate(material, &mut |table| {
+                let path = table.save(output)?;
+                println!(
+                    "Wrote `{}` in {:.1}s (longest mate: {} plies)",
+                    path.display(),
+                    start.elapsed().as_secs_f64(),
+                    table.longest_mate()
+                );
+                start = Instant::now();
+                Ok(())
+            })
+            .expect("Generating the tables failed");
+    }
+}
+
+fn verify_tables() {
+    let collisions = magic_collisions();
+    if collisions.is_empty() {
+        println!("All rook and bishop magics are collision-free");
+        return;
+    }
+
+    for MagicCollision { piece_type, square } in collisions {
+        println!("The {piece_type:?} magic of {square:?} has collisions");
+    }
+    std::process::exit(1);
+}
+
+fn show_book(path: &Path, fen: &str) {
+    let book = PolyglotBook::load(path).expect("Must be able to load the book");
+    let mut board = Board::from_fen(fen).expect("Must be a valid fen");
+
+    println!("key: {:016x}", polyglot_key(&board));
+
+    let moves = book.moves(&board);
+    if moves.is_empty() {
+        println!("Position is not in the book");
+        return;
+    }
+
+    let total: u32 = moves.iter().map(|&(_, weight)| weight as u32).sum();
+    for (m, weight) in moves {
+        let share = weight as f64 / total.max(1) as f64 * 100.0;
+        println!(
+            "{:<8} {:>5}  weight: {weight:>5}  ({share:.1}%)",
+            format!("{}", m.display_algebraic(&mut board)),
+            format!("{}", m.display_smith())
+        );
+    }
 }
 
 fn uci_loop() {
-    let mut board = Board::from_fen(FEN_STARTPOS).unwrap();
-    let mut lazysmp = LazySMPSetup::default().create();
+    let mut engine = Engine::default();
 
     let mut stdin = BufReader::new(std::io::stdin());
     let mut input = String::new();
@@ -97,73 +315,65 @@ fn uci_loop() {
         stdin.read_line(&mut input).expect("Must be able to read from stdin");
 
         let Ok(message) = GuiMessage::parse(&input) else {
-            println!("Received unknown command");
+            print_info_string("Received unknown command".to_string());
             continue;
         };
 
         match message {
             GuiMessage::Uci => print_uci_info(),
-            GuiMessage::Ucinewgame => {
-                lazysmp.reset_ttable();
-                board = Board::from_fen(FEN_STARTPOS).unwrap();
-                lazysmp.stop_search();
-            }
+            GuiMessage::Ucinewgame => engine.new_game(),
             GuiMessage::Isready => println!("{}", EngineMessage::Readyok),
             GuiMessage::Position { pos, moves } => {
-                setup_position(&mut board, pos, &moves);
-                lazysmp.set_board(board.clone());
-            }
-            GuiMessage::Go(go) => {
-                let config = SearchConfig {
-                    report_mode: ReportMode::Uci,
-                    allow_null_pruning: true,
-                    go,
+                let fen = match &pos {
+                    uci::Position::Fen(fen) => fen,
+                    uci::Position::Startpos => FEN_STARTPOS,
                 };
 
-                if lazysmp.start_search(config).is_err() {
-                    println!("Already searching");
-                };
+                if let Err(e) = engine.set_position(fen, &moves) {
+                    print_info_string(format!("Invalid position ({e}). Setting up `startpos` instead."));
+                    engine.set_board(Board::startpos());
+                }
+            }
+            GuiMessage::Setoption { name, value } => {
+                if let Err(e) = engine.set_option(&name, value.as_deref()) {
+                    print_info_string(format!("Could not set option: {e}"));
+                }
             }
-            GuiMessage::Stop => {
-                lazysmp.stop_search();
+            GuiMessage::Go(go) => {
+                // The result is printed by the listener, so the handle is not needed.
+                if engine.search(go, ReportMode::Uci).is_err() {
+                    print_info_string("Already searching".to_string());
+                };
             }
+            GuiMessage::Stop => engine.stop(),
             GuiMessage::Quit => {
-                lazysmp.stop_search();
+                engine.stop();
                 return;
             }
-            _ => println!("This uci command is currently not supported."),
+            _ => print_info_string("This uci command is currently not supported.".to_string()),
         }
     }
 }
 
+/// Prints a message for the user. Anything else than an info string would break the protocol.
+fn print_info_string(string: String) {
+    let info = uci::Info {
+        string: Some(string),
+        ..Default::default()
+    };
+    println!("{}", EngineMessage::Info(info));
+}
+
 fn print_uci_info() {
     let name_msg: EngineMessage = EngineMessage::Id(Id::Name("Mattis".to_string()));
     let author_msg: EngineMessage = EngineMessage::Id(Id::Author("Anton Bornhoeft".to_string()));
 
     println!("{name_msg}",);
     println!("{author_msg}");
-    println!("{}", EngineMessage::Uciok);
-}
-
-fn setup_position(board: &mut Board, pos: uci::Position, moves: &[String]) {
-    let fen = match &pos {
-        uci::Position::Fen(fen) => fen,
-        uci::Position::Startpos => FEN_STARTPOS,
-    };
 
-    *board = Board::from_fen(fen).unwrap();
-
-    for move_str in moves {
-        let chess_move = board.find_move::<SmithNotation>(move_str);
-
-        if let Some(cm) = chess_move {
-            board.make_move(cm);
-        } else {
-            *board = Board::from_fen(FEN_STARTPOS).unwrap();
-            println!("Invalid move `{move_str}`. Setting up `startpos` instead.");
-            break;
-        }
+    for option in Engine::uci_options() {
+        println!("{}", EngineMessage::Option(option));
     }
 
-    board.ply = 0;
+    println!("{}", EngineMessage::Uciok);
 }
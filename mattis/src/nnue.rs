@@ -0,0 +1,449 @@
+//! Efficiently updatable neural network (NNUE) evaluation.
+//!
+//! The network uses a HalfKA feature set: every (king bucket, piece, square) triple is one input feature.
+//! Both sides have their own perspective with their own accumulator. Each perspective is oriented,
+//! so that its king starts on the first rank, and mirrored horizontally, so that its king is always on
+//! the files A-D. This leaves 32 king buckets with 768 piece-square features each.
+//!
+//! ```text
+//! features (24576) -> 2 x 256 accumulators -> CReLU -> 1 output
+//! ```
+//!
+//! The accumulators are kept up to date incrementally by `Board::add_piece`, `Board::clear_piece` and
+//! `Board::move_piece`. A king move changes all features of its own perspective, so that perspective is
+//! refreshed from scratch instead.
+//!
+//! # Network Format
+//! All values are stored in little endian byte order.
+//! ```text
+//! // offset   type                    content
+//! // 0        [u8; 8]                 magic bytes `MATTNNUE`
+//! // 8        u32                     format version (currently 1)
+//! // 12       u32                     number of input features (must be 24576)
+//! // 16       u32                     number of hidden neurons per perspective (must be 256)
+//! // 20       [[i16; 256]; 24576]     feature transformer weights (one row per feature)
+//! // ..       [i16; 256]              feature transformer biases
+//! // ..       [i16; 512]              output weights (side to move first, then the other side)
+//! // ..       i32                     output bias
+//! ```
+//!
+//! The feature transformer is quantized with `QA = 255`, the output weights with `QB = 64`.
+//! The raw network output is scaled by `SCALE = 400` to yield centipawns.
+
+pub mod simd;
+
+use crate::board::Board;
+use mattis_bitboard::BitBoard;
+use mattis_types::{Color, Eval, Piece, Square};
+use std::{
+    fmt::Debug,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+use thiserror::Error;
+
+pub const KING_BUCKETS: usize = 32;
+pub const FEATURES: usize = KING_BUCKETS * 768;
+pub const HIDDEN: usize = 256;
+
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+pub const SCALE: i32 = 400;
+
+const MAGIC: &[u8; 8] = b"MATTNNUE";
+const VERSION: u32 = 1;
+
+// Static evaluations must never reach into the range of mate scores.
+const MAX_EVAL: i64 = 20_000;
+
+#[derive(Debug, Error)]
+pub enum NnueError {
+    #[error("could not read or write the network file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("network file does not start with the magic bytes `MATTNNUE`")]
+    InvalidMagic,
+
+    #[error("network file has unsupported version {0} (expected {VERSION})")]
+    UnsupportedVersion(u32),
+
+    #[error("network has {0} features and {1} hidden neurons (expected {FEATURES} and {HIDDEN})")]
+    WrongDimensions(u32, u32),
+}
+
+/// A row of `HIDDEN` values, aligned for SIMD loads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, align(64))]
+pub struct Row(pub [i16; HIDDEN]);
+
+impl Default for Row {
+    fn default() -> Self {
+        Self([0; HIDDEN])
+    }
+}
+
+pub struct Network {
+    ft_weights: Box<[Row]>, // `FEATURES` rows
+    ft_bias: Row,
+    out_weights: [Row; 2], // [side to move, other side]
+    out_bias: i32,
+}
+
+impl Network {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, NnueError> {
+        let file = std::fs::File::open(path)?;
+        Self::read(&mut BufReader::new(file))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NnueError> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, NnueError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(NnueError::InvalidMagic);
+        }
+
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(NnueError::UnsupportedVersion(version));
+        }
+
+        let features = read_u32(reader)?;
+        let hidden = read_u32(reader)?;
+        if features as usize != FEATURES || hidden as usize != HIDDEN {
+            return Err(NnueError::WrongDimensions(features, hidden));
+        }
+
+        let mut ft_weights = vec![Row::default(); FEATURES].into_boxed_slice();
+        for row in ft_weights.iter_mut() {
+            read_row(reader, row)?;
+        }
+
+        let mut ft_bias = Row::default();
+        read_row(reader, &mut ft_bias)?;
+
+        let mut out_weights = [Row::default(); 2];
+        read_row(reader, &mut out_weights[0])?;
+        read_row(reader, &mut out_weights[1])?;
+
+        let mut out_bias = [0; 4];
+        reader.read_exact(&mut out_bias)?;
+        let out_bias = i32::from_le_bytes(out_bias);
+
+        Ok(Self {
+            ft_weights,
+            ft_bias,
+            out_weights,
+            out_bias,
+        })
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), NnueError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(FEATURES as u32).to_le_bytes())?;
+        writer.write_all(&(HIDDEN as u32).to_le_bytes())?;
+
+        for row in self.ft_weights.iter().chain([&self.ft_bias]).chain(&self.out_weights) {
+            write_row(writer, row)?;
+        }
+
+        writer.write_all(&self.out_bias.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl Debug for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Network")
+            .field("features", &FEATURES)
+            .field("hidden", &HIDDEN)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The accumulators of both perspectives, indexed by `Color`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulator([Row; 2]);
+
+/// A network together with the accumulators for one board.
+#[derive(Clone)]
+pub struct Nnue {
+    network: Arc<Network>,
+    accumulator: Accumulator,
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>, bitboards: &[BitBoard; 12], king_square: [Square; 2]) -> Self {
+        let mut this = Self {
+            network,
+            accumulator: Accumulator([Row::default(); 2]),
+        };
+
+        this.refresh(Color::White, king_square[Color::White], bitboards);
+        this.refresh(Color::Black, king_square[Color::Black], bitboards);
+        this
+    }
+
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    /// Recalculates the accumulator of one perspective from scratch.
+    pub fn refresh(&mut self, perspective: Color, king: Square, bitboards: &[BitBoard; 12]) {
+        let acc = &mut self.accumulator.0[perspective];
+        *acc = self.network.ft_bias;
+
+        for piece in Piece::ALL {
+            for square in bitboards[piece].iter_bit_indices() {
+                let feature = feature_index(perspective, king, piece, square);
+                add_row(acc, &self.network.ft_weights[feature]);
+            }
+        }
+    }
+
+    pub fn add_piece(&mut self, piece: Piece, square: Square, king_square: [Square; 2]) {
+        for perspective in Color::iter_all() {
+            let feature = feature_index(perspective, king_square[perspective], piece, square);
+            add_row(&mut self.accumulator.0[perspective], &self.network.ft_weights[feature]);
+        }
+    }
+
+    pub fn clear_piece(&mut self, piece: Piece, square: Square, king_square: [Square; 2]) {
+        for perspective in Color::iter_all() {
+            let feature = feature_index(perspective, king_square[perspective], piece, square);
+            sub_row(&mut self.accumulator.0[perspective], &self.network.ft_weights[feature]);
+        }
+    }
+
+    /// Moves a piece in the accumulator of a single perspective.
+    pub fn move_piece(&mut self, perspective: Color, king: Square, piece: Piece, from: Square, to: Square) {
+        let weights = &self.network.ft_weights;
+        let acc = &mut self.accumulator.0[perspective];
+        sub_row(acc, &weights[feature_index(perspective, king, piece, from)]);
+        add_row(acc, &weights[feature_index(perspective, king, piece, to)]);
+    }
+
+    /// Evaluates the position from the point of view of `color`.
+    pub fn evaluate(&self, color: Color) -> Eval {
+        let us = &self.accumulator.0[color];
+        let them = &self.accumulator.0[color.flipped()];
+
+        let output = simd::crelu_dot(&us.0, &self.network.out_weights[0].0) as i64
+            + simd::crelu_dot(&them.0, &self.network.out_weights[1].0) as i64
+            + self.network.out_bias as i64;
+
+        let eval = output * SCALE as i64 / (QA * QB) as i64;
+        Eval::from(eval.clamp(-MAX_EVAL, MAX_EVAL) as i16)
+    }
+
+    /// Checks, that the incrementally updated accumulators match a full refresh.
+    pub fn is_consistent(&self, bitboards: &[BitBoard; 12], king_square: [Square; 2]) -> bool {
+        let fresh = Nnue::new(Arc::clone(&self.network), bitboards, king_square);
+        fresh.accumulator == self.accumulator
+    }
+}
+
+impl PartialEq for Nnue {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.network, &other.network) && self.accumulator == other.accumulator
+    }
+}
+
+impl Eq for Nnue {}
+
+impl Debug for Nnue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Nnue")
+            .field("network", &self.network)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Board {
+    /// Attaches a network to the board, which switches `evaluation` from the handcrafted evaluation to NNUE.
+    /// Passing `None` switches back to the handcrafted evaluation.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.nnue = network.map(|network| Box::new(Nnue::new(network, &self.bitboards, self.king_square)));
+    }
+}
+
+/// Calculates the index of the input feature for a `piece` on a `square`, seen from `perspective`.
+pub fn feature_index(perspective: Color, king: Square, piece: Piece, square: Square) -> usize {
+    // Orient the board, so that the perspective's pieces start at the first rank.
+    let orient = |square: Square| match perspective {
+        Color::White => usize::from(square),
+        Color::Black => usize::from(square) ^ 56,
+    };
+
+    let mut king = orient(king);
+    let mut square = orient(square);
+
+    // Mirror the board horizontally, so that the king is always on the files A-D.
+    if king % 8 >= 4 {
+        king ^= 7;
+        square ^= 7;
+    }
+
+    let bucket = (king / 8) * 4 + king % 8;
+    let relative_piece = if piece.color() == perspective { 0 } else { 6 } + usize::from(piece.piece_type());
+
+    bucket * 768 + relative_piece * 64 + square
+}
+
+// The feature transformer uses wrapping arithmetic, to behave exactly like the SIMD implementations would.
+// The compiler auto-vectorizes these loops.
+
+fn add_row(acc: &mut Row, weights: &Row) {
+    for (a, w) in acc.0.iter_mut().zip(&weights.0) {
+        *a = a.wrapping_add(*w);
+    }
+}
+
+fn sub_row(acc: &mut Row, weights: &Row) {
+    for (a, w) in acc.0.iter_mut().zip(&weights.0) {
+        *a = a.wrapping_sub(*w);
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_row(reader: &mut impl Read, row: &mut Row) -> std::io::Result<()> {
+    let mut bytes = [0; HIDDEN * 2];
+    reader.read_exact(&mut bytes)?;
+
+    for (v, b) in row.0.iter_mut().zip(bytes.chunks_exact(2)) {
+        *v = i16::from_le_bytes([b[0], b[1]]);
+    }
+
+    Ok(())
+}
+
+fn write_row(writer: &mut impl Write, row: &Row) -> std::io::Result<()> {
+    let mut bytes = [0; HIDDEN * 2];
+
+    for (v, b) in row.0.iter().zip(bytes.chunks_exact_mut(2)) {
+        b.copy_from_slice(&v.to_le_bytes());
+    }
+
+    writer.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simd, Network, Row, FEATURES, HIDDEN};
+    use crate::board::{movegen::MoveList, Board};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::sync::Arc;
+
+    fn random_network(seed: u64) -> Network {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut random_row = |range: std::ops::Range<i16>| {
+            let mut row = Row::default();
+            row.0.iter_mut().for_each(|v| *v = rng.gen_range(range.clone()));
+            row
+        };
+
+        Network {
+            ft_weights: (0..FEATURES).map(|_| random_row(-64..64)).collect(),
+            ft_bias: random_row(-64..64),
+            out_weights: [random_row(-128..128), random_row(-128..128)],
+            out_bias: 1234,
+        }
+    }
+
+    #[test]
+    fn save_and_load_network() {
+        let network = random_network(1);
+        let mut bytes = vec![];
+        network.write(&mut bytes).unwrap();
+
+        let loaded = Network::read(&mut bytes.as_slice()).unwrap();
+        assert!(network.ft_weights == loaded.ft_weights);
+        assert_eq!(network.ft_bias, loaded.ft_bias);
+        assert_eq!(network.out_weights, loaded.out_weights);
+        assert_eq!(network.out_bias, loaded.out_bias);
+    }
+
+    #[test]
+    fn reject_invalid_network() {
+        let bytes = b"NOTANNUE\x01\x00\x00\x00";
+        assert!(Network::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn incremental_updates_match_refresh() {
+        let network = Arc::new(random_network(2));
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/1ppppp1p/8/p4Pp1/8/8/PPPPP1PP/RNBQKBNR w KQkq g6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+
+        for fen in fens {
+            let mut board = Board::from_fen(fen).unwrap();
+            board.set_network(Some(Arc::clone(&network)));
+
+            // Play random moves and take them back again. `check_board_integrity` compares the
+            // incrementally updated accumulators against a full refresh after every move.
+            let mut played = 0;
+            for _ in 0..40 {
+                let mut moves = MoveList::new();
+                board.generate_all_moves(&mut moves);
+                moves.retain(|m| board.clone().make_move(*m));
+
+                if moves.is_empty() {
+                    break;
+                }
+
+                assert!(board.make_move(moves[rng.gen_range(0..moves.len())]));
+                board.check_board_integrity();
+                played += 1;
+            }
+
+            for _ in 0..played {
+                board.take_move();
+            }
+
+            let fresh = {
+                let mut b = Board::from_fen(fen).unwrap();
+                b.set_network(Some(Arc::clone(&network)));
+                b
+            };
+
+            assert_eq!(board.nnue, fresh.nnue);
+        }
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut rng = StdRng::seed_from_u64(4);
+
+        for _ in 0..100 {
+            let mut input = [0; HIDDEN];
+            let mut weights = [0; HIDDEN];
+            input.iter_mut().for_each(|v| *v = rng.gen_range(-400..400));
+            weights.iter_mut().for_each(|v| *v = rng.gen_range(-128..128));
+
+            assert_eq!(
+                simd::crelu_dot(&input, &weights),
+                simd::crelu_dot_scalar(&input, &weights)
+            );
+        }
+    }
+}
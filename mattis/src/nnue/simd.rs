@@ -0,0 +1,115 @@
+//! SIMD kernels for the NNUE output layer.
+//!
+//! x86-64 uses AVX2 if the CPU supports it and falls back to SSE2, which every x86-64 CPU has.
+//! aarch64 always has NEON. Every other architecture uses the scalar implementation.
+
+use super::{HIDDEN, QA};
+
+/// Calculates the dot product of the clipped (`0..=QA`) `input` with the `weights`.
+#[cfg(target_arch = "x86_64")]
+pub fn crelu_dot(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+    if is_x86_feature_detected!("avx2") {
+        // Safety: We checked, that the CPU supports AVX2.
+        unsafe { x86::crelu_dot_avx2(input, weights) }
+    } else {
+        // Safety: SSE2 is part of the x86-64 baseline.
+        unsafe { x86::crelu_dot_sse2(input, weights) }
+    }
+}
+
+/// Calculates the dot product of the clipped (`0..=QA`) `input` with the `weights`.
+#[cfg(target_arch = "aarch64")]
+pub fn crelu_dot(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+    // Safety: NEON is part of the aarch64 baseline.
+    unsafe { neon::crelu_dot(input, weights) }
+}
+
+/// Calculates the dot product of the clipped (`0..=QA`) `input` with the `weights`.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn crelu_dot(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+    crelu_dot_scalar(input, weights)
+}
+
+pub fn crelu_dot_scalar(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+    input
+        .iter()
+        .zip(weights)
+        .map(|(&x, &w)| (x as i32).clamp(0, QA) * w as i32)
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{HIDDEN, QA};
+    use std::arch::x86_64::*;
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn crelu_dot_avx2(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+        let zero = _mm256_setzero_si256();
+        let qa = _mm256_set1_epi16(QA as i16);
+        let mut sum = _mm256_setzero_si256();
+
+        for i in (0..HIDDEN).step_by(16) {
+            let x = _mm256_loadu_si256(input.as_ptr().add(i).cast());
+            let w = _mm256_loadu_si256(weights.as_ptr().add(i).cast());
+            let x = _mm256_min_epi16(_mm256_max_epi16(x, zero), qa);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(x, w));
+        }
+
+        let sum = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256::<1>(sum));
+        horizontal_sum(sum)
+    }
+
+    /// # Safety
+    /// The CPU must support SSE2.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn crelu_dot_sse2(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+        let zero = _mm_setzero_si128();
+        let qa = _mm_set1_epi16(QA as i16);
+        let mut sum = _mm_setzero_si128();
+
+        for i in (0..HIDDEN).step_by(8) {
+            let x = _mm_loadu_si128(input.as_ptr().add(i).cast());
+            let w = _mm_loadu_si128(weights.as_ptr().add(i).cast());
+            let x = _mm_min_epi16(_mm_max_epi16(x, zero), qa);
+            sum = _mm_add_epi32(sum, _mm_madd_epi16(x, w));
+        }
+
+        horizontal_sum(sum)
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    unsafe fn horizontal_sum(v: __m128i) -> i32 {
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b01_00_11_10>(v));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b10_11_00_01>(v));
+        _mm_cvtsi128_si32(v)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{HIDDEN, QA};
+    use std::arch::aarch64::*;
+
+    /// # Safety
+    /// The CPU must support NEON.
+    #[target_feature(enable = "neon")]
+    pub unsafe fn crelu_dot(input: &[i16; HIDDEN], weights: &[i16; HIDDEN]) -> i32 {
+        let zero = vdupq_n_s16(0);
+        let qa = vdupq_n_s16(QA as i16);
+        let mut sum = vdupq_n_s32(0);
+
+        for i in (0..HIDDEN).step_by(8) {
+            let x = vld1q_s16(input.as_ptr().add(i));
+            let w = vld1q_s16(weights.as_ptr().add(i));
+            let x = vminq_s16(vmaxq_s16(x, zero), qa);
+            sum = vmlal_s16(sum, vget_low_s16(x), vget_low_s16(w));
+            sum = vmlal_high_s16(sum, x, w);
+        }
+
+        vaddvq_s32(sum)
+    }
+}
@@ -60,7 +60,7 @@ impl AlgebraicNotation {
         let moving_piece = board.pieces[cmove.start()].unwrap();
 
         let mut movelist = MoveList::new();
-        board.generate_all_moves(&mut movelist);
+        board.generate_legal_moves(&mut movelist);
 
         let mut ambiguities = movelist
             .iter()
@@ -97,10 +97,7 @@ impl AlgebraicNotation {
         assert!(board.make_move(cmove));
 
         if board.in_check() {
-            let mut movelist = MoveList::new();
-            board.generate_all_moves(&mut movelist);
-
-            if movelist.is_empty() {
+            if !board.has_legal_moves() {
                 write!(w, "#")?;
             } else {
                 write!(w, "+")?;
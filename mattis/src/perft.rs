@@ -54,15 +54,18 @@ pub fn perft(board: &mut Board, depth: usize, check_integrity: bool) -> u32 {
     }
 
     let mut movelist = MoveList::default();
-    board.generate_all_moves(&mut movelist);
+    board.generate_legal_moves(&mut movelist);
+
+    // All generated moves are legal, so we can count the leaves without making the moves (bulk counting).
+    if depth == 1 && !check_integrity {
+        return movelist.len() as u32;
+    }
+
     let mut sum = 0;
 
-    // Try to make each move in the movelist.
     for m in movelist {
-        // Skip pseudomoves (moves, that cannot be made, because the would lead to an illegal position).
-        if !board.make_move(m) {
-            continue;
-        };
+        let is_legal = board.make_move(m);
+        debug_assert!(is_legal, "The legal move generator generated an illegal move");
 
         // Sum the leave count of each move for the final result
         sum += perft(board, depth - 1, check_integrity);
@@ -0,0 +1,264 @@
+//! Reading games in the PGN format.
+//!
+//! Only the parts of PGN, that are necessary to replay the main line of a game, are supported: Tags, moves in
+//! standard algebraic notation and the game result. Comments, variations and annotations are skipped.
+
+use crate::{
+    board::{movegen::MoveList, Board, FenError},
+    chess_move::ChessMove,
+    notation::AlgebraicNotation,
+};
+use std::io::{self, BufRead};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PgnError {
+    #[error("could not read the pgn file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("game has an invalid `FEN` tag: {0}")]
+    InvalidFen(#[from] FenError),
+
+    #[error("illegal move `{san}` at ply {ply}")]
+    IllegalMove { san: String, ply: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    WhiteWins,
+    Draw,
+    BlackWins,
+    Unknown, // `*`, for ongoing or abandoned games
+}
+
+impl GameResult {
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(Self::WhiteWins),
+            "1/2-1/2" => Some(Self::Draw),
+            "0-1" => Some(Self::BlackWins),
+            "*" => Some(Self::Unknown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>, // The moves of the main line in standard algebraic notation
+    pub result: GameResult,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the start position, which is given by the `FEN` tag or the standard start position.
+    pub fn start_position(&self) -> Result<Board, PgnError> {
+        match self.tag("FEN") {
+            Some(fen) => Ok(Board::from_fen(fen)?),
+            None => Ok(Board::startpos()),
+        }
+    }
+
+    /// Replays the moves of the game and calls `f` with each position and the move played from it.
+    ///
+    /// Stops at the first illegal move, after calling `f` for all moves before it.
+    pub fn replay(&self, mut f: impl FnMut(&mut Board, ChessMove)) -> Result<(), PgnError> {
+        let mut board = self.start_position()?;
+
+        for (ply, san) in self.moves.iter().enumerate() {
+            let m = find_san_move(&mut board, san).ok_or_else(|| PgnError::IllegalMove { san: san.clone(), ply })?;
+
+            f(&mut board, m);
+            board.make_move(m);
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the legal move, that is written as `san` in standard algebraic notation.
+///
+/// Check and annotation symbols are optional, and castling can be written with `O` or `0`.
+pub fn find_san_move(board: &mut Board, san: &str) -> Option<ChessMove> {
+    fn normalize(san: &str) -> String {
+        san.chars()
+            .filter(|c| !matches!(c, '+' | '#' | '!' | '?' | '='))
+            .map(|c| if c == '0' { 'O' } else { c })
+            .collect()
+    }
+
+    let san = normalize(san);
+    let mut legal_moves = MoveList::new();
+    board.generate_legal_moves(&mut legal_moves);
+
+    legal_moves.into_iter().find(|&m| {
+        let mut written = String::new();
+        AlgebraicNotation::write(&mut written, m, board).is_ok() && normalize(&written) == san
+    })
+}
+
+/// Reads the games of a PGN file one after another.
+pub struct PgnReader<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+        }
+    }
+
+    fn read_game(&mut self) -> io::Result<Option<PgnGame>> {
+        let mut game = PgnGame {
+            tags: vec![],
+            moves: vec![],
+            result: GameResult::Unknown,
+        };
+        let mut in_comment = false; // Inside of `{...}`, which can span several lines
+        let mut variation_depth = 0; // Nesting depth of `(...)`
+        let mut has_content = false;
+
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                // A game without result at the end of the file
+                return Ok(has_content.then_some(game));
+            }
+
+            let line = self.line.trim();
+            if !in_comment && variation_depth == 0 && line.starts_with('[') {
+                if let Some(tag) = parse_tag(line) {
+                    game.tags.push(tag);
+                    has_content = true;
+                }
+                continue;
+            }
+
+            // Put spaces around the delimiters, so they become separate tokens.
+            let line = line
+                .replace('{', " { ")
+                .replace('}', " } ")
+                .replace('(', " ( ")
+                .replace(')', " ) ");
+
+            for token in line.split_whitespace() {
+                match token {
+                    _ if in_comment => in_comment = token != "}",
+                    "{" => in_comment = true,
+                    "(" => variation_depth += 1,
+                    ")" => variation_depth -= 1,
+                    _ if token.starts_with(';') => break, // Comment until the end of the line
+                    _ if variation_depth > 0 || token.starts_with('$') || token == "e.p." => {}
+                    _ => {
+                        if let Some(result) = GameResult::from_token(token) {
+                            game.result = result;
+                            return Ok(Some(game));
+                        }
+
+                        // Move numbers can be attached to the move (`1.e4` or `1...e5`).
+                        let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                        if !san.is_empty() {
+                            game.moves.push(san.to_string());
+                            has_content = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = io::Result<PgnGame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_game().transpose()
+    }
+}
+
+/// Parses a tag pair like `[Event "Casual Game"]`.
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    Some((name.to_string(), value.replace("\\\"", "\"")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_san_move, GameResult, PgnError, PgnReader};
+    use crate::board::Board;
+
+    const PGN: &str = r#"[Event "Test"]
+[White "A"]
+[Black "B"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 {a comment
+spanning lines} Nc6 (2... d6 3. d4) 3.Bb5 a6 $1 4. Ba4 Nf6 5. O-O Be7 ; rest of the line
+6. Re1 b5 7. Bb3 d6 1-0
+
+[Event "Test 2"]
+[FEN "4k3/P7/8/8/8/8/8/4K3 w - - 0 1"]
+
+1. a8=Q+ Kd7 2. Qb7+ *
+
+[Event "Broken"]
+
+1. e4 e5 2. Ke3 1/2-1/2
+"#;
+
+    #[test]
+    fn read_games() {
+        let games: Vec<_> = PgnReader::new(PGN.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(games.len(), 3);
+
+        assert_eq!(games[0].tag("White"), Some("A"));
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+        assert_eq!(
+            games[0].moves,
+            ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O", "Be7", "Re1", "b5", "Bb3", "d6"]
+        );
+
+        let mut plies = 0;
+        games[0].replay(|_, _| plies += 1).unwrap();
+        assert_eq!(plies, 14);
+
+        assert_eq!(games[1].result, GameResult::Unknown);
+        let mut moves = vec![];
+        games[1]
+            .replay(|_, m| moves.push(format!("{}", m.display_smith())))
+            .unwrap();
+        assert_eq!(moves, ["a7a8q", "e8d7", "a8b7"]);
+
+        let mut plies = 0;
+        let result = games[2].replay(|_, _| plies += 1);
+        assert!(matches!(result, Err(PgnError::IllegalMove { ply: 2, .. })));
+        assert_eq!(plies, 2);
+    }
+
+    #[test]
+    fn san_moves() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/2N3N1/8/R3K2R w KQkq - 0 1").unwrap();
+
+        let smith = |board: &mut Board, san| find_san_move(board, san).map(|m| format!("{}", m.display_smith()));
+        assert_eq!(smith(&mut board, "O-O-O").as_deref(), Some("e1c1"));
+        assert_eq!(smith(&mut board, "0-0").as_deref(), Some("e1g1"));
+        assert_eq!(smith(&mut board, "Nce4").as_deref(), Some("c3e4"));
+        assert_eq!(smith(&mut board, "Nge4!?").as_deref(), Some("g3e4"));
+        assert_eq!(smith(&mut board, "Ne4"), None); // Ambiguous
+        assert_eq!(smith(&mut board, "Rxa8+").as_deref(), Some("a1a8"));
+        assert_eq!(smith(&mut board, "Ke3"), None);
+    }
+}
@@ -1,28 +1,58 @@
 use crate::{
-    board::{movegen::MoveList, Board},
+    board::Board,
     chess_move::ChessMove,
     eval::evaluation,
     hashtable::{EntryType, PrincipalVariation, Probe, TranspositionTable},
+    syzygy::{Syzygy, Wdl},
+    tablebase::Tablebases,
     time_man::TimeMan,
 };
-use history::SearchHistory;
+use extensions::SearchDoubleExtensions;
+use history::{history_bonus, ThreadHistories};
 use killers::SearchKillers;
-use mattis_types::{Eval, Piece, PieceType};
+use mattis_types::{Eval, Piece, PieceType, Square};
 use mattis_uci as uci;
 use mattis_uci::EngineMessage;
-use std::sync::Arc;
-
+use move_picker::{previous_move, MovePicker};
+use params::SearchParams;
+use pv_table::PvTable;
+use reductions::late_move_reduction;
+use smallvec::SmallVec;
+use static_evals::SearchStaticEvals;
+use std::sync::{mpsc, Arc};
+
+pub mod counter_moves;
+pub mod extensions;
 pub mod history;
 pub mod killers;
 pub mod lazy_smp;
+mod move_picker;
+pub mod params;
+pub mod pv_table;
+pub mod reductions;
+pub mod skill;
+pub mod static_evals;
+
+/// The deepest iteration of the iterative deepening. It is only reached, if every line ends early,
+/// like in positions covered by the tablebases.
+const MAX_SEARCH_DEPTH: u16 = 100;
 
 struct ABContext {
     time_man: TimeMan,
     stats: SearchStats,
     transposition_table: Arc<TranspositionTable>,
     search_killers: SearchKillers,
-    search_history: SearchHistory,
+    histories: ThreadHistories,
+    static_evals: SearchStaticEvals,
+    double_extensions: SearchDoubleExtensions,
+    pv_table: PvTable,
+    mate_search: Option<u16>, // Searching for a mate in this number of moves
+    excluded_root_moves: SmallVec<[ChessMove; 4]>, // Root moves, that are skipped (to find the next best moves)
     allow_null_pruning: bool,
+    params: SearchParams,
+    tablebases: Option<Arc<Tablebases>>, // Probed instead of searching positions with few pieces
+    syzygy: Option<Arc<Syzygy>>,         // Probed after captures and pawn moves
+    syzygy_probe_depth: u16,             // Positions with the most pieces of the tables are only probed this deep
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -33,6 +63,7 @@ pub struct SearchStats {
     pub leaves: u64,            // Total count of visited leaf nodes
     pub fh: u64,                // Count of fail-highs (beta cut off)
     pub fhf: u64,               // Count of fail-highs at the first move
+    pub tbhits: u64,            // Count of positions found in the tablebases
     pub bestmove: ChessMove,    // The best move
     pub pv: PrincipalVariation, // Principle Variation Line
 }
@@ -46,6 +77,7 @@ impl Default for SearchStats {
             leaves: 0,
             fh: 0,
             fhf: 0,
+            tbhits: 0,
             bestmove: ChessMove::default(),
             pv: PrincipalVariation::new(),
         }
@@ -66,7 +98,7 @@ impl IterativeDeepening {
     }
 
     fn next_depth(&mut self, board: &mut Board, ctx: &mut ABContext) -> Option<SearchStats> {
-        if !ctx.time_man.enough_time_for_next_depth(&ctx.stats) {
+        if self.next_depth > MAX_SEARCH_DEPTH || !ctx.time_man.enough_time_for_next_depth(&ctx.stats) {
             return None;
         };
 
@@ -76,7 +108,16 @@ impl IterativeDeepening {
         let mut loop_count = 0;
 
         let score = loop {
-            let score = alpha_beta(alpha, beta, self.next_depth, board, ctx, ctx.allow_null_pruning, true);
+            let score = alpha_beta(
+                alpha,
+                beta,
+                self.next_depth,
+                board,
+                ctx,
+                ctx.allow_null_pruning,
+                true,
+                None,
+            );
 
             if ctx.time_man.stop(&ctx.stats, true) {
                 ctx.stats.depth -= 1;
@@ -106,54 +147,113 @@ impl IterativeDeepening {
             None
         } else {
             ctx.stats.score = score;
-            ctx.stats.pv = ctx.transposition_table.pv(board, ctx.stats.depth as usize, None);
+            // A cutoff by the transposition table at the root leaves no collected line, only then the table is used.
+            let line = ctx.pv_table.line(0);
+            ctx.stats.pv = if line.is_empty() {
+                ctx.transposition_table.pv(board, ctx.stats.depth as usize, line)
+            } else {
+                PrincipalVariation::from_slice(line)
+            };
             ctx.stats.bestmove = ctx.stats.pv.first().copied().unwrap_or_default();
             Some(ctx.stats.clone())
         }
     }
 }
 
-fn take_next_move(
-    list: &mut MoveList,
-    pv_move: Option<ChessMove>,
-    ctx: &ABContext,
-    board: &Board,
-) -> Option<ChessMove> {
-    let (idx, _) = list
-        .iter()
-        .enumerate()
-        .min_by_key(|(_, m)| -score_move(**m, pv_move, ctx, board))?;
-
-    let m = list.swap_remove(idx);
-    Some(m)
+/// Runs a complete search on the current thread, without reporting anything.
+///
+/// This is meant for tooling like data generation, which runs many small searches in parallel.
+/// The returned stats belong to the last fully searched depth.
+pub fn search_on_current_thread(
+    board: &mut Board,
+    transposition_table: Arc<TranspositionTable>,
+    time_man: TimeMan,
+) -> SearchStats {
+    let mut ctx = ABContext {
+        time_man,
+        stats: SearchStats::default(),
+        transposition_table,
+        search_killers: Default::default(),
+        histories: Default::default(),
+        static_evals: Default::default(),
+        double_extensions: Default::default(),
+        pv_table: Default::default(),
+        mate_search: None,
+        excluded_root_moves: SmallVec::new(),
+        allow_null_pruning: true,
+        params: SearchParams::default(),
+        tablebases: None,
+        syzygy: None,
+        syzygy_probe_depth: 0,
+    };
+
+    let mut iterative_deepening = IterativeDeepening::new(Eval::DRAW, 1);
+    while iterative_deepening.next_depth(board, &mut ctx).is_some() {}
+
+    ctx.stats
 }
 
-fn score_move(m: ChessMove, pv_move: Option<ChessMove>, ctx: &ABContext, board: &Board) -> i32 {
-    let captured = if m.is_en_passant() {
-        Some(PieceType::Pawn)
-    } else {
-        board.pieces[m.end()].map(Piece::piece_type)
-    };
+impl ABContext {
+    /// Checks, if the score proves a mate within the number of moves of the mate search.
+    fn proves_mate(&self, score: Eval) -> bool {
+        self.mate_search
+            .is_some_and(|moves| score > Eval::DRAW && score.mate_ply().is_some_and(|ply| (ply as u16) < 2 * moves))
+    }
 
-    if Some(m) == pv_move {
-        2_000_000
-    } else if let Some(victim) = captured {
-        //SAFETY: A chess move always moves a piece
-        let attacker = unsafe { board.pieces[m.start()].unwrap_unchecked().piece_type() };
-        1_000_000 + mvv_lva(attacker, victim)
-    } else if ctx.search_killers.slot1(board.ply) == m {
-        900_000
-    } else if ctx.search_killers.slot2(board.ply) == m {
-        800_000
-    } else {
-        let piece = board.pieces[m.start()].unwrap();
-        ctx.search_history.entry(piece, m.end()) as i32
+    /// Probes the Syzygy tables right after captures and pawn moves, where the fifty move counter doesn't change
+    /// the result. Positions with the most pieces of the tables are only probed with enough depth left, because
+    /// their tables are the largest and slowest to access.
+    fn probe_syzygy(&self, board: &mut Board, depth: u16) -> Option<Wdl> {
+        let syzygy = self.syzygy.as_ref()?;
+        let piece_count = board.bb_all.bit_count() as usize;
+        let deep_enough = piece_count < syzygy.max_pieces() || depth >= self.syzygy_probe_depth;
+
+        (board.ply >= 1 && board.fifty_move == 0 && deep_enough)
+            .then(|| syzygy.probe_wdl(board))
+            .flatten()
+    }
+
+    /// Sums up the butterfly history and both continuation histories of a quiet move.
+    fn quiet_history(&self, previous_moves: &[Option<(Piece, Square)>; 2], piece: Piece, square: Square) -> i32 {
+        let continuation: i32 = previous_moves
+            .iter()
+            .zip(&self.histories.continuation_history)
+            .filter_map(|(previous, history)| previous.map(|previous| history.entry(previous, piece, square)))
+            .sum();
+
+        self.histories.search_history.entry(piece, square) + continuation
+    }
+
+    /// Rewards a quiet move for a beta cutoff (or punishes it with a negative bonus).
+    fn update_quiet_history(
+        &mut self,
+        previous_moves: &[Option<(Piece, Square)>; 2],
+        piece: Piece,
+        square: Square,
+        bonus: i32,
+    ) {
+        self.histories.search_history.update(piece, square, bonus);
+
+        for (previous, history) in previous_moves.iter().zip(&mut self.histories.continuation_history) {
+            if let Some(previous) = previous {
+                history.update(*previous, piece, square, bonus);
+            }
+        }
     }
 }
 
-fn mvv_lva(attacker: PieceType, victim: PieceType) -> i32 {
-    const SCORES: [i32; PieceType::ALL.len()] = [1, 2, 3, 4, 5, 6];
-    (SCORES[victim] << 3) - SCORES[attacker]
+/// Returns the moved piece and the type of the captured piece of a capture, which has not been made yet.
+fn capture_pieces(board: &Board, m: ChessMove) -> (Piece, PieceType) {
+    // Safety: A chess move always moves a piece.
+    let attacker = unsafe { board.pieces[m.start()].unwrap_unchecked() };
+    let victim = if m.is_en_passant() {
+        PieceType::Pawn
+    } else {
+        // Safety: A capture always captures a piece.
+        unsafe { board.pieces[m.end()].unwrap_unchecked().piece_type() }
+    };
+
+    (attacker, victim)
 }
 
 #[allow(clippy::too_many_arguments)] // TODO: reduce the number of arguments into an args struct or something
@@ -165,7 +265,10 @@ fn alpha_beta(
     ctx: &mut ABContext,
     allow_null_move: bool,
     is_pv: bool,
+    excluded_move: Option<ChessMove>,
 ) -> Eval {
+    ctx.pv_table.clear(board.ply);
+
     // We frequently check, if the search should stop
     // (e.g. because of time running out or a gui command).
     if ctx.time_man.stop(&ctx.stats, true) {
@@ -174,11 +277,6 @@ fn alpha_beta(
 
     ctx.stats.nodes += 1;
 
-    if depth == 0 {
-        ctx.stats.leaves += 1;
-        return quiescence(alpha, beta, board, ctx);
-    }
-
     // Check if we reached a draw by fifty move rule or 3-fold-repetition.
     // We actually evaluate a single repetition as a draw, so we can find
     // drawn positions earlier.
@@ -186,37 +284,136 @@ fn alpha_beta(
         return Eval::DRAW;
     }
 
+    // Positions covered by the tablebases don't need to be searched, their exact score is known.
+    // The tables ignore the fifty move rule and repetitions, so they are probed after the draw check.
+    if board.ply >= 1 {
+        if let Some(value) = ctx.tablebases.as_ref().and_then(|tablebases| tablebases.probe(board)) {
+            ctx.stats.tbhits += 1;
+            return value.eval(board.ply).clamp(alpha, beta);
+        }
+    }
+
+    if depth == 0 {
+        ctx.stats.leaves += 1;
+        return quiescence(alpha, beta, board, ctx);
+    }
+
+    // The Syzygy tables tell the result of the position, but not how long it takes to win. So a win or loss
+    // outside of the window cuts off, and otherwise it bounds the score of the search.
+    let (mut tb_floor, mut tb_ceiling) = (-Eval::MAX, Eval::MAX);
+    if let Some(wdl) = excluded_move
+        .is_none()
+        .then(|| ctx.probe_syzygy(board, depth))
+        .flatten()
+    {
+        ctx.stats.tbhits += 1;
+        let score = wdl.eval(board.ply);
+
+        match wdl {
+            Wdl::Win if score < beta => tb_floor = score,
+            Wdl::Loss if score > alpha => tb_ceiling = score,
+            _ => return score.clamp(alpha, beta),
+        }
+    }
+
     // We extend the depth, if we are in check. This increases the chance to
     // properly evaluate, whether we are able to get out of check or not.
     // Even though we handle being in check in the quiescence search, this still
     // seems to yield positive results.
-    if board.in_check() {
+    let in_check = board.in_check();
+    if in_check {
         depth += 1;
     }
 
     // Probe the transposition table. There a two kinds of hashtable hits:
     // A CutOff-Hit allows us to safely perform a branch cutoff and return early.
     // Otherwise we can still use the table hit for move ordering.
-    let hashtable_probe = ctx.transposition_table.probe(board, alpha, beta, depth);
-    let pv_move = match hashtable_probe {
+    // A search with an excluded move doesn't use the table, because the entry belongs to the search of all moves.
+    let tt_entry = excluded_move
+        .is_none()
+        .then(|| ctx.transposition_table.load_entry(board))
+        .flatten();
+
+    // Neither do we store results, that are missing some moves.
+    let excludes_moves = excluded_move.is_some() || (board.ply == 0 && !ctx.excluded_root_moves.is_empty());
+    let pv_move = match tt_entry.map_or(Probe::NoHit, |data| data.probe(alpha, beta, depth)) {
         Probe::NoHit => None,
         Probe::Pv(cmove) => Some(cmove),
+        // The root is always searched, so the principal variation and its score are complete.
+        Probe::CutOff(_) if board.ply == 0 => tt_entry.map(|data| data.cmove),
         Probe::CutOff(score) => return score,
     };
 
+    // Double extensions are limited per search path, so the search can't explode.
+    let double_extensions = if board.ply == 0 {
+        0
+    } else {
+        ctx.double_extensions.get(board.ply)
+    };
+
+    // Remember the static evaluation of this position, so we can tell whether our position is improving.
+    // An improving position makes it less likely, that late moves fail low.
+    // The table remembers the static evaluation, so we don't have to evaluate the position again.
+    let static_eval = (!in_check).then(|| {
+        tt_entry
+            .and_then(|data| data.static_eval)
+            .unwrap_or_else(|| evaluation(board))
+    });
+    ctx.static_evals.set(board.ply, static_eval);
+    let improving = ctx.static_evals.is_improving(board.ply);
+    let params = ctx.params;
+
+    // A mate search has to find every mate within its depth limit. It must not skip any moves based on the
+    // static evaluation or on the assumption, that our move ordering is good.
+    let selective = ctx.mate_search.is_none();
+
+    if let Some(static_eval) = static_eval.filter(|_| selective && !is_pv && board.ply != 0) {
+        // Reverse futility pruning:
+        // If our static evaluation beats beta by a margin, that grows with the remaining depth, we assume the
+        // opponent won't be able to catch up and cut off right away.
+        let margin = params.rfp_margin * (depth as i16 - improving as i16);
+        if depth <= params.rfp_max_depth as u16 && !beta.is_mate() && static_eval - margin >= beta {
+            return beta;
+        }
+
+        // Razoring:
+        // If we are far behind alpha at low depth, only captures could save us. We verify that with the quiescence
+        // search and give up on this node, if it confirms the fail-low.
+        if depth <= params.razoring_max_depth as u16 && static_eval + params.razoring_margin * depth as i16 <= alpha {
+            let score = quiescence(alpha, beta, board, ctx);
+
+            if score <= alpha {
+                return alpha;
+            }
+        }
+    }
+
+    // Futility pruning:
+    // At low depth, quiet moves can't improve a static evaluation, that is far below alpha, by enough.
+    // These moves are skipped in the move loop.
+    let futility_pruning = static_eval.is_some_and(|static_eval| {
+        let margin = params.futility_base + params.futility_margin * depth as i16;
+        selective
+            && !is_pv
+            && depth <= params.futility_max_depth as u16
+            && !alpha.is_mate()
+            && static_eval + margin <= alpha
+    });
+
+    // Late move pruning:
+    // At low depth, we expect a fail-low, once we have searched a certain number of moves without improving alpha.
+    let late_move_pruning = selective && !is_pv && !in_check && depth <= params.lmp_max_depth as u16;
+    let late_move_count = params.lmp_base as usize + params.lmp_factor as usize * (depth as usize).pow(2);
+
     // Null move pruning optimization.
     // We do a nothing move (passing move) and see if we are still much better than the oponent (by causing a beta cutoff).
     // In that case we can be sure to have found a good position and return early.
     // We don't want null move pruning, if we are in check, because that would cause an illegal position.
-    if allow_null_move
-        && !is_pv
-        && !board.in_check()
-        && board.ply != 0
-        && board.count_big_pieces[board.color] > 1
-        && depth >= 4
+    if allow_null_move && !is_pv && !in_check && board.ply != 0 && board.count_big_pieces[board.color] > 1 && depth >= 4
     {
         board.make_null_move();
-        let score = -alpha_beta(-beta, -beta + 1i16, depth - 4, board, ctx, false, false);
+        ctx.double_extensions.set(board.ply, double_extensions);
+        let score = -alpha_beta(-beta, -beta + 1i16, depth - 4, board, ctx, false, false, None);
         board.take_null_move();
 
         // Don't use the results, if we entered stop-mode in the meantime.
@@ -232,15 +429,79 @@ fn alpha_beta(
         }
     }
 
-    let mut moves = MoveList::default();
-    board.generate_all_moves(&mut moves);
+    let killers = [ctx.search_killers.slot1(board.ply), ctx.search_killers.slot2(board.ply)];
+    let previous_moves = [previous_move(board, 1), previous_move(board, 2)];
+    let counter_move = previous_moves[0]
+        .map(|(piece, square)| ctx.histories.search_counter_moves.get(piece, square))
+        .unwrap_or_default();
+    let mut move_picker = MovePicker::new(board, pv_move, killers, counter_move, previous_moves);
+
+    // Moves, that were searched without causing a beta cutoff. They are punished, once another move cuts off.
+    let mut searched_quiets = SmallVec::<[ChessMove; 32]>::new();
+    let mut searched_captures = SmallVec::<[ChessMove; 16]>::new();
 
     let mut best_move = ChessMove::default(); // Will contain the best move we found during the search.
     let mut best_score = -Eval::MAX; // TODO: do we really need this?
     let mut legal_moves = 0; // Counts the number of legal moves. Not every generated move is necessarily legal.
     let mut alpha_changed = false; // signals if alpha has changed during the evaluation of each move
 
-    while let Some(m) = take_next_move(&mut moves, pv_move, ctx, board) {
+    while let Some(m) = move_picker.next(board, ctx) {
+        if excluded_move == Some(m) || (board.ply == 0 && ctx.excluded_root_moves.contains(&m)) {
+            continue;
+        }
+
+        // Singular extensions:
+        // If the hash move is a lot better than all other moves, it is singular and we extend it. To find out, we
+        // search all other moves with a reduced depth and a null window below the stored score of the hash move.
+        let mut extension = 0;
+        let singular_candidate = tt_entry.filter(|data| {
+            data.cmove == m
+                && selective
+                && board.ply != 0
+                && (board.ply as u16) < 2 * ctx.stats.depth
+                && depth >= params.singular_min_depth as u16
+                && data.kind != EntryType::Alpha
+                && data.depth as u16 + 3 >= depth
+                && !data.score.is_mate()
+        });
+
+        if let Some(data) = singular_candidate {
+            let singular_beta = data.score - params.singular_margin * depth as i16;
+            let singular_depth = (depth - 1) / 2;
+            let score = alpha_beta(
+                singular_beta - 1_i16,
+                singular_beta,
+                singular_depth,
+                board,
+                ctx,
+                false,
+                false,
+                Some(m),
+            );
+
+            if ctx.time_man.stop(&ctx.stats, true) {
+                return Eval::DRAW;
+            }
+
+            if score < singular_beta {
+                extension = 1;
+
+                // Double extensions:
+                // If all other moves are much worse, we extend the hash move even further.
+                if !is_pv
+                    && score < singular_beta - params.double_extension_margin
+                    && double_extensions < params.double_extension_limit as u8
+                {
+                    extension = 2;
+                }
+            } else if singular_beta >= beta {
+                // Multi-cut:
+                // The hash move is not singular, because another move beats the singular beta as well.
+                // If even the singular beta is above beta, we expect multiple moves to fail high and cut off.
+                return beta;
+            }
+        }
+
         let is_legal_move = board.make_move(m);
 
         // The move might have been illegal. in that case the move was not made and we can skip to the next one.
@@ -249,27 +510,106 @@ fn alpha_beta(
         }
 
         legal_moves += 1;
+        ctx.double_extensions
+            .set(board.ply, double_extensions + (extension == 2) as u8);
+        let new_depth = depth - 1 + extension;
+
+        // Late move reductions:
+        // Thanks to move ordering, quiet moves late in the list rarely improve alpha. We search them with a reduced
+        // depth first and only do a full depth search, if they surprisingly beat alpha.
+        let is_quiet = !m.is_capture() && !m.is_promotion();
+        let gives_check = board.in_check();
+        let mut reduction = 0;
+
+        // Quiet moves, that don't give check, can be pruned. We keep the first move, so we always have a best move.
+        let prunable = is_quiet && !gives_check && legal_moves > 1;
+        if prunable && futility_pruning {
+            board.take_move();
+            continue;
+        }
 
-        let score = if !alpha_changed {
-            -alpha_beta(-beta, -alpha, depth - 1, board, ctx, ctx.allow_null_pruning, is_pv)
+        // Once late move pruning kicks in, we don't even need to generate the remaining quiet moves.
+        if prunable && late_move_pruning && legal_moves > late_move_count {
+            move_picker.skip_quiets();
+            board.take_move();
+            continue;
+        }
+
+        if selective && depth >= 3 && legal_moves > 1 + is_pv as usize && is_quiet && !in_check {
+            reduction = late_move_reduction(depth, legal_moves);
+
+            if is_pv {
+                reduction -= 1;
+            }
+
+            // The move is already made, so the killers and the history belong to the previous ply.
+            if ctx.search_killers.slot1(board.ply - 1) == m || ctx.search_killers.slot2(board.ply - 1) == m {
+                reduction -= 1;
+            }
+
+            // Safety: The move was made, so there is a piece on the end square.
+            let piece = unsafe { board.pieces[m.end()].unwrap_unchecked() };
+            reduction -= (ctx.quiet_history(&previous_moves, piece, m.end()) / 8192).clamp(-2, 2) as i16;
+
+            if gives_check {
+                reduction -= 1;
+            }
+
+            if !improving {
+                reduction += 1;
+            }
+
+            // Always drop into at least a depth 1 search.
+            reduction = reduction.clamp(0, depth as i16 - 2);
+        }
+
+        // Principal variation search:
+        // The first move is searched with the full window. For every other move we only try to prove, that it is
+        // worse than our current best move, using a null window. If that fails, we have to search it again.
+        let score = if legal_moves == 1 {
+            -alpha_beta(
+                -beta,
+                -alpha,
+                new_depth,
+                board,
+                ctx,
+                ctx.allow_null_pruning,
+                is_pv,
+                None,
+            )
         } else {
-            let est = -alpha_beta(
+            let reduced_depth = new_depth - reduction as u16;
+            let mut score = -alpha_beta(
                 -alpha - 1_i16,
                 -alpha,
-                depth - 1,
+                reduced_depth,
                 board,
                 ctx,
                 ctx.allow_null_pruning,
                 false,
+                None,
             );
-            if est > alpha {
-                -alpha_beta(-beta, -alpha, depth - 1, board, ctx, ctx.allow_null_pruning, true)
-            } else {
-                -Eval::MAX
+
+            if score > alpha && reduction > 0 {
+                score = -alpha_beta(
+                    -alpha - 1_i16,
+                    -alpha,
+                    new_depth,
+                    board,
+                    ctx,
+                    ctx.allow_null_pruning,
+                    false,
+                    None,
+                );
             }
+
+            if score > alpha && score < beta {
+                score = -alpha_beta(-beta, -alpha, new_depth, board, ctx, ctx.allow_null_pruning, true, None);
+            }
+
+            score
         };
 
-        // let score = -alpha_beta(-beta, -alpha, depth - 1, board, ctx, ctx.allow_null_pruning);
         board.take_move();
 
         // Don't use the result of alpha-beta if we entered stop-mode in the meantime. The result is probably nonsense.
@@ -290,26 +630,55 @@ fn alpha_beta(
             // A quiet move, that caused a beta-cutoff is labeled a 'killer-move'.
             // If the same move is encountered at the same ply but in a different position, it will be
             // prefered by move ordering. We use two killer slots, to not forget good moves in some situations.
+            let bonus = history_bonus(depth);
             if !m.is_capture() && !m.is_promotion() {
                 ctx.search_killers.store(board.ply, m);
+
+                // The move also becomes the counter move to the previous move.
+                if let Some((piece, square)) = previous_moves[0] {
+                    ctx.histories.search_counter_moves.store(piece, square, m);
+                }
+
+                // Reward the move in the histories and punish the quiet moves, that failed to cut off before it.
+                // Safety: A chess move always moves a piece.
+                let piece = unsafe { board.pieces[m.start()].unwrap_unchecked() };
+                ctx.update_quiet_history(&previous_moves, piece, m.end(), bonus);
+
+                for quiet in searched_quiets {
+                    // Safety: The searched moves were taken back, so their pieces are on their start squares.
+                    let piece = unsafe { board.pieces[quiet.start()].unwrap_unchecked() };
+                    ctx.update_quiet_history(&previous_moves, piece, quiet.end(), -bonus);
+                }
+            } else if m.is_capture() {
+                let (attacker, victim) = capture_pieces(board, m);
+                ctx.histories.capture_history.update(attacker, m.end(), victim, bonus);
+            }
+
+            // Captures, that were searched before, didn't refute the previous move either.
+            for capture in searched_captures {
+                let (attacker, victim) = capture_pieces(board, capture);
+                ctx.histories
+                    .capture_history
+                    .update(attacker, capture.end(), victim, -bonus);
             }
 
             // Store the move in the hashtable and mark it as a beta-cutoff
-            ctx.transposition_table.store(board, beta, m, depth, EntryType::Beta);
+            if !excludes_moves {
+                ctx.transposition_table
+                    .store(board, beta, static_eval, m, depth, EntryType::Beta);
+            }
 
             return beta; // fail hard beta-cutoff
         } else if score > alpha {
             alpha = score;
             alpha_changed = true;
+            ctx.pv_table.update(board.ply, m);
+        }
 
-            // If we improved alpha with this move, we increase a corresponding score in our history.
-            // This helps move ordering by prefering moves that are similar to moves which caused alpha improvements before.
-            // TODO: I am not sure, we are doing this right. I should test not using the history heuristic or using a
-            // different added value.
-            if !m.is_capture() {
-                let piece = board.pieces[m.start()].unwrap();
-                *ctx.search_history.entry_mut(piece, m.end()) += depth as u64; // TODO: is this better: += depth * depth or 2^depth?
-            }
+        if m.is_capture() {
+            searched_captures.push(m);
+        } else if !m.is_promotion() {
+            searched_quiets.push(m);
         }
 
         if score > best_score {
@@ -319,8 +688,13 @@ fn alpha_beta(
     }
 
     // If we haven't found any legal move, we are either in checkmate or in a stalemate.
+    // Unless the only legal move was excluded.
+    if legal_moves == 0 && excludes_moves {
+        return alpha;
+    }
+
     if legal_moves == 0 {
-        if board.in_check() {
+        if in_check {
             return -Eval::mate_in(board.ply as u8);
         }
 
@@ -336,13 +710,17 @@ fn alpha_beta(
         EntryType::Alpha
     };
     let score = if alpha_changed { alpha } else { best_score }; // TODO: I think, weh should be able to always use alpha here?
-    ctx.transposition_table
-        .store(board, score, best_move, depth, hashentry_kind);
+    if !excludes_moves {
+        ctx.transposition_table
+            .store(board, score, static_eval, best_move, depth, hashentry_kind);
+    }
 
-    alpha
+    alpha.clamp(tb_floor, tb_ceiling)
 }
 
 fn quiescence(mut alpha: Eval, beta: Eval, board: &mut Board, ctx: &mut ABContext) -> Eval {
+    ctx.pv_table.clear(board.ply);
+
     if board.is_repetition() || board.fifty_move >= 100 {
         return Eval::DRAW;
     }
@@ -358,16 +736,36 @@ fn quiescence(mut alpha: Eval, beta: Eval, board: &mut Board, ctx: &mut ABContex
         }
     }
 
-    let mut moves = MoveList::with_capacity(64);
-
-    if in_check {
-        board.generate_all_moves(&mut moves);
+    // When we are in check, we need to consider every evasion. Otherwise captures, that lose material, are very
+    // unlikely to improve alpha, so the move picker doesn't return them at all.
+    // A mate search can't rely on material, because a sacrifice can lead to the mate.
+    let mut move_picker = if in_check {
+        MovePicker::new(board, None, [ChessMove::default(); 2], ChessMove::default(), [None; 2])
+    } else if ctx.mate_search.is_some() {
+        MovePicker::new_captures()
     } else {
-        board.generate_capture_moves(&mut moves);
-    }
+        MovePicker::new_good_captures()
+    };
 
     let mut legal_moves = 0;
-    while let Some(m) = take_next_move(&mut moves, None, ctx, board) {
+    while let Some(m) = move_picker.next(board, ctx) {
+        // Delta pruning:
+        // Skip captures, that can't raise the score above alpha, even if we win the captured piece for free.
+        if !in_check && ctx.mate_search.is_none() {
+            let captured = if m.is_en_passant() {
+                Some(PieceType::Pawn)
+            } else {
+                board.pieces[m.end()].map(Piece::piece_type)
+            };
+
+            let promotion_gain = m.promoted().map_or(0, |p| p.value() - PieceType::Pawn.value());
+            let max_gain = captured.map_or(0, PieceType::value) + promotion_gain + ctx.params.delta_margin;
+
+            if standing_pat + max_gain <= alpha {
+                continue;
+            }
+        }
+
         let is_valid_move = board.make_move(m);
 
         if !is_valid_move {
@@ -398,93 +796,229 @@ fn quiescence(mut alpha: Eval, beta: Eval, board: &mut Board, ctx: &mut ABContex
     alpha
 }
 
+/// The final result of a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub bestmove: ChessMove,
+    pub ponder: Option<ChessMove>, // The expected answer to the best move
+    pub score: Eval,
+    pub pv: PrincipalVariation,
+    pub depth: u16,
+    pub nodes: u64,
+    pub tbhits: u64,
+}
+
+impl From<&SearchStats> for SearchResult {
+    fn from(stats: &SearchStats) -> Self {
+        Self {
+            bestmove: stats.bestmove,
+            ponder: stats.pv.get(1).copied(),
+            score: stats.score,
+            pv: stats.pv.clone(),
+            depth: stats.depth,
+            nodes: stats.nodes,
+            tbhits: stats.tbhits,
+        }
+    }
+}
+
+/// Receives the progress and the result of a search. The methods are called on the main search thread.
+pub trait SearchListener: Send + Sync {
+    /// Called with the stats of every completed depth.
+    fn depth_finished(&self, _stats: &SearchStats) {}
+
+    /// Called once with the final stats, after the search has stopped.
+    fn search_finished(&self, _stats: &SearchStats) {}
+}
+
+/// A progress update of a search, for listening with a closure or a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchUpdate {
+    Depth(SearchStats),
+    Finished(SearchStats),
+}
+
+impl<F> SearchListener for F
+where
+    F: Fn(SearchUpdate) + Send + Sync,
+{
+    fn depth_finished(&self, stats: &SearchStats) {
+        self(SearchUpdate::Depth(stats.clone()));
+    }
+
+    fn search_finished(&self, stats: &SearchStats) {
+        self(SearchUpdate::Finished(stats.clone()));
+    }
+}
+
+impl SearchListener for mpsc::Sender<SearchUpdate> {
+    // Sending only fails, if nobody is listening anymore.
+    fn depth_finished(&self, stats: &SearchStats) {
+        let _ = self.send(SearchUpdate::Depth(stats.clone()));
+    }
+
+    fn search_finished(&self, stats: &SearchStats) {
+        let _ = self.send(SearchUpdate::Finished(stats.clone()));
+    }
+}
+
+/// Ignores the search.
+impl SearchListener for () {}
+
+/// Prints the progress and the result of the search to stdout.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ReportMode {
     Uci,
     Full,
 }
 
-fn report_after_depth(mode: ReportMode, stats: SearchStats) {
-    match mode {
-        ReportMode::Uci => {
-            let info = EngineMessage::Info(uci::Info {
-                depth: Some(stats.depth as u32),
-                nodes: Some(stats.nodes as u32),
-                pv: stats.pv.into_iter().map(|m| format!("{}", m.display_smith())).collect(),
-                // FIXME: Mate score can be off by 1 at low depths,
-                // because the score comes straight from the hashtable which stored the entry one move ago.
-                score: Some(uci::Score(stats.score)),
-                ..Default::default()
-            });
-
-            println!("{info}");
+impl SearchListener for ReportMode {
+    fn depth_finished(&self, stats: &SearchStats) {
+        match self {
+            ReportMode::Uci => {
+                let info = EngineMessage::Info(uci::Info {
+                    depth: Some(stats.depth as u32),
+                    nodes: Some(stats.nodes as u32),
+                    pv: stats.pv.iter().map(|m| format!("{}", m.display_smith())).collect(),
+                    score: Some(uci::Score(stats.score)),
+                    tbhits: Some(stats.tbhits as u32),
+                    ..Default::default()
+                });
+
+                println!("{info}");
+            }
+            ReportMode::Full => {
+                println!("Intermediate (depth {}):", stats.depth);
+                println!(
+                    " - bestmove: {}, score: {}, ",
+                    stats.bestmove.display_smith(),
+                    uci::Score(stats.score)
+                );
+                println!(
+                    " - leaves: {}, nodes: {}, ratio: {:0.02}, tbhits: {}",
+                    stats.leaves,
+                    stats.nodes,
+                    stats.leaves as f64 / stats.nodes as f64,
+                    stats.tbhits
+                );
+                println!(
+                    " - fhf: {}, fh: {}, ratio: {:0.02}",
+                    stats.fhf,
+                    stats.fh,
+                    stats.fhf as f64 / stats.fh as f64
+                );
+                print!(" - pv:");
+
+                for m in &stats.pv {
+                    print!(" {}", m.display_smith());
+                }
+
+                println!()
+            }
         }
-        ReportMode::Full => {
-            println!("Intermediate (depth {}):", stats.depth);
-            println!(
-                " - bestmove: {}, score: {}, ",
-                stats.bestmove.display_smith(),
-                uci::Score(stats.score)
-            );
-            println!(
-                " - leaves: {}, nodes: {}, ratio: {:0.02}",
-                stats.leaves,
-                stats.nodes,
-                stats.leaves as f64 / stats.nodes as f64
-            );
-            println!(
-                " - fhf: {}, fh: {}, ratio: {:0.02}",
-                stats.fhf,
-                stats.fh,
-                stats.fhf as f64 / stats.fh as f64
-            );
-            print!(" - pv:");
+    }
 
-            for m in stats.pv {
-                print!(" {}", m.display_smith());
-            }
+    fn search_finished(&self, stats: &SearchStats) {
+        match self {
+            ReportMode::Uci => {
+                let bestmove = EngineMessage::Bestmove {
+                    move_: format!("{}", stats.bestmove.display_smith()),
+                    ponder: stats.pv.get(1).map(|m| format!("{}", m.display_smith())),
+                };
 
-            println!()
+                println!("{bestmove}");
+            }
+            ReportMode::Full => {
+                println!("Final (depth {}):", stats.depth);
+                println!(
+                    " - bestmove: {}, score: {}, ",
+                    stats.bestmove.display_smith(),
+                    uci::Score(stats.score)
+                );
+                println!(
+                    " - leaves: {}, nodes: {}, ratio: {:0.02}, tbhits: {}",
+                    stats.leaves,
+                    stats.nodes,
+                    stats.leaves as f64 / stats.nodes as f64,
+                    stats.tbhits
+                );
+                println!(
+                    " - fhf: {}, fh: {}, ratio: {:0.02}",
+                    stats.fhf,
+                    stats.fh,
+                    stats.fhf as f64 / stats.fh as f64
+                );
+                print!(" - pv:");
+
+                for m in &stats.pv {
+                    print!(" {}", m.display_smith());
+                }
+
+                println!()
+            }
         }
     }
 }
 
-fn report_after_search(mode: ReportMode, stats: SearchStats) {
-    match mode {
-        ReportMode::Uci => {
-            let bestmove = EngineMessage::Bestmove {
-                move_: format!("{}", stats.bestmove.display_smith()),
-                ponder: None,
-            };
+#[cfg(test)]
+mod tests {
+    use super::{ABContext, IterativeDeepening, SearchStats};
+    use crate::{board::Board, hashtable::TranspositionTable, time_man::Limits};
+    use mattis_types::Eval;
+    use std::sync::Arc;
+
+    fn mate_search(fen: &str, moves: u16) -> SearchStats {
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut ctx = ABContext {
+            time_man: Limits::new().depth(Some(2 * moves - 1)).start_now(),
+            stats: SearchStats::default(),
+            transposition_table: Arc::new(TranspositionTable::new(16)),
+            search_killers: Default::default(),
+            histories: Default::default(),
+            static_evals: Default::default(),
+            double_extensions: Default::default(),
+            pv_table: Default::default(),
+            mate_search: Some(moves),
+            excluded_root_moves: Default::default(),
+            allow_null_pruning: false,
+            params: Default::default(),
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: 0,
+        };
 
-            println!("{bestmove}");
+        let mut iterative_deepening = IterativeDeepening::new(Eval::DRAW, 1);
+        while let Some(stats) = iterative_deepening.next_depth(&mut board, &mut ctx) {
+            if ctx.proves_mate(stats.score) {
+                return stats;
+            }
         }
-        ReportMode::Full => {
-            println!("Final (depth {}):", stats.depth);
-            println!(
-                " - bestmove: {}, score: {}, ",
-                stats.bestmove.display_smith(),
-                uci::Score(stats.score)
-            );
-            println!(
-                " - leaves: {}, nodes: {}, ratio: {:0.02}",
-                stats.leaves,
-                stats.nodes,
-                stats.leaves as f64 / stats.nodes as f64
-            );
-            println!(
-                " - fhf: {}, fh: {}, ratio: {:0.02}",
-                stats.fhf,
-                stats.fh,
-                stats.fhf as f64 / stats.fh as f64
-            );
-            print!(" - pv:");
 
-            for m in stats.pv {
-                print!(" {}", m.display_smith());
-            }
+        ctx.stats
+    }
 
-            println!()
-        }
+    #[test]
+    fn find_mates() {
+        // Back rank mate
+        let stats = mate_search("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", 1);
+        assert_eq!(stats.score, Eval::mate_in(1));
+        assert_eq!(format!("{}", stats.bestmove.display_smith()), "d1d8");
+
+        // Queen sacrifice followed by a discovered mate
+        let stats = mate_search("r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1", 2);
+        assert_eq!(stats.score, Eval::mate_in(3));
+        assert_eq!(format!("{}", stats.bestmove.display_smith()), "h6h7");
+
+        // The king is hunted down
+        let stats = mate_search("r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - 0 1", 3);
+        assert_eq!(stats.score, Eval::mate_in(5));
+        assert_eq!(stats.pv.len(), 5);
+    }
+
+    #[test]
+    fn no_mate_within_limit() {
+        // The mate in 2 can't be proven with a search for a mate in 1.
+        let stats = mate_search("r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1", 1);
+        assert!(stats.score.mate_ply().is_none_or(|ply| ply > 1));
     }
 }
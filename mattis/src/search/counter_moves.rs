@@ -0,0 +1,22 @@
+use crate::chess_move::ChessMove;
+use mattis_types::{Piece, Square};
+
+/// Remembers the quiet move, that refuted a move (indexed by the moved piece and its end square) with a beta cutoff.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SearchCounterMoves([[ChessMove; 64]; 12]);
+
+impl SearchCounterMoves {
+    pub fn get(&self, piece: Piece, square: Square) -> ChessMove {
+        self.0[piece][square]
+    }
+
+    pub fn store(&mut self, piece: Piece, square: Square, m: ChessMove) {
+        self.0[piece][square] = m;
+    }
+}
+
+impl Default for SearchCounterMoves {
+    fn default() -> Self {
+        Self([[ChessMove::default(); 64]; 12])
+    }
+}
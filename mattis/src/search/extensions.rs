@@ -0,0 +1,23 @@
+/// The number of double extensions on the current search path, indexed by ply.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SearchDoubleExtensions(Box<[u8]>);
+
+impl SearchDoubleExtensions {
+    pub fn new(size: usize) -> Self {
+        Self(vec![0; size].into_boxed_slice())
+    }
+
+    pub fn get(&self, ply: usize) -> u8 {
+        self.0[ply]
+    }
+
+    pub fn set(&mut self, ply: usize, count: u8) {
+        self.0[ply] = count;
+    }
+}
+
+impl Default for SearchDoubleExtensions {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
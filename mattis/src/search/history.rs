@@ -1,15 +1,34 @@
-use mattis_types::{Piece, Square};
+use super::counter_moves::SearchCounterMoves;
+use mattis_types::{Piece, PieceType, Square};
 
+/// The absolute value of every history entry stays below this bound.
+pub const MAX_HISTORY: i32 = 16_384;
+
+/// Returns the bonus for a move, that caused a beta cutoff at the given depth. Moves, that were searched
+/// before it without causing the cutoff, receive the same value as a malus.
+pub fn history_bonus(depth: u16) -> i32 {
+    (depth as i32 * depth as i32 * 16 + depth as i32 * 32).min(1536)
+}
+
+/// Gravity update: The closer an entry gets to the bound, the less it changes in the same direction.
+/// This keeps the entries bounded and lets stale values decay, once moves stop working.
+fn apply_bonus(entry: &mut i16, bonus: i32) {
+    let bonus = bonus.clamp(-MAX_HISTORY, MAX_HISTORY);
+    let value = *entry as i32;
+    *entry = (value + bonus - value * bonus.abs() / MAX_HISTORY) as i16;
+}
+
+/// Butterfly history for quiet moves, indexed by the moved piece and its end square.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct SearchHistory([[u64; 64]; 12]);
+pub struct SearchHistory([[i16; 64]; 12]);
 
 impl SearchHistory {
-    pub fn entry(&self, piece: Piece, square: Square) -> u64 {
-        self.0[piece][square]
+    pub fn entry(&self, piece: Piece, square: Square) -> i32 {
+        self.0[piece][square] as i32
     }
 
-    pub fn entry_mut(&mut self, piece: Piece, square: Square) -> &mut u64 {
-        &mut self.0[piece][square]
+    pub fn update(&mut self, piece: Piece, square: Square, bonus: i32) {
+        apply_bonus(&mut self.0[piece][square], bonus);
     }
 }
 
@@ -18,3 +37,120 @@ impl Default for SearchHistory {
         Self([[0; 64]; 12])
     }
 }
+
+/// History for captures, indexed by the attacking piece, the target square and the type of the captured piece.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CaptureHistory([[[i16; 6]; 64]; 12]);
+
+impl CaptureHistory {
+    pub fn entry(&self, attacker: Piece, square: Square, victim: PieceType) -> i32 {
+        self.0[attacker][square][victim as usize] as i32
+    }
+
+    pub fn update(&mut self, attacker: Piece, square: Square, victim: PieceType, bonus: i32) {
+        apply_bonus(&mut self.0[attacker][square][victim as usize], bonus);
+    }
+}
+
+impl Default for CaptureHistory {
+    fn default() -> Self {
+        Self([[[0; 6]; 64]; 12])
+    }
+}
+
+/// History for quiet moves in the context of an earlier move: Indexed by the piece and end square
+/// of the earlier move and the piece and end square of the current move.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContinuationHistory(Box<[i16]>);
+
+impl ContinuationHistory {
+    fn index(previous: (Piece, Square), piece: Piece, square: Square) -> usize {
+        ((previous.0 as usize * 64 + previous.1 as usize) * 12 + piece as usize) * 64 + square as usize
+    }
+
+    pub fn entry(&self, previous: (Piece, Square), piece: Piece, square: Square) -> i32 {
+        self.0[Self::index(previous, piece, square)] as i32
+    }
+
+    pub fn update(&mut self, previous: (Piece, Square), piece: Piece, square: Square, bonus: i32) {
+        apply_bonus(&mut self.0[Self::index(previous, piece, square)], bonus);
+    }
+}
+
+impl ContinuationHistory {
+    /// Resets all entries without reallocating the table.
+    pub fn clear(&mut self) {
+        self.0.fill(0);
+    }
+}
+
+impl Default for ContinuationHistory {
+    fn default() -> Self {
+        Self(vec![0; 12 * 64 * 12 * 64].into_boxed_slice())
+    }
+}
+
+/// The move ordering statistics of a search thread, which are kept between the searches of a game.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadHistories {
+    pub search_history: SearchHistory,
+    pub capture_history: CaptureHistory,
+    pub continuation_history: [ContinuationHistory; 2], // Indexed by the move one and two plies ago
+    pub search_counter_moves: SearchCounterMoves,
+}
+
+impl ThreadHistories {
+    /// Forgets everything, that was learned in the previous game.
+    pub fn clear(&mut self) {
+        self.search_history = Default::default();
+        self.capture_history = Default::default();
+        self.continuation_history
+            .iter_mut()
+            .for_each(ContinuationHistory::clear);
+        self.search_counter_moves = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{history_bonus, SearchHistory, ThreadHistories, MAX_HISTORY};
+    use mattis_types::{Piece, Square};
+
+    #[test]
+    fn gravity_keeps_entries_bounded() {
+        let mut history = SearchHistory::default();
+
+        for _ in 0..1000 {
+            history.update(Piece::WhiteKnight, Square::F3, history_bonus(20));
+        }
+        let saturated = history.entry(Piece::WhiteKnight, Square::F3);
+        assert!((1..=MAX_HISTORY).contains(&saturated));
+
+        // A malus on a saturated entry has a bigger effect than the bonus before.
+        history.update(Piece::WhiteKnight, Square::F3, -history_bonus(20));
+        assert!(saturated - history.entry(Piece::WhiteKnight, Square::F3) > history_bonus(20));
+
+        for _ in 0..1000 {
+            history.update(Piece::WhiteKnight, Square::F3, -history_bonus(20));
+        }
+        let entry = history.entry(Piece::WhiteKnight, Square::F3);
+        assert!((-MAX_HISTORY..0).contains(&entry));
+    }
+
+    #[test]
+    fn clear_forgets_the_previous_game() {
+        let mut histories = ThreadHistories::default();
+        let previous = (Piece::BlackPawn, Square::E5);
+        histories
+            .search_history
+            .update(Piece::WhiteKnight, Square::F3, history_bonus(10));
+        histories.continuation_history[1].update(previous, Piece::WhiteKnight, Square::F3, history_bonus(10));
+
+        histories.clear();
+        assert_eq!(histories.search_history.entry(Piece::WhiteKnight, Square::F3), 0);
+        assert_eq!(
+            histories.continuation_history[1].entry(previous, Piece::WhiteKnight, Square::F3),
+            0
+        );
+    }
+}
@@ -1,44 +1,111 @@
-use super::{alpha_beta, report_after_search, ABContext, SearchStats};
+use super::{alpha_beta, ABContext, SearchStats};
 use crate::{
     board::Board,
+    book::{BookSelection, PolyglotBook},
     chess_move::ChessMove,
     hashtable::TranspositionTable,
-    search::{report_after_depth, IterativeDeepening, ReportMode},
+    search::{
+        history::ThreadHistories,
+        params::SearchParams,
+        skill::{search_with_skill, Skill},
+        IterativeDeepening, SearchListener,
+    },
+    syzygy::{RootProbe, Syzygy},
+    tablebase::Tablebases,
     time_man::{Limits, TimeMan},
 };
 use bus::{Bus, BusReader};
 use mattis_types::{Color, Eval};
 use mattis_uci as uci;
+use rand::{rngs::StdRng, SeedableRng};
+use smallvec::smallvec;
 
 use std::{
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Condvar, Mutex,
     },
     thread::JoinHandle,
     time::Duration,
 };
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct SearchConfig {
-    pub report_mode: ReportMode,
+    pub listener: Arc<dyn SearchListener>, // Receives the progress and the result of the search
     pub allow_null_pruning: bool,
+    pub params: SearchParams,
+    pub skill: Option<Skill>,            // Limits the strength of the search
+    pub book: Option<Arc<PolyglotBook>>, // Plays a book move instead of searching, if there is one
+    pub book_selection: BookSelection,
+    pub book_seed: Option<u64>,              // Picks the weighted book moves reproducibly
+    pub tablebases: Option<Arc<Tablebases>>, // Plays the best move of the tables, if they cover the position
+    pub syzygy: Option<Arc<Syzygy>>,         // Restricts the root moves and is probed within the search
+    pub syzygy_probe_depth: u16,
     pub go: uci::Go,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 struct ThreadConfig {
-    report_mode: ReportMode,
+    listener: Arc<dyn SearchListener>,
     time_man: TimeMan,
     estimate_eval: Eval,
     estimate_bestmove: ChessMove,
     allow_null_pruning: bool,
+    params: SearchParams,
+    tablebases: Option<Arc<Tablebases>>,
+    syzygy: Option<Arc<Syzygy>>,
+    syzygy_probe_depth: u16,
+    root_probe: Option<RootProbe>, // Only the best moves of the Syzygy tables are searched
+    mate: Option<u16>,
+    skill: Option<Skill>,
+    instant_move: Option<(ChessMove, Eval)>, // A move from the book or the tablebases, that is played without search
+    outcome: Arc<SearchOutcome>,
+}
+
+/// The final stats of a search, which are set by the main search thread once it has finished.
+#[derive(Debug, Default)]
+pub struct SearchOutcome {
+    stats: Mutex<Option<SearchStats>>,
+    finished: Condvar,
+}
+
+impl SearchOutcome {
+    fn finish(&self, stats: SearchStats) {
+        *self.stats.lock().unwrap() = Some(stats);
+        self.finished.notify_all();
+    }
+
+    /// Blocks until the search has finished and returns its final stats.
+    pub fn wait(&self) -> SearchStats {
+        let stats = self
+            .finished
+            .wait_while(self.stats.lock().unwrap(), |stats| stats.is_none());
+        stats
+            .unwrap()
+            .clone()
+            .expect("The stats are set, when the search has finished")
+    }
+
+    /// Like [`SearchOutcome::wait`], but gives up after the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<SearchStats> {
+        let (stats, _) = self
+            .finished
+            .wait_timeout_while(self.stats.lock().unwrap(), timeout, |stats| stats.is_none())
+            .unwrap();
+        stats.clone()
+    }
+
+    /// Returns the final stats, if the search has already finished.
+    pub fn try_stats(&self) -> Option<SearchStats> {
+        self.stats.lock().unwrap().clone()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 enum Message {
     StartSearch(Arc<ThreadConfig>),
     SetupBoard(Box<Board>),
+    NewGame,
     Quit,
 }
 
@@ -107,6 +174,8 @@ impl LazySMPSetup {
             supporters,
             ttable,
             search_stop_flag: None,
+            search_outcome: None,
+            presearch_histories: None,
             board: Board::startpos(),
             bus,
         }
@@ -118,6 +187,8 @@ pub struct LazySMP {
     supporters: Vec<JoinHandle<()>>,
     ttable: Arc<TranspositionTable>,
     search_stop_flag: Option<Arc<AtomicBool>>,
+    search_outcome: Option<Arc<SearchOutcome>>, // The outcome of the last search, until it is waited for
+    presearch_histories: Option<ThreadHistories>, // Allocated by the first presearch of a game
     board: Board,
     bus: Bus<Message>,
 }
@@ -127,6 +198,15 @@ impl LazySMP {
         self.ttable.reset();
     }
 
+    /// Clears the move ordering histories of all threads, which are otherwise kept between searches.
+    pub fn clear_histories(&mut self) {
+        if let Some(histories) = &mut self.presearch_histories {
+            histories.clear();
+        }
+
+        self.bus.broadcast(Message::NewGame);
+    }
+
     pub fn set_board(&mut self, board: Board) {
         self.board = board.clone();
 
@@ -134,10 +214,10 @@ impl LazySMP {
         self.bus.broadcast(message);
     }
 
-    /// Starts a new search.
+    /// Starts a new search and returns its outcome, which can be waited on.
     ///
     /// Fails, if a search is already running
-    pub fn start_search(&mut self, search_config: SearchConfig) -> Result<(), AlreadyRunning> {
+    pub fn start_search(&mut self, search_config: SearchConfig) -> Result<Arc<SearchOutcome>, AlreadyRunning> {
         if self.is_search_running() {
             return Err(AlreadyRunning);
         }
@@ -150,9 +230,26 @@ impl LazySMP {
         // Calculate the time limit and create the time manager
         let (hard_time, soft_time) = calculate_time_limit(&search_config.go, self.board.color).unzip();
 
+        // A mate in N moves is at most 2N-1 plies away.
+        let mate = search_config.go.mate.map(|moves| moves.clamp(1, u8::MAX as u32) as u16);
+        let mate_depth = mate.map(|moves| 2 * moves - 1);
+        let skill = search_config.skill;
+        let depth = [
+            search_config.go.depth.map(|d| d as u16),
+            mate_depth,
+            skill.map(|s| s.depth_limit()),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let nodes = [search_config.go.nodes.map(|n| n as u64), skill.map(|s| s.node_limit())]
+            .into_iter()
+            .flatten()
+            .min();
+
         let time_man = Limits::new()
-            .depth(search_config.go.depth.map(|d| d as u16))
-            .nodes(search_config.go.nodes.map(|n| n as u64))
+            .depth(depth)
+            .nodes(nodes)
             .hard_time(hard_time)
             .soft_time(soft_time)
             .start_now();
@@ -161,24 +258,57 @@ impl LazySMP {
         let stop_flag = time_man.raw_stop_flag();
         self.search_stop_flag = Some(stop_flag);
 
+        let outcome = Arc::new(SearchOutcome::default());
+        self.search_outcome = Some(Arc::clone(&outcome));
+
+        // Analysis and mate searches are always searched.
+        let instant_move = (!search_config.go.infinite && mate.is_none())
+            .then(|| self.instant_move(&search_config))
+            .flatten();
+
         // Estimate a very rough evaluation result for the first aspiration window
         // TODO: maybe the main search thread should do this?
         // TODO: Or maybe test, if this is even worth it at all?
-        let (estimate_eval, estimate_bestmove) = self.presearch(&search_config);
+        let (estimate_eval, mut estimate_bestmove) = match instant_move {
+            Some((m, score)) => (score, m),
+            None => self.presearch(&search_config),
+        };
+
+        // If the Syzygy tables cover the root, they already know the result. The search only has to find the best
+        // of the moves, that keep it, and doesn't probe the tables anymore.
+        let root_probe = instant_move
+            .is_none()
+            .then(|| search_config.syzygy.as_ref()?.probe_root(&mut self.board.clone()))
+            .flatten();
+        if let Some(root_probe) = &root_probe {
+            if !root_probe.best_moves.contains(&estimate_bestmove) {
+                estimate_bestmove = root_probe.best_moves[0];
+            }
+        }
 
         // Create the Message for telling the threads to start searching
         let message = Message::StartSearch(Arc::new(ThreadConfig {
-            report_mode: search_config.report_mode,
+            listener: Arc::clone(&search_config.listener),
             time_man: time_man.clone(),
             estimate_eval,
             estimate_bestmove,
-            allow_null_pruning: search_config.allow_null_pruning,
+            // Null move pruning can hide mates in zugzwang positions.
+            allow_null_pruning: search_config.allow_null_pruning && mate.is_none(),
+            params: search_config.params,
+            tablebases: search_config.tablebases.clone(),
+            syzygy: search_config.syzygy.clone().filter(|_| root_probe.is_none()),
+            syzygy_probe_depth: search_config.syzygy_probe_depth,
+            root_probe,
+            mate,
+            skill,
+            instant_move,
+            outcome: Arc::clone(&outcome),
         }));
 
         // Tell each thread to start searching
         self.bus.broadcast(message);
 
-        Ok(())
+        Ok(outcome)
     }
 
     /// Stops the search, if it is running. Otherwise nothing happens.
@@ -188,6 +318,25 @@ impl LazySMP {
         }
     }
 
+    /// Blocks until the last started search has finished and returns its final stats.
+    ///
+    /// Returns `None`, if no search was started since the last wait.
+    pub fn wait(&mut self) -> Option<SearchStats> {
+        Some(self.search_outcome.take()?.wait())
+    }
+
+    /// Like [`LazySMP::wait`], but gives up after the timeout. The search keeps running in this case
+    /// and can be waited for again.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<SearchStats> {
+        let stats = self.search_outcome.as_ref()?.wait_timeout(timeout);
+
+        if stats.is_some() {
+            self.search_outcome = None;
+        }
+
+        stats
+    }
+
     /// Is there currently a search running on the thread pool?
     pub fn is_search_running(&self) -> bool {
         // A search is running if:
@@ -199,14 +348,39 @@ impl LazySMP {
             .unwrap_or(false)
     }
 
-    fn presearch(&self, config: &SearchConfig) -> (Eval, ChessMove) {
+    /// Looks up a move in the book, or the best move and its score in the tablebases.
+    fn instant_move(&self, config: &SearchConfig) -> Option<(ChessMove, Eval)> {
+        let mut rng = config
+            .book_seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        let book_move = config
+            .book
+            .as_ref()
+            .and_then(|book| book.pick(&self.board, config.book_selection, &mut rng));
+
+        book_move.map(|m| (m, Eval::DRAW)).or_else(|| {
+            let (m, value) = config.tablebases.as_ref()?.best_move(&mut self.board.clone())?;
+            Some((m, value.eval(0)))
+        })
+    }
+
+    fn presearch(&mut self, config: &SearchConfig) -> (Eval, ChessMove) {
         let mut ctx = ABContext {
             time_man: Limits::new().start_now(),
             stats: SearchStats::default(),
             transposition_table: Arc::clone(&self.ttable),
             search_killers: Default::default(),
-            search_history: Default::default(),
+            histories: self.presearch_histories.take().unwrap_or_default(),
+            static_evals: Default::default(),
+            double_extensions: Default::default(),
+            pv_table: Default::default(),
+            mate_search: None,
+            excluded_root_moves: Default::default(),
             allow_null_pruning: config.allow_null_pruning,
+            params: config.params,
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: 0,
         };
 
         let score = alpha_beta(
@@ -217,9 +391,18 @@ impl LazySMP {
             &mut ctx,
             config.allow_null_pruning,
             false,
+            None,
         );
 
-        (score, self.ttable.load_move(self.board.position_key).unwrap())
+        // There is no move, if the position is already checkmate or stalemate.
+        let bestmove = self
+            .ttable
+            .load_move(self.board.position_key)
+            .filter(|m| self.board.is_pseudo_legal(*m))
+            .unwrap_or_default();
+        self.presearch_histories = Some(ctx.histories);
+
+        (score, bestmove)
     }
 }
 
@@ -237,70 +420,105 @@ impl Drop for LazySMP {
 
 fn search_thread(kind: ThreadKind, ttable: Arc<TranspositionTable>, mut rx: BusReader<Message>) {
     let mut board = Board::startpos();
+    let mut histories = ThreadHistories::default();
 
     loop {
         match rx.recv().unwrap() {
             Message::SetupBoard(new_board) => board = *new_board,
+            Message::NewGame => histories.clear(),
             Message::Quit => break,
             Message::StartSearch(config) => {
-                let ctx = ABContext {
+                // The root moves, that are skipped because of the Syzygy tables, count as hits as well.
+                let (excluded_root_moves, tbhits) = match &config.root_probe {
+                    Some(root_probe) => (
+                        root_probe.other_moves.iter().copied().collect(),
+                        (root_probe.best_moves.len() + root_probe.other_moves.len()) as u64,
+                    ),
+                    None => Default::default(),
+                };
+
+                let mut ctx = ABContext {
                     time_man: config.time_man.clone(),
-                    stats: SearchStats::default(),
+                    stats: SearchStats {
+                        tbhits,
+                        ..Default::default()
+                    },
                     transposition_table: Arc::clone(&ttable),
                     search_killers: Default::default(),
-                    search_history: Default::default(),
+                    histories,
+                    static_evals: Default::default(),
+                    double_extensions: Default::default(),
+                    pv_table: Default::default(),
+                    mate_search: config.mate,
+                    excluded_root_moves,
                     allow_null_pruning: config.allow_null_pruning,
+                    params: config.params,
+                    tablebases: config.tablebases.clone(),
+                    syzygy: config.syzygy.clone(),
+                    syzygy_probe_depth: config.syzygy_probe_depth,
                 };
 
                 match kind {
-                    ThreadKind::Main => search_as_main(
-                        config.estimate_eval,
-                        config.estimate_bestmove,
-                        config.report_mode,
-                        &mut board,
-                        ctx,
-                    ),
+                    ThreadKind::Main => search_as_main(&config, &mut board, &mut ctx),
+                    // A limited search runs on the main thread only, so its results are reproducible.
+                    ThreadKind::Supporter(_) if config.skill.is_some() => {}
+                    ThreadKind::Supporter(_) if config.instant_move.is_some() => {}
                     ThreadKind::Supporter(thread_num) => {
-                        search_as_supporter(thread_num, config.estimate_eval, &mut board, ctx)
+                        search_as_supporter(thread_num, config.estimate_eval, &mut board, &mut ctx)
                     }
                 }
+
+                histories = ctx.histories;
             }
         };
     }
 }
 
-fn search_as_main(
-    estimate_eval: Eval,
-    estimate_bestmove: ChessMove,
-    report_mode: ReportMode,
-    board: &mut Board,
-    mut ctx: ABContext,
-) {
-    let mut iterative_deepening = IterativeDeepening::new(estimate_eval, 1);
-
-    while let Some(stats) = iterative_deepening.next_depth(board, &mut ctx) {
-        report_after_depth(report_mode, stats);
+fn search_as_main(config: &ThreadConfig, board: &mut Board, ctx: &mut ABContext) {
+    let listener = config.listener.as_ref();
+    let estimate_bestmove = config.estimate_bestmove;
+
+    if let Some((m, score)) = config.instant_move {
+        ctx.stats.bestmove = m;
+        ctx.stats.score = score;
+        ctx.stats.pv = smallvec![m];
+    } else if let Some(skill) = config.skill {
+        search_with_skill(skill, listener, board, ctx);
+    } else {
+        let mut iterative_deepening = IterativeDeepening::new(config.estimate_eval, 1);
+
+        while let Some(stats) = iterative_deepening.next_depth(board, ctx) {
+            let mate_proven = ctx.proves_mate(stats.score);
+            listener.depth_finished(&stats);
+
+            // A mate search is done, as soon as it found a short enough mate.
+            if mate_proven {
+                break;
+            }
+        }
     }
 
     // Under extreme time pressure, the iterative deepening can be stopped very early.
     // In this case, the stats do not contain a valid bestmove.
     // Return the estimated bestmove instead.
-    if ctx.stats.bestmove.is_nomove() {
+    if ctx.stats.bestmove.is_nomove() && !estimate_bestmove.is_nomove() {
         ctx.stats.bestmove = estimate_bestmove;
         ctx.stats.pv = ctx
             .transposition_table
-            .pv(board, ctx.stats.depth as usize, Some(estimate_bestmove));
+            .pv(board, ctx.stats.depth as usize, &[estimate_bestmove]);
     }
 
-    report_after_search(report_mode, ctx.stats);
+    // Stop first, so the listener can start the next search right away.
     ctx.time_man.force_stop();
+    listener.search_finished(&ctx.stats);
+    config.outcome.finish(std::mem::take(&mut ctx.stats));
 }
 
-fn search_as_supporter(thread_num: u32, expected_eval: Eval, board: &mut Board, mut ctx: ABContext) {
+fn search_as_supporter(thread_num: u32, expected_eval: Eval, board: &mut Board, ctx: &mut ABContext) {
     let start_depth = u16::min(thread_num as u16 + 1, ctx.time_man.depth_limit());
     loop {
         let mut iterative_deepening = IterativeDeepening::new(expected_eval, start_depth);
-        while iterative_deepening.next_depth(board, &mut ctx).is_some() {}
+        while iterative_deepening.next_depth(board, ctx).is_some() {}
 
         if ctx.time_man.stop(&ctx.stats, false) {
             break;
@@ -328,3 +546,59 @@ pub fn calculate_time_limit(go: &uci::Go, color: Color) -> Option<(Duration, Dur
         (hard_limit, soft_limit)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{LazySMPSetup, SearchConfig};
+    use mattis_uci as uci;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    fn config(go: uci::Go) -> SearchConfig {
+        SearchConfig {
+            listener: Arc::new(()),
+            allow_null_pruning: true,
+            params: Default::default(),
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: 0,
+            skill: None,
+            book: None,
+            book_selection: Default::default(),
+            book_seed: None,
+            go,
+        }
+    }
+
+    #[test]
+    fn wait_for_search() {
+        let mut lazy_smp = LazySMPSetup::default().thread_count(2).ttable_size(1).create();
+        assert_eq!(lazy_smp.wait(), None);
+
+        let go = uci::Go {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let outcome = lazy_smp.start_search(config(go)).unwrap();
+        let stats = lazy_smp.wait().unwrap();
+        assert_eq!(stats.depth, 4);
+        assert!(!stats.bestmove.is_nomove());
+        assert!(!lazy_smp.is_search_running());
+        assert_eq!(lazy_smp.wait(), None);
+        assert_eq!(outcome.try_stats(), Some(stats));
+
+        // An unlimited search only finishes after it has been stopped. Until then, the wait sleeps for the whole
+        // timeout instead of polling the search.
+        lazy_smp.start_search(config(uci::Go::default())).unwrap();
+        let start = Instant::now();
+        assert_eq!(lazy_smp.wait_timeout(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(lazy_smp.is_search_running());
+
+        lazy_smp.stop_search();
+        assert!(lazy_smp.wait_timeout(Duration::from_secs(10)).is_some());
+        assert_eq!(lazy_smp.wait(), None);
+    }
+}
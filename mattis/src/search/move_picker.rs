@@ -0,0 +1,370 @@
+use super::ABContext;
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+};
+use mattis_types::{Piece, PieceType, Square};
+use smallvec::SmallVec;
+
+type ScoredMoveList = SmallVec<[(ChessMove, i32); 64]>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Stage {
+    TTMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Killer1,
+    Killer2,
+    CounterMove,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+/// Hands out the moves of a position one by one, roughly sorted from best to worst.
+///
+/// Moves are generated lazily in stages, so a beta cutoff by an early move saves the work for all later stages:
+/// 1. The move from the transposition table
+/// 2. Captures, that don't lose material according to the static exchange evaluation (MVV and capture history order)
+/// 3. The killer moves and the counter move
+/// 4. All other quiet moves (butterfly and continuation history order)
+/// 5. Captures, that lose material
+///
+/// Moves from outside the move generator (hash, killer and counter moves) are checked for pseudo-legality,
+/// and no move is returned twice. Like the move generator, the picker returns pseudo-legal moves.
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: ChessMove,
+    killers: [ChessMove; 2],
+    counter_move: ChessMove,
+    previous_moves: [Option<(Piece, Square)>; 2],
+    captures_only: bool,
+    skip_bad_captures: bool,
+    moves: ScoredMoveList,
+    bad_captures: ScoredMoveList,
+}
+
+impl MovePicker {
+    /// Creates a move picker for all moves in the main search.
+    ///
+    /// `previous_moves` are the last two moves (see [`previous_move`]), which index the continuation histories.
+    pub fn new(
+        board: &Board,
+        tt_move: Option<ChessMove>,
+        killers: [ChessMove; 2],
+        counter_move: ChessMove,
+        previous_moves: [Option<(Piece, Square)>; 2],
+    ) -> Self {
+        let tt_move = tt_move.filter(|m| board.is_pseudo_legal(*m)).unwrap_or_default();
+
+        Self {
+            stage: Stage::TTMove,
+            tt_move,
+            killers,
+            counter_move,
+            previous_moves,
+            captures_only: false,
+            skip_bad_captures: false,
+            moves: ScoredMoveList::new(),
+            bad_captures: ScoredMoveList::new(),
+        }
+    }
+
+    /// Creates a move picker, that only returns captures (good captures first).
+    pub fn new_captures() -> Self {
+        Self {
+            stage: Stage::GenerateCaptures,
+            tt_move: ChessMove::default(),
+            killers: [ChessMove::default(); 2],
+            counter_move: ChessMove::default(),
+            previous_moves: [None; 2],
+            captures_only: true,
+            skip_bad_captures: false,
+            moves: ScoredMoveList::new(),
+            bad_captures: ScoredMoveList::new(),
+        }
+    }
+
+    /// Creates a move picker, that only returns the captures, which don't lose material.
+    pub fn new_good_captures() -> Self {
+        Self {
+            skip_bad_captures: true,
+            ..Self::new_captures()
+        }
+    }
+
+    /// Skips all remaining quiet moves (except for the hash move, if it wasn't returned yet).
+    pub fn skip_quiets(&mut self) {
+        if (Stage::Killer1..=Stage::Quiets).contains(&self.stage) {
+            self.stage = Stage::BadCaptures;
+        }
+
+        self.captures_only = true;
+    }
+
+    pub fn next(&mut self, board: &Board, ctx: &ABContext) -> Option<ChessMove> {
+        loop {
+            match self.stage {
+                Stage::TTMove => {
+                    self.stage = Stage::GenerateCaptures;
+
+                    if !self.tt_move.is_nomove() {
+                        return Some(self.tt_move);
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    let mut list = MoveList::new();
+                    board.generate_capture_moves(&mut list);
+
+                    self.moves.clear();
+                    self.moves.extend(
+                        list.into_iter()
+                            .filter(|m| *m != self.tt_move)
+                            .map(|m| (m, score_capture(m, board, ctx))),
+                    );
+
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    let Some((m, score)) = pop_best(&mut self.moves) else {
+                        self.stage = if self.skip_bad_captures {
+                            Stage::Done
+                        } else if self.captures_only {
+                            Stage::BadCaptures
+                        } else {
+                            Stage::Killer1
+                        };
+                        continue;
+                    };
+
+                    // Captures, that lose material, are delayed until all quiet moves are done.
+                    if board.see(m, 0) {
+                        return Some(m);
+                    }
+
+                    self.bad_captures.push((m, score));
+                }
+                Stage::Killer1 => {
+                    self.stage = Stage::Killer2;
+
+                    if self.is_new_quiet(self.killers[0], board) {
+                        return Some(self.killers[0]);
+                    }
+                }
+                Stage::Killer2 => {
+                    self.stage = Stage::CounterMove;
+
+                    if self.killers[1] != self.killers[0] && self.is_new_quiet(self.killers[1], board) {
+                        return Some(self.killers[1]);
+                    }
+                }
+                Stage::CounterMove => {
+                    self.stage = Stage::GenerateQuiets;
+
+                    let counter_move = self.counter_move;
+                    if !self.killers.contains(&counter_move) && self.is_new_quiet(counter_move, board) {
+                        return Some(counter_move);
+                    }
+                }
+                Stage::GenerateQuiets => {
+                    let mut list = MoveList::new();
+                    board.generate_quiet_moves(&mut list);
+
+                    self.moves.clear();
+                    self.moves.extend(
+                        list.into_iter()
+                            .filter(|m| *m != self.tt_move && !self.killers.contains(m) && *m != self.counter_move)
+                            .map(|m| (m, score_quiet(m, board, ctx, &self.previous_moves))),
+                    );
+
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => {
+                    if let Some((m, _)) = pop_best(&mut self.moves) {
+                        return Some(m);
+                    }
+
+                    self.stage = Stage::BadCaptures;
+                }
+                Stage::BadCaptures => {
+                    if let Some((m, _)) = pop_best(&mut self.bad_captures) {
+                        return Some(m);
+                    }
+
+                    self.stage = Stage::Done;
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Checks, if a move from the killer or counter move tables can be played and wasn't returned yet.
+    fn is_new_quiet(&self, m: ChessMove, board: &Board) -> bool {
+        m != self.tt_move && !m.is_capture() && board.is_pseudo_legal(m)
+    }
+}
+
+/// Removes and returns the move with the highest score.
+fn pop_best(moves: &mut ScoredMoveList) -> Option<(ChessMove, i32)> {
+    let (idx, _) = moves.iter().enumerate().max_by_key(|(_, (_, score))| *score)?;
+    Some(moves.swap_remove(idx))
+}
+
+fn score_capture(m: ChessMove, board: &Board, ctx: &ABContext) -> i32 {
+    let victim = if m.is_en_passant() {
+        PieceType::Pawn
+    } else {
+        // Safety: A capture always captures a piece.
+        unsafe { board.pieces[m.end()].unwrap_unchecked().piece_type() }
+    };
+
+    // Safety: A chess move always moves a piece.
+    let attacker = unsafe { board.pieces[m.start()].unwrap_unchecked() };
+    let promotion = m.promoted().map_or(0, |p| p.value() as i32);
+
+    // The most valuable victim comes first. The capture history replaces the least valuable attacker rule
+    // and may also reorder captures of victims with similar values.
+    (victim.value() as i32 + promotion) * 16 + ctx.histories.capture_history.entry(attacker, m.end(), victim) / 8
+}
+
+fn score_quiet(m: ChessMove, board: &Board, ctx: &ABContext, previous_moves: &[Option<(Piece, Square)>; 2]) -> i32 {
+    // Safety: A chess move always moves a piece.
+    let piece = unsafe { board.pieces[m.start()].unwrap_unchecked() };
+
+    // Quiet promotions (mostly to a queen) are usually very good moves.
+    let promotion = match m.promoted() {
+        Some(PieceType::Queen) => 1_000_000,
+        Some(_) => -1_000_000,
+        None => 0,
+    };
+
+    promotion + ctx.quiet_history(previous_moves, piece, m.end())
+}
+
+/// Returns the piece and end square of the move `plies_ago` plies back (1 or 2), which index the counter move and
+/// continuation history tables. Null moves have no previous move.
+pub fn previous_move(board: &Board, plies_ago: usize) -> Option<(Piece, Square)> {
+    debug_assert!((1..=2).contains(&plies_ago));
+
+    let index = board.history.len().checked_sub(plies_ago)?;
+    let previous = board.history[index].move16;
+
+    if previous.is_nomove() {
+        return None;
+    }
+
+    // The opponent may have captured the piece since. It belonged to the side, that is to move now.
+    if let Some(entry) = board.history.get(index + 1) {
+        if entry.move16.end() == previous.end() && !entry.move16.is_en_passant() {
+            if let Some(captured) = entry.captured {
+                return Some((Piece::new(captured, board.color), previous.end()));
+            }
+        }
+    }
+
+    board.pieces[previous.end()].map(|piece| (piece, previous.end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MovePicker;
+    use crate::{
+        board::{movegen::MoveList, Board},
+        chess_move::ChessMove,
+        hashtable::TranspositionTable,
+        search::{ABContext, SearchStats},
+        time_man::Limits,
+    };
+    use std::sync::Arc;
+
+    fn context() -> ABContext {
+        ABContext {
+            time_man: Limits::new().start_now(),
+            stats: SearchStats::default(),
+            transposition_table: Arc::new(TranspositionTable::new(1)),
+            search_killers: Default::default(),
+            histories: Default::default(),
+            static_evals: Default::default(),
+            double_extensions: Default::default(),
+            pv_table: Default::default(),
+            mate_search: None,
+            excluded_root_moves: Default::default(),
+            allow_null_pruning: true,
+            params: Default::default(),
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: 0,
+        }
+    }
+
+    #[test]
+    fn picks_every_move_exactly_once() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/1ppppp1p/8/p4Pp1/8/8/PPPPP1PP/RNBQKBNR w KQkq g6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+
+        let ctx = context();
+
+        for fen in fens {
+            let board = Board::from_fen(fen).unwrap();
+            let mut expected = MoveList::new();
+            board.generate_all_moves(&mut expected);
+
+            // Use moves from the position as hash, killer and counter moves, and one move, that is not possible.
+            let invalid = ChessMove::build()
+                .start(mattis_types::Square::A4)
+                .end(mattis_types::Square::A5)
+                .finish();
+            let killers = [expected[expected.len() - 1], invalid];
+            let mut picker = MovePicker::new(
+                &board,
+                Some(expected[3]),
+                killers,
+                expected[expected.len() / 2],
+                [None; 2],
+            );
+
+            let mut picked = MoveList::new();
+            while let Some(m) = picker.next(&board, &ctx) {
+                picked.push(m);
+            }
+
+            assert_eq!(picked[0], expected[3]);
+            assert_eq!(picked.len(), expected.len());
+            assert!(expected.iter().all(|m| picked.contains(m)));
+        }
+    }
+
+    #[test]
+    fn captures_only() {
+        let board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let ctx = context();
+
+        let mut expected = MoveList::new();
+        board.generate_capture_moves(&mut expected);
+
+        let mut picker = MovePicker::new_captures();
+        let mut picked = MoveList::new();
+        while let Some(m) = picker.next(&board, &ctx) {
+            picked.push(m);
+        }
+
+        assert_eq!(picked.len(), expected.len());
+
+        // Losing captures come last.
+        let first_bad = picked.iter().position(|m| !board.see(*m, 0)).unwrap_or(picked.len());
+        assert!(picked[first_bad..].iter().all(|m| !board.see(*m, 0)));
+
+        let mut picker = MovePicker::new_good_captures();
+        let mut good = MoveList::new();
+        while let Some(m) = picker.next(&board, &ctx) {
+            good.push(m);
+        }
+
+        assert_eq!(good.as_slice(), &picked[..first_bad]);
+    }
+}
@@ -0,0 +1,103 @@
+use mattis_uci::{OptionKind, UciOption};
+
+macro_rules! search_params {
+    ($($(#[doc = $doc:literal])* $name:ident: $default:literal, $min:literal..=$max:literal;)*) => {
+        /// Tunable margins and limits of the search.
+        ///
+        /// Every parameter is also available as a spin option named after the field, so the values can be tuned
+        /// (e.g. with SPSA) through `setoption` without recompiling.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct SearchParams {
+            $($(#[doc = $doc])* pub $name: i16,)*
+        }
+
+        impl Default for SearchParams {
+            fn default() -> Self {
+                Self {
+                    $($name: $default,)*
+                }
+            }
+        }
+
+        impl SearchParams {
+            /// Returns a spin option for every parameter with its default value and range.
+            pub fn uci_options() -> Vec<UciOption> {
+                vec![$(UciOption {
+                    name: stringify!($name).to_string(),
+                    kind: OptionKind::Spin {
+                        default: $default,
+                        min: $min,
+                        max: $max,
+                    },
+                },)*]
+            }
+
+            /// Sets the parameter with the given (case insensitive) name.
+            ///
+            /// Returns false, if there is no such parameter or the value is out of range.
+            pub fn set(&mut self, name: &str, value: i64) -> bool {
+                $(if name.eq_ignore_ascii_case(stringify!($name)) {
+                    if !($min..=$max).contains(&value) {
+                        return false;
+                    }
+
+                    self.$name = value as i16;
+                    return true;
+                })*
+
+                false
+            }
+        }
+    };
+}
+
+search_params! {
+    /// Maximum depth for reverse futility pruning
+    rfp_max_depth: 8, 0..=16;
+    /// Reverse futility margin per ply of depth
+    rfp_margin: 80, 0..=400;
+    /// Maximum depth for razoring
+    razoring_max_depth: 3, 0..=8;
+    /// Razoring margin per ply of depth
+    razoring_margin: 250, 0..=1000;
+    /// Maximum depth for futility pruning of quiet moves
+    futility_max_depth: 6, 0..=16;
+    /// Base futility margin
+    futility_base: 100, 0..=500;
+    /// Futility margin per ply of depth
+    futility_margin: 90, 0..=400;
+    /// Maximum depth for late move pruning
+    lmp_max_depth: 6, 0..=16;
+    /// Number of quiet moves, that are always searched by late move pruning
+    lmp_base: 3, 0..=20;
+    /// Number of additional quiet moves per squared depth, that are searched by late move pruning
+    lmp_factor: 2, 0..=10;
+    /// Delta pruning margin in the quiescence search
+    delta_margin: 200, 0..=1000;
+    /// Minimum depth for singular extensions
+    singular_min_depth: 8, 0..=20;
+    /// Margin per ply of depth, by which the hash move has to beat all other moves to be singular
+    singular_margin: 2, 0..=20;
+    /// Margin below the singular beta, that allows a double extension
+    double_extension_margin: 20, 0..=200;
+    /// Maximum number of double extensions on a single search path
+    double_extension_limit: 6, 0..=16;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchParams;
+
+    #[test]
+    fn set_params_by_name() {
+        let mut params = SearchParams::default();
+
+        assert!(params.set("RFP_MARGIN", 120));
+        assert_eq!(params.rfp_margin, 120);
+
+        assert!(!params.set("rfp_margin", 10_000));
+        assert!(!params.set("unknown", 1));
+        assert_eq!(params.rfp_margin, 120);
+        assert_eq!(SearchParams::uci_options().len(), 15);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::chess_move::ChessMove;
+
+/// Lines beyond this ply are not collected.
+const MAX_PV_PLY: usize = 128;
+
+/// Collects the principal variation during the search.
+///
+/// Every ply has its own line. Whenever a move raises alpha, the line of that ply becomes the move followed by
+/// the line of the next ply. The line at ply 0 is the principal variation of the root.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PvTable {
+    moves: Box<[[ChessMove; MAX_PV_PLY]]>,
+    lengths: [usize; MAX_PV_PLY],
+}
+
+impl PvTable {
+    /// Starts a new (empty) line at the given ply. Has to be called, when the search enters a node.
+    pub fn clear(&mut self, ply: usize) {
+        if ply < MAX_PV_PLY {
+            self.lengths[ply] = 0;
+        }
+    }
+
+    /// Replaces the line at the given ply with `m` followed by the line of the next ply.
+    pub fn update(&mut self, ply: usize, m: ChessMove) {
+        if ply >= MAX_PV_PLY {
+            return;
+        }
+
+        // The line of a ply starts at the index of the ply, so it always fits behind the move of the previous ply.
+        let (current, next) = self.moves.split_at_mut(ply + 1);
+        let line = &mut current[ply];
+        line[ply] = m;
+        let mut length = 1;
+
+        if let (Some(next), Some(&next_length)) = (next.first(), self.lengths.get(ply + 1)) {
+            let range = ply + 1..ply + 1 + next_length;
+            line[range.clone()].copy_from_slice(&next[range]);
+            length += next_length;
+        }
+
+        self.lengths[ply] = length;
+    }
+
+    /// Returns the line at the given ply.
+    pub fn line(&self, ply: usize) -> &[ChessMove] {
+        &self.moves[ply][ply..ply + self.lengths[ply]]
+    }
+}
+
+impl Default for PvTable {
+    fn default() -> Self {
+        Self {
+            moves: vec![[ChessMove::default(); MAX_PV_PLY]; MAX_PV_PLY].into_boxed_slice(),
+            lengths: [0; MAX_PV_PLY],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PvTable, MAX_PV_PLY};
+    use crate::chess_move::ChessMove;
+    use mattis_types::Square;
+
+    #[test]
+    fn collect_lines() {
+        let moves: Vec<_> = [Square::A1, Square::B1, Square::C1, Square::D1]
+            .into_iter()
+            .map(|square| ChessMove::build().start(square).end(Square::H8).finish())
+            .collect();
+        let mut table = PvTable::default();
+
+        for ply in 0..4 {
+            table.clear(ply);
+        }
+
+        table.update(2, moves[2]);
+        table.update(1, moves[1]);
+        table.update(0, moves[0]);
+        assert_eq!(table.line(0), &moves[..3]);
+
+        // A new line at ply 1 doesn't change the line at ply 0, until it is copied.
+        table.clear(1);
+        table.clear(2);
+        table.update(1, moves[3]);
+        assert_eq!(table.line(0), &moves[..3]);
+        table.update(0, moves[2]);
+        assert_eq!(table.line(0), &[moves[2], moves[3]]);
+
+        // Lines at the last plies are truncated.
+        table.clear(MAX_PV_PLY - 1);
+        table.update(MAX_PV_PLY - 1, moves[0]);
+        table.update(MAX_PV_PLY - 2, moves[1]);
+        assert_eq!(table.line(MAX_PV_PLY - 2), &[moves[1], moves[0]]);
+
+        table.clear(MAX_PV_PLY);
+        table.update(MAX_PV_PLY, moves[2]);
+        assert_eq!(table.line(MAX_PV_PLY - 1), &[moves[0]]);
+    }
+}
@@ -0,0 +1,45 @@
+use ctor::ctor;
+
+const MAX_DEPTH: usize = 64;
+const MAX_MOVES: usize = 64;
+
+/// Returns the base late move reduction for the `move_index`-th legal move (starting at 1) at the given depth.
+///
+/// Later moves at higher depths are reduced more, following `0.75 + ln(depth) * ln(move_index) / 2.25`.
+pub fn late_move_reduction(depth: u16, move_index: usize) -> i16 {
+    let depth = (depth as usize).min(MAX_DEPTH - 1);
+    let move_index = move_index.min(MAX_MOVES - 1);
+    REDUCTIONS[depth][move_index] as i16
+}
+
+#[ctor]
+static REDUCTIONS: [[u8; MAX_MOVES]; MAX_DEPTH] = {
+    let mut table = [[0; MAX_MOVES]; MAX_DEPTH];
+
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (move_index, entry) in row.iter_mut().enumerate().skip(1) {
+            let reduction = 0.75 + (depth as f64).ln() * (move_index as f64).ln() / 2.25;
+            *entry = reduction as u8;
+        }
+    }
+
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::late_move_reduction;
+
+    #[test]
+    fn reductions_grow_with_depth_and_move_index() {
+        assert_eq!(late_move_reduction(1, 1), 0);
+        assert_eq!(late_move_reduction(3, 2), 1);
+
+        for depth in 1..100 {
+            for move_index in 1..100 {
+                assert!(late_move_reduction(depth, move_index) <= late_move_reduction(depth + 1, move_index));
+                assert!(late_move_reduction(depth, move_index) <= late_move_reduction(depth, move_index + 1));
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use super::{ABContext, IterativeDeepening, SearchListener, SearchStats};
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+};
+use mattis_types::{Eval, PieceType};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// The highest skill level. It disables the strength limit.
+pub const MAX_SKILL_LEVEL: u8 = 20;
+
+/// Rough calibration of skill levels to Elo ratings, sorted by Elo.
+/// Elo ratings between two points are interpolated.
+const ELO_CALIBRATION: [(u16, u8); 6] = [(800, 0), (1100, 3), (1400, 6), (1700, 10), (2000, 14), (2400, 19)];
+
+/// The strength settings, that can be changed through UCI options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Strength {
+    pub skill_level: u8,      // `Skill Level`
+    pub limit_strength: bool, // `UCI_LimitStrength`
+    pub elo: u16,             // `UCI_Elo`
+}
+
+impl Strength {
+    pub const MIN_ELO: u16 = ELO_CALIBRATION[0].0;
+    pub const MAX_ELO: u16 = ELO_CALIBRATION[ELO_CALIBRATION.len() - 1].0;
+
+    /// Returns the skill, that limits the search. There is none at full strength.
+    ///
+    /// `UCI_LimitStrength` takes precedence over the skill level.
+    pub fn skill(&self) -> Option<Skill> {
+        let skill = if self.limit_strength {
+            Skill::from_elo(self.elo)
+        } else {
+            Skill::new(self.skill_level)
+        };
+
+        (skill.level < MAX_SKILL_LEVEL).then_some(skill)
+    }
+}
+
+impl Default for Strength {
+    fn default() -> Self {
+        Self {
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: 1500,
+        }
+    }
+}
+
+/// Weakens the search for lower skill levels.
+///
+/// The search is limited in depth and nodes and looks at several principal variations. Instead of the best move,
+/// it picks one of them randomly, preferring better moves. The lower the level, the more likely it picks a worse move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Skill {
+    level: u8,
+    seed: Option<u64>,
+}
+
+impl Skill {
+    /// Number of principal variations, from which the move is picked
+    const MULTI_PV: usize = 4;
+
+    pub fn new(level: u8) -> Self {
+        Self {
+            level: level.min(MAX_SKILL_LEVEL),
+            seed: None,
+        }
+    }
+
+    /// Returns the skill level, that matches the given Elo rating.
+    pub fn from_elo(elo: u16) -> Self {
+        let elo = elo.clamp(Strength::MIN_ELO, Strength::MAX_ELO);
+
+        let level = ELO_CALIBRATION
+            .windows(2)
+            .find(|points| elo <= points[1].0)
+            .map_or(MAX_SKILL_LEVEL, |points| {
+                let ((elo_low, level_low), (elo_high, level_high)) = (points[0], points[1]);
+                let progress = (elo - elo_low) as f64 / (elo_high - elo_low) as f64;
+                level_low + (progress * (level_high - level_low) as f64).round() as u8
+            });
+
+        Self::new(level)
+    }
+
+    /// Makes the random move choice reproducible.
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn depth_limit(&self) -> u16 {
+        1 + self.level as u16
+    }
+
+    pub fn node_limit(&self) -> u64 {
+        200 << (self.level / 2)
+    }
+
+    fn rng(&self) -> StdRng {
+        self.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+    }
+
+    /// Picks a move from the candidates, which have to be sorted from best to worst.
+    ///
+    /// Every candidate gets a random bonus, that is larger for lower levels and for candidates, that are further
+    /// behind the best one. The candidate with the highest score including its bonus is picked.
+    pub fn pick(&self, candidates: &[(ChessMove, Eval)], rng: &mut impl Rng) -> Option<ChessMove> {
+        let &(_, best) = candidates.first()?;
+        let &(_, worst) = candidates.last()?;
+
+        let weakness = 120 - 2 * self.level as i32;
+        let spread = (best.inner() as i32 - worst.inner() as i32).min(PieceType::Pawn.value() as i32);
+
+        candidates
+            .iter()
+            .map(|&(m, score)| {
+                let behind = best.inner() as i32 - score.inner() as i32;
+                let bonus = (weakness * behind + spread * rng.gen_range(0..weakness)) / 128;
+                (m, score.inner() as i32 + bonus)
+            })
+            .max_by_key(|&(_, score)| score)
+            .map(|(m, _)| m)
+    }
+}
+
+/// Runs the search for a limited skill.
+///
+/// Every depth searches the best moves one after another, by excluding the moves found before at the root.
+/// The move is picked from the candidates of the last completed depth.
+pub(super) fn search_with_skill(skill: Skill, listener: &dyn SearchListener, board: &mut Board, ctx: &mut ABContext) {
+    let mut legal_moves = MoveList::new();
+    board.generate_legal_moves(&mut legal_moves);
+    let multi_pv = Skill::MULTI_PV.min(legal_moves.len());
+
+    // Root moves, which are already excluded (like by the tablebases), stay excluded.
+    let excluded_moves = ctx.excluded_root_moves.len();
+    let mut candidates: Vec<(ChessMove, Eval)> = Vec::with_capacity(multi_pv);
+    let mut best_stats: Option<SearchStats> = None;
+
+    'deepening: for depth in 1..=ctx.time_man.depth_limit() {
+        let mut depth_candidates = Vec::with_capacity(multi_pv);
+        let mut depth_stats = None;
+        ctx.excluded_root_moves.truncate(excluded_moves);
+
+        for pv_index in 0..multi_pv {
+            let expected_eval = candidates.get(pv_index).map_or(Eval::DRAW, |&(_, score)| score);
+            let mut iterative_deepening = IterativeDeepening::new(expected_eval, depth);
+
+            let Some(stats) = iterative_deepening.next_depth(board, ctx) else {
+                // A partial result is better than nothing after the first depth.
+                if candidates.is_empty() {
+                    candidates = depth_candidates;
+                }
+                break 'deepening;
+            };
+
+            if stats.bestmove.is_nomove() || ctx.excluded_root_moves.contains(&stats.bestmove) {
+                break;
+            }
+
+            depth_candidates.push((stats.bestmove, stats.score));
+            ctx.excluded_root_moves.push(stats.bestmove);
+            depth_stats.get_or_insert(stats);
+        }
+
+        // The searches of later moves can produce better scores, than the first one, because they use
+        // different aspiration windows and table entries.
+        depth_candidates.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        candidates = depth_candidates;
+
+        if let Some(stats) = depth_stats {
+            listener.depth_finished(&stats);
+            best_stats = Some(stats);
+        }
+    }
+
+    ctx.excluded_root_moves.truncate(excluded_moves);
+
+    let mut stats = best_stats.unwrap_or_else(|| ctx.stats.clone());
+    if let Some(m) = skill.pick(&candidates, &mut skill.rng()) {
+        if m != stats.bestmove {
+            stats.score = candidates
+                .iter()
+                .find(|(c, _)| *c == m)
+                .map_or(stats.score, |&(_, s)| s);
+            stats.bestmove = m;
+            stats.pv = ctx.transposition_table.pv(board, 1, &[m]);
+        }
+    }
+
+    ctx.stats = stats;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_with_skill, Skill, Strength, MAX_SKILL_LEVEL};
+    use crate::{
+        board::{movegen::MoveList, Board},
+        chess_move::ChessMove,
+        hashtable::TranspositionTable,
+        search::{ABContext, SearchStats},
+        time_man::Limits,
+    };
+    use mattis_types::{Eval, Square};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::Arc;
+
+    fn limited_search(fen: &str, skill: Skill) -> SearchStats {
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut ctx = ABContext {
+            time_man: Limits::new()
+                .depth(Some(skill.depth_limit()))
+                .nodes(Some(skill.node_limit()))
+                .start_now(),
+            stats: SearchStats::default(),
+            transposition_table: Arc::new(TranspositionTable::new(1)),
+            search_killers: Default::default(),
+            histories: Default::default(),
+            static_evals: Default::default(),
+            double_extensions: Default::default(),
+            pv_table: Default::default(),
+            mate_search: None,
+            excluded_root_moves: Default::default(),
+            allow_null_pruning: true,
+            params: Default::default(),
+            tablebases: None,
+            syzygy: None,
+            syzygy_probe_depth: 0,
+        };
+
+        search_with_skill(skill, &(), &mut board, &mut ctx);
+        ctx.stats
+    }
+
+    #[test]
+    fn elo_calibration() {
+        assert_eq!(Skill::from_elo(0).level(), 0);
+        assert_eq!(Skill::from_elo(Strength::MIN_ELO).level(), 0);
+        assert_eq!(Skill::from_elo(1400).level(), 6);
+        assert_eq!(Skill::from_elo(1550).level(), 8);
+        assert_eq!(Skill::from_elo(Strength::MAX_ELO).level(), 19);
+        assert_eq!(Skill::from_elo(u16::MAX).level(), 19);
+
+        // Weaker ratings never result in higher levels.
+        for elo in Strength::MIN_ELO..Strength::MAX_ELO {
+            assert!(Skill::from_elo(elo).level() <= Skill::from_elo(elo + 1).level());
+        }
+    }
+
+    #[test]
+    fn full_strength_has_no_skill() {
+        let mut strength = Strength::default();
+        assert_eq!(strength.skill(), None);
+
+        strength.skill_level = 5;
+        assert_eq!(strength.skill().map(|skill| skill.level()), Some(5));
+
+        strength.limit_strength = true;
+        strength.elo = Strength::MAX_ELO;
+        assert_eq!(strength.skill().map(|skill| skill.level()), Some(19));
+
+        strength.skill_level = MAX_SKILL_LEVEL;
+        strength.elo = 1100;
+        assert_eq!(strength.skill().map(|skill| skill.level()), Some(3));
+    }
+
+    #[test]
+    fn pick_weighted_moves() {
+        let candidates: Vec<_> = [Square::A3, Square::B3, Square::C3, Square::D3]
+            .into_iter()
+            .zip([50, 30, -20, -300])
+            .map(|(square, score)| {
+                let m = ChessMove::build().start(Square::A2).end(square).finish();
+                (m, Eval::from(score))
+            })
+            .collect();
+
+        let count_best = |level: u8| {
+            let mut rng = StdRng::seed_from_u64(7);
+            (0..1000)
+                .filter(|_| Skill::new(level).pick(&candidates, &mut rng) == Some(candidates[0].0))
+                .count()
+        };
+
+        // Lower levels pick the best move less often.
+        assert!(count_best(0) < count_best(10));
+        assert!(count_best(10) < count_best(19));
+
+        // High levels never pick a move, that is far behind.
+        let mut rng = StdRng::seed_from_u64(7);
+        assert!((0..1000).all(|_| Skill::new(19).pick(&candidates, &mut rng) != Some(candidates[3].0)));
+
+        // The same seed always picks the same moves.
+        let picks = |seed| {
+            let skill = Skill::new(3).seed(seed);
+            let mut rng = skill.rng();
+            (0..20).map(|_| skill.pick(&candidates, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(1), picks(1));
+
+        assert_eq!(Skill::new(0).pick(&[], &mut StdRng::seed_from_u64(0)), None);
+    }
+
+    #[test]
+    fn limited_search_is_reproducible() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        for level in [0, 4, 8] {
+            let skill = Skill::new(level).seed(42);
+            let first = limited_search(fen, skill);
+
+            assert!(legal_moves.contains(&first.bestmove));
+            assert!(first.depth <= skill.depth_limit());
+            assert_eq!(limited_search(fen, skill).bestmove, first.bestmove);
+        }
+
+        // A position with a single legal move
+        let stats = limited_search("7k/8/8/8/8/8/6q1/7K w - - 0 1", Skill::new(0).seed(1));
+        assert_eq!(format!("{}", stats.bestmove.display_smith()), "h1g2");
+    }
+}
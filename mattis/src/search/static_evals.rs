@@ -0,0 +1,41 @@
+use mattis_types::Eval;
+
+/// The static evaluation of every position on the current search path, indexed by ply.
+/// Positions in check have no static evaluation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SearchStaticEvals(Box<[Option<Eval>]>);
+
+impl SearchStaticEvals {
+    pub fn new(size: usize) -> Self {
+        Self(vec![None; size].into_boxed_slice())
+    }
+
+    pub fn get(&self, ply: usize) -> Option<Eval> {
+        self.0[ply]
+    }
+
+    pub fn set(&mut self, ply: usize, eval: Option<Eval>) {
+        self.0[ply] = eval;
+    }
+
+    /// A position is improving, if its static evaluation is better than the one of our last position two plies ago.
+    /// If we were in check back then, we compare with the position four plies ago instead.
+    pub fn is_improving(&self, ply: usize) -> bool {
+        let Some(eval) = self.get(ply) else {
+            return false;
+        };
+
+        let previous = ply
+            .checked_sub(2)
+            .and_then(|p| self.get(p))
+            .or_else(|| ply.checked_sub(4).and_then(|p| self.get(p)));
+
+        previous.is_none_or(|previous| eval > previous)
+    }
+}
+
+impl Default for SearchStaticEvals {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
@@ -0,0 +1,638 @@
+//! Probing of Syzygy endgame tablebases.
+//!
+//! Syzygy tables come in two kinds: WDL tables (`.rtbw`) tell, whether a position is won, drawn or lost, and DTZ
+//! tables (`.rtbz`) store the distance to the next zeroing move (a capture or a pawn move), which resets the fifty
+//! move counter. Wins, that can't be forced before the fifty move rule applies, are called cursed wins, and the
+//! corresponding losses blessed losses.
+//!
+//! The tables don't store the values of all positions: If the side to move has a winning capture, the stored value
+//! is arbitrary. So the captures are always searched before the table is probed. Positions with castling rights are
+//! not covered, en passant captures are searched like any other capture.
+//!
+//! The WDL tables are probed within the search right after zeroing moves, where the fifty move counter doesn't
+//! matter. At the root, the DTZ tables pick the moves, that keep the best result under the fifty move rule.
+
+mod encoding;
+mod table;
+#[cfg(test)]
+mod writer;
+
+use self::{
+    encoding::{Material, MAX_PIECES},
+    table::{ProbeFailure, Table, TableKind},
+};
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+};
+use mattis_types::{CastlePerms, Eval, Piece, PieceType};
+use smallvec::SmallVec;
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    ops::Neg,
+    path::PathBuf,
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// The score of a won position. It is below the mate scores, because the tables don't tell the distance to mate.
+const TB_WIN: i16 = 20_000;
+
+/// Ranks the root moves by their DTZ. It is larger than any sum of DTZ and fifty move counter.
+const MAX_DTZ: i32 = 1 << 18;
+
+#[derive(Debug, Error)]
+pub enum SyzygyError {
+    #[error("could not read the tablebase directory: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The result of a position for the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss, // A loss, which is saved by the fifty move rule
+    Draw,
+    CursedWin, // A win, which is spoiled by the fifty move rule
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            -2 => Some(Self::Loss),
+            -1 => Some(Self::BlessedLoss),
+            0 => Some(Self::Draw),
+            1 => Some(Self::CursedWin),
+            2 => Some(Self::Win),
+            _ => None,
+        }
+    }
+
+    fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    /// Converts the result into a score at the given distance from the root. Cursed wins and blessed losses are
+    /// scored just slightly apart from a draw, so they are still preferred over a real draw.
+    pub fn eval(self, ply: usize) -> Eval {
+        let win = Eval::from(TB_WIN - ply.min(u8::MAX as usize) as i16);
+
+        match self {
+            Self::Win => win,
+            Self::Loss => -win,
+            _ => Eval::from(2 * self.value() as i16),
+        }
+    }
+
+    /// The DTZ of a position with this result, right before the zeroing move is made.
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Self::Win => 1,
+            Self::CursedWin => 101,
+            Self::Draw => 0,
+            Self::BlessedLoss => -101,
+            Self::Loss => -1,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::Loss => Self::Win,
+            Self::BlessedLoss => Self::CursedWin,
+            Self::Draw => Self::Draw,
+            Self::CursedWin => Self::BlessedLoss,
+            Self::Win => Self::Loss,
+        }
+    }
+}
+
+/// The root moves, ranked by the DTZ tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootProbe {
+    pub best_moves: Vec<ChessMove>, // The moves, that keep the best result under the fifty move rule
+    pub other_moves: Vec<ChessMove>,
+    pub wdl: Wdl, // The result of the best moves, considering the fifty move counter of the root
+}
+
+/// All Syzygy tables found in a set of directories. The files are only opened, once they are probed.
+#[derive(Debug, Default)]
+pub struct Syzygy {
+    wdl: HashMap<u64, Arc<Table>>, // Indexed by the key of the material and of the mirrored material
+    dtz: HashMap<u64, Arc<Table>>,
+    max_pieces: usize, // The most pieces of any WDL table
+}
+
+impl Syzygy {
+    /// Finds the tables in the directories, which are separated like the `PATH` variable (`:` or `;` on Windows).
+    pub fn load(paths: &str) -> Result<Self, SyzygyError> {
+        let mut syzygy = Self::default();
+
+        for dir in std::env::split_paths(paths) {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let kind = match path.extension().and_then(|extension| extension.to_str()) {
+                    Some(extension) if extension == TableKind::Wdl.extension() => TableKind::Wdl,
+                    Some(extension) if extension == TableKind::Dtz.extension() => TableKind::Dtz,
+                    _ => continue,
+                };
+
+                let material = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok());
+                if let Some(material) = material {
+                    syzygy.insert(kind, material, path);
+                }
+            }
+        }
+
+        Ok(syzygy)
+    }
+
+    /// Adds a table, unless there already is one of the same material. Tables from earlier directories win.
+    fn insert(&mut self, kind: TableKind, material: Material, path: PathBuf) {
+        let tables = match kind {
+            TableKind::Wdl => &mut self.wdl,
+            TableKind::Dtz => &mut self.dtz,
+        };
+        if tables.contains_key(&material.key()) {
+            return;
+        }
+
+        let table = Arc::new(Table::new(kind, material, path));
+        tables.insert(material.mirrored().key(), Arc::clone(&table));
+        tables.insert(material.key(), table);
+
+        if kind == TableKind::Wdl {
+            self.max_pieces = self.max_pieces.max(material.piece_count());
+        }
+    }
+
+    /// The most pieces (including the kings) of any WDL table.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// The number of WDL tables.
+    pub fn len(&self) -> usize {
+        // Every table is stored twice, unless both sides have the same pieces.
+        self.wdl.values().map(Arc::as_ptr).collect::<HashSet<_>>().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wdl.is_empty()
+    }
+
+    /// Checks, if the tables could cover the position.
+    fn covers(&self, board: &Board) -> bool {
+        board.bb_all.bit_count() as usize <= self.max_pieces && board.castle_perms == CastlePerms::NONE
+    }
+
+    /// Returns the result of the position for the side to move, ignoring the fifty move counter.
+    ///
+    /// Fails, if the position or one of the positions after a capture isn't covered by the tables.
+    pub fn probe_wdl(&self, board: &mut Board) -> Option<Wdl> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        self.search_wdl(board, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Returns the DTZ of the position in plies, positive if the side to move wins and negative if it loses. A DTZ
+    /// of 0 is a draw. Cursed wins and blessed losses have a DTZ beyond 100.
+    ///
+    /// The DTZ can be off by one ply, because some tables store it in moves.
+    pub fn probe_dtz(&self, board: &mut Board) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        self.dtz(board)
+    }
+
+    /// Ranks the legal moves of the root by their DTZ and returns the best ones. A won position is only
+    /// converted, if the next zeroing move can be reached before the fifty move rule draws the game. A lost position
+    /// is defended by the moves, that reach the fifty move rule, if there are any.
+    ///
+    /// Fails, if the position isn't covered by the tables or has no legal moves.
+    pub fn probe_root(&self, board: &mut Board) -> Option<RootProbe> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        let fifty_move = board.fifty_move as i32;
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        let mut ranked_moves = Vec::with_capacity(legal_moves.len());
+        for m in legal_moves {
+            board.make_move(m);
+
+            // A mating move wins, even if it reaches the fifty move rule.
+            let rank = if board.in_check() && !has_legal_moves(board) {
+                Some(MAX_DTZ)
+            } else if board.fifty_move == 0 {
+                // The DTZ of a zeroing move only depends on the result.
+                self.search_wdl(board, false)
+                    .map(|(wdl, _)| root_rank((-wdl).dtz_before_zeroing(), fifty_move))
+            } else if board.is_repetition() || board.fifty_move >= 100 {
+                Some(0)
+            } else {
+                self.dtz(board).map(|dtz| root_rank(-dtz - dtz.signum(), fifty_move))
+            };
+
+            board.take_move();
+            ranked_moves.push((m, rank?));
+        }
+
+        let best_rank = ranked_moves.iter().map(|&(_, rank)| rank).max()?;
+        let (best_moves, other_moves) = ranked_moves.into_iter().partition(|&(_, rank)| rank == best_rank);
+        let moves = |ranked: Vec<(ChessMove, i32)>| ranked.into_iter().map(|(m, _)| m).collect();
+
+        let bound = MAX_DTZ / 2 - 100;
+        let wdl = match best_rank {
+            rank if rank >= bound => Wdl::Win,
+            rank if rank > 0 => Wdl::CursedWin,
+            0 => Wdl::Draw,
+            rank if rank > -bound => Wdl::BlessedLoss,
+            _ => Wdl::Loss,
+        };
+
+        Some(RootProbe {
+            best_moves: moves(best_moves),
+            other_moves: moves(other_moves),
+            wdl,
+        })
+    }
+
+    /// Finds the result of the position by searching the captures (and with `zeroing_moves` the pawn moves as
+    /// well) and probing the table. Also tells, if a zeroing move is the best move, because the DTZ tables don't
+    /// store a useful value in this case.
+    fn search_wdl(&self, board: &mut Board, zeroing_moves: bool) -> Option<(Wdl, bool)> {
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        let mut best = Wdl::Loss;
+        let mut searched_moves = 0;
+        for &m in &legal_moves {
+            let zeroing = m.is_capture() || (zeroing_moves && is_pawn_move(board, m));
+            if !zeroing {
+                continue;
+            }
+
+            searched_moves += 1;
+            board.make_move(m);
+            let wdl = self.search_wdl(board, false);
+            board.take_move();
+
+            let wdl = -wdl?.0;
+            if wdl == Wdl::Win {
+                return Some((wdl, true));
+            }
+            best = best.max(wdl);
+        }
+
+        // If all moves were searched, the value of the table could be wrong, like with en passant captures.
+        let searched_all = searched_moves > 0 && searched_moves == legal_moves.len();
+        let wdl = match searched_all {
+            true => best,
+            false => Wdl::from_value(self.probe_table(board, TableKind::Wdl, Wdl::Draw).ok()?)?,
+        };
+
+        match best >= wdl {
+            true => Some((best, best > Wdl::Draw || searched_all)),
+            false => Some((wdl, false)),
+        }
+    }
+
+    fn dtz(&self, board: &mut Board) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search_wdl(board, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        } else if zeroing_best {
+            return Some(wdl.dtz_before_zeroing());
+        }
+
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        match self.probe_table(board, TableKind::Dtz, wdl) {
+            Ok(dtz) => return Some((dtz + 100 * cursed as i32) * wdl.value().signum()),
+            Err(ProbeFailure::Unavailable) => return None,
+            Err(ProbeFailure::ChangeStm) => {}
+        }
+
+        // The table only stores the positions of the other side to move, so we look one ply ahead and pick the
+        // move with the shortest DTZ, that keeps the result.
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        let mut min_dtz = None;
+        for m in legal_moves {
+            // The DTZ of a zeroing move is taken from before the move. The result after the move still tells, if
+            // it keeps the win.
+            let zeroing = m.is_capture() || is_pawn_move(board, m);
+            board.make_move(m);
+            let dtz = match zeroing {
+                true => self.search_wdl(board, false).map(|(wdl, _)| -wdl.dtz_before_zeroing()),
+                false => self.dtz(board).map(|dtz| -dtz),
+            };
+            let mates = dtz == Some(1) && board.in_check() && !has_legal_moves(board);
+            board.take_move();
+
+            let mut dtz = dtz?;
+            if mates {
+                min_dtz = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz.signum() == wdl.value().signum() && min_dtz.is_none_or(|min_dtz| dtz < min_dtz) {
+                min_dtz = Some(dtz);
+            }
+        }
+
+        // Without legal moves, the position is checkmate.
+        Some(min_dtz.unwrap_or(-1))
+    }
+
+    /// Looks up the value of the position in the table of its material.
+    fn probe_table(&self, board: &Board, kind: TableKind, wdl: Wdl) -> Result<i32, ProbeFailure> {
+        // Two kings are always a draw, there is no table for them.
+        if board.bb_all.bit_count() == 2 {
+            return Ok(0);
+        }
+
+        let key = Material::from_board(board).key();
+        let tables = match kind {
+            TableKind::Wdl => &self.wdl,
+            TableKind::Dtz => &self.dtz,
+        };
+        let table = tables.get(&key).ok_or(ProbeFailure::Unavailable)?;
+
+        let pieces: SmallVec<[(Piece, u8); MAX_PIECES]> = board
+            .pieces
+            .iter()
+            .enumerate()
+            .filter_map(|(square, piece)| piece.map(|piece| (piece, square as u8)))
+            .collect();
+
+        table.probe(&pieces, board.color, key, wdl)
+    }
+}
+
+/// Ranks a root move by its DTZ. All moves, that keep a result under the fifty move rule, are ranked equally.
+/// Beyond that, the shortest wins and the longest losses are ranked higher.
+fn root_rank(dtz: i32, fifty_move: i32) -> i32 {
+    match dtz.signum() {
+        1 if dtz + fifty_move <= 99 => MAX_DTZ,
+        1 => MAX_DTZ / 2 - (dtz + fifty_move),
+        -1 if -dtz * 2 + fifty_move < 100 => -MAX_DTZ,
+        -1 => -MAX_DTZ / 2 + (-dtz + fifty_move),
+        _ => 0,
+    }
+}
+
+fn is_pawn_move(board: &Board, m: ChessMove) -> bool {
+    board.pieces[m.start()].is_some_and(|piece| piece.piece_type() == PieceType::Pawn)
+}
+
+fn has_legal_moves(board: &Board) -> bool {
+    let mut legal_moves = MoveList::new();
+    board.generate_legal_moves(&mut legal_moves);
+    !legal_moves.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        table::TableKind,
+        writer::{for_each_placement, write_table},
+        Syzygy, Wdl,
+    };
+    use crate::{
+        board::{movegen::MoveList, Board},
+        engine::Engine,
+        notation::SmithNotation,
+        search::lazy_smp::LazySMPSetup,
+        tablebase::{Tablebases, TbValue},
+    };
+    use mattis_types::{Color, Piece, PieceType};
+    use mattis_uci as uci;
+    use std::sync::{Arc, OnceLock};
+
+    /// Our own tables, which the Syzygy tables are generated from.
+    fn tablebases() -> &'static Tablebases {
+        static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+
+        TABLEBASES.get_or_init(|| {
+            let mut tablebases = Tablebases::new();
+            for material in ["KQK", "KRK", "KPK"] {
+                tablebases
+                    .generate(&material.parse().unwrap(), &mut |_| Ok(()))
+                    .unwrap();
+            }
+            tablebases
+        })
+    }
+
+    /// Writes the WDL tables of all 3 piece endgames with a queen, a rook or a pawn, and the DTZ tables of the
+    /// ones without pawns, which are the same as the distances to mate.
+    fn syzygy() -> &'static Arc<Syzygy> {
+        static SYZYGY: OnceLock<Arc<Syzygy>> = OnceLock::new();
+
+        SYZYGY.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("mattis-syzygy-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            let wdl = |board: &Board| {
+                tablebases().probe(board).map(|value| match value {
+                    TbValue::Win(_) => 4,
+                    TbValue::Draw => 2,
+                    TbValue::Loss(_) => 0,
+                })
+            };
+            let dtz = |board: &Board| {
+                tablebases().probe(board).map(|value| match value {
+                    TbValue::Win(plies) | TbValue::Loss(plies) => plies.saturating_sub(1) as u16,
+                    TbValue::Draw => 0,
+                })
+            };
+
+            for name in ["KQvK", "KRvK", "KPvK"] {
+                write_table(&dir, TableKind::Wdl, name, wdl);
+            }
+            for name in ["KQvK", "KRvK"] {
+                write_table(&dir, TableKind::Dtz, name, dtz);
+            }
+
+            let syzygy = Syzygy::load(dir.to_str().unwrap()).unwrap();
+            Arc::new(syzygy)
+        })
+    }
+
+    /// Sets up every `step`-th placement of the pieces with both sides to move and calls `f` with the legal ones.
+    fn for_each_position(pieces: &[Piece], step: usize, f: &mut impl FnMut(&mut Board)) {
+        let mut count = 0;
+        for_each_placement(pieces.len(), &mut |squares| {
+            count += 1;
+            let pawn_on_back_rank = pieces
+                .iter()
+                .zip(squares)
+                .any(|(piece, &square)| piece.piece_type() == PieceType::Pawn && !(8..56).contains(&square));
+            if count % step != 0 || pawn_on_back_rank {
+                return;
+            }
+
+            for color in [Color::White, Color::Black] {
+                let mut board = Board::new();
+                for (&piece, &square) in pieces.iter().zip(squares) {
+                    board.pieces[square as usize] = Some(piece);
+                }
+                board.color = color;
+                board.update_redundant_data();
+                board.position_key = board.generate_position_key();
+
+                if tablebases().probe(&board).is_some() {
+                    f(&mut board);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn wdl_matches_the_generated_tables() {
+        let materials = [
+            [Piece::WhiteKing, Piece::BlackKing, Piece::WhiteQueen],
+            [Piece::WhiteKing, Piece::BlackKing, Piece::BlackRook],
+            [Piece::WhiteKing, Piece::BlackKing, Piece::WhitePawn],
+            [Piece::WhiteKing, Piece::BlackKing, Piece::BlackPawn],
+        ];
+
+        for pieces in materials {
+            for_each_position(&pieces, 13, &mut |board| {
+                let expected = match tablebases().probe(board).unwrap() {
+                    TbValue::Win(_) => Wdl::Win,
+                    TbValue::Draw => Wdl::Draw,
+                    TbValue::Loss(_) => Wdl::Loss,
+                };
+                assert_eq!(syzygy().probe_wdl(board), Some(expected), "{}", board.as_fen());
+            });
+        }
+
+        // Two kings don't need a table, but a bishop does.
+        let mut board = Board::from_fen("8/8/4k3/8/8/2K5/8/8 w - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_wdl(&mut board), Some(Wdl::Draw));
+        let mut board = Board::from_fen("8/8/4k3/8/8/2KB4/8/8 w - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_wdl(&mut board), None);
+
+        // The rook can be captured right away.
+        let mut board = Board::from_fen("8/8/4k3/4r3/8/2K5/8/8 b - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_wdl(&mut board), Some(Wdl::Win));
+        board.color = Color::White;
+        assert_eq!(syzygy().probe_wdl(&mut board), Some(Wdl::Loss));
+    }
+
+    #[test]
+    fn dtz_matches_the_distance_to_mate() {
+        for pieces in [
+            [Piece::WhiteKing, Piece::BlackKing, Piece::WhiteRook],
+            [Piece::WhiteKing, Piece::BlackKing, Piece::BlackQueen],
+        ] {
+            // The positions of the losing side are only stored in the table of the other side to move.
+            for_each_position(&pieces, 61, &mut |board| {
+                let expected = match tablebases().probe(board).unwrap() {
+                    TbValue::Win(plies) => plies as i32,
+                    TbValue::Draw => 0,
+                    TbValue::Loss(plies) => -(plies.max(1) as i32),
+                };
+                assert_eq!(syzygy().probe_dtz(board), Some(expected), "{}", board.as_fen());
+            });
+        }
+
+        // Without a DTZ table, only draws and zeroing moves can be ranked.
+        let mut board = Board::from_fen("8/8/4k3/8/8/2K5/4P3/8 w - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_dtz(&mut board), None);
+        let mut board = Board::from_fen("8/8/4k3/8/8/2K5/4P3/8 b - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_dtz(&mut board), Some(0));
+        let mut board = Board::from_fen("4k3/8/3K4/3P4/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(syzygy().probe_dtz(&mut board), None);
+    }
+
+    #[test]
+    fn root_moves_keep_the_win() {
+        let dtz_after = |board: &mut Board, m| {
+            board.make_move(m);
+            let dtz = syzygy().probe_dtz(board).unwrap();
+            board.take_move();
+            dtz
+        };
+
+        // Every move, that doesn't give away the rook, wins.
+        let mut board = Board::from_fen("8/8/8/8/8/1k6/8/R3K3 w - - 0 1").unwrap();
+        let root = syzygy().probe_root(&mut board).unwrap();
+        assert_eq!(root.wdl, Wdl::Win);
+        assert!(root.best_moves.iter().all(|&m| dtz_after(&mut board, m) < 0));
+        assert!(root.other_moves.iter().all(|&m| dtz_after(&mut board, m) == 0));
+        assert!(!root.other_moves.is_empty());
+
+        // Close to the fifty move rule, only the fastest mates are left.
+        let mut board = Board::from_fen("8/8/8/8/8/1k6/8/R3K3 w - - 76 1").unwrap();
+        let root = syzygy().probe_root(&mut board).unwrap();
+        assert_eq!(root.wdl, Wdl::Win);
+        assert_eq!(root.best_moves, [board.find_move::<SmithNotation>("a1c1").unwrap()]);
+        assert!(root
+            .other_moves
+            .iter()
+            .all(|&m| 1 - dtz_after(&mut board, m) + 76 > 99 || dtz_after(&mut board, m) == 0));
+
+        // Too close to the fifty move rule, the win becomes a draw.
+        let mut board = Board::from_fen("8/8/8/8/8/1k6/8/R3K3 w - - 98 1").unwrap();
+        assert_eq!(syzygy().probe_root(&mut board).unwrap().wdl, Wdl::CursedWin);
+
+        // The mate is found immediately.
+        let mut board = Board::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 99 1").unwrap();
+        let root = syzygy().probe_root(&mut board).unwrap();
+        assert_eq!(root.wdl, Wdl::Win);
+        assert_eq!(root.best_moves, [board.find_move::<SmithNotation>("a1a8").unwrap()]);
+
+        // Positions with castling rights are not covered.
+        let mut board = Board::from_fen("8/8/8/8/8/1k6/8/R3K3 w Q - 0 1").unwrap();
+        assert_eq!(syzygy().probe_root(&mut board), None);
+    }
+
+    #[test]
+    fn search_with_syzygy() {
+        let mut engine = Engine::new(LazySMPSetup::default().thread_count(1).ttable_size(1));
+        engine.set_syzygy(Some(Arc::clone(syzygy())));
+
+        // Capturing the rook leads into a won table.
+        engine
+            .set_position("8/8/3k4/8/3r4/8/3Q4/3K4 w - - 0 1", &[] as &[&str])
+            .unwrap();
+        let go = uci::Go {
+            depth: Some(4),
+            ..Default::default()
+        };
+        let result = engine.search(go.clone(), ()).unwrap().wait();
+        assert_eq!(format!("{}", result.bestmove.display_smith()), "d2d4");
+        assert_eq!(result.score, Wdl::Win.eval(1));
+        assert!(result.tbhits > 0);
+
+        // At the root, only the winning moves are searched.
+        let fen = "8/8/8/8/8/1k6/8/R3K3 w - - 0 1";
+        engine.set_position(fen, &[] as &[&str]).unwrap();
+        let result = engine.search(go, ()).unwrap().wait();
+        let root = syzygy().probe_root(&mut Board::from_fen(fen).unwrap()).unwrap();
+        assert!(root.best_moves.contains(&result.bestmove));
+
+        let mut legal_moves = MoveList::new();
+        engine.board().generate_legal_moves(&mut legal_moves);
+        assert_eq!(root.best_moves.len() + root.other_moves.len(), legal_moves.len());
+    }
+}
@@ -0,0 +1,522 @@
+//! The indexing of positions within the Syzygy tables.
+//!
+//! The tables exploit the symmetries of the board: Without pawns, the leading piece is mirrored into the triangle
+//! a1-d1-d4, with pawns the leading pawn is mirrored onto the files a to d. Pieces of the same type and color are
+//! encoded together as a combination of squares, so their order doesn't matter. The pieces are split into groups,
+//! which are encoded one after another in an order chosen by the generator of the table.
+
+use crate::board::Board;
+use ctor::ctor;
+use mattis_types::{Color, Piece, PieceType};
+use smallvec::SmallVec;
+use std::{fmt::Display, str::FromStr};
+
+/// The most pieces (including both kings), a table can have.
+pub(super) const MAX_PIECES: usize = 7;
+
+/// Encodes a piece like the table files: the piece type from 1 (pawn) to 6 (king), plus 8 for black pieces.
+pub(super) fn piece_code(piece: Piece) -> u8 {
+    u8::from(piece.piece_type()) + 1 + 8 * u8::from(piece.color())
+}
+
+/// Decodes a piece of a table file.
+pub(super) fn decode_piece(code: u8) -> Option<Piece> {
+    let color = match code & 8 {
+        0 => Color::White,
+        _ => Color::Black,
+    };
+    let piece_type = *PieceType::ALL.get(((code & 7) as usize).checked_sub(1)?)?;
+    (code < 16).then_some(Piece::new(piece_type, color))
+}
+
+/// The material of a table like `KRvKP`. The pieces before the `v` are called white, even though a table also covers
+/// the positions with swapped colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) struct Material {
+    counts: [[u8; 6]; 2], // Indexed by color and piece type
+}
+
+impl Material {
+    pub(super) fn from_board(board: &Board) -> Self {
+        let mut counts = [[0; 6]; 2];
+        for piece in Piece::ALL {
+            counts[u8::from(piece.color()) as usize][u8::from(piece.piece_type()) as usize] =
+                board.count_pieces[piece] as u8;
+        }
+
+        Self { counts }
+    }
+
+    /// Identifies the material. Tables are looked up by this key and by the key of the mirrored material.
+    pub(super) fn key(&self) -> u64 {
+        self.counts
+            .iter()
+            .flatten()
+            .fold(0, |key, &count| (key << 4) | count as u64)
+    }
+
+    pub(super) fn mirrored(&self) -> Self {
+        Self {
+            counts: [self.counts[1], self.counts[0]],
+        }
+    }
+
+    pub(super) fn count(&self, piece: Piece) -> u8 {
+        self.counts[u8::from(piece.color()) as usize][u8::from(piece.piece_type()) as usize]
+    }
+
+    pub(super) fn piece_count(&self) -> usize {
+        self.counts.iter().flatten().map(|&count| count as usize).sum()
+    }
+
+    pub(super) fn has_pawns(&self) -> bool {
+        self.counts[0][0] + self.counts[1][0] > 0
+    }
+
+    /// Checks, if any side has exactly one piece of a kind (apart from the king).
+    pub(super) fn has_unique_pieces(&self) -> bool {
+        self.counts.iter().any(|counts| counts[..5].contains(&1))
+    }
+
+    /// Both sides have the same pieces.
+    pub(super) fn is_symmetric(&self) -> bool {
+        self.counts[0] == self.counts[1]
+    }
+
+    /// The pawns of the leading color and of the other color. The side with fewer pawns leads, because that
+    /// compresses better.
+    pub(super) fn pawn_counts(&self) -> [u8; 2] {
+        let (white, black) = (self.counts[0][0], self.counts[1][0]);
+        match black == 0 || (white > 0 && black >= white) {
+            true => [white, black],
+            false => [black, white],
+        }
+    }
+}
+
+impl FromStr for Material {
+    type Err = ();
+
+    /// Parses the material from the name of a table file, like `KRvKP`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (white, black) = s.split_once('v').ok_or(())?;
+        let mut counts = [[0; 6]; 2];
+
+        for (side, pieces) in counts.iter_mut().zip([white, black]) {
+            for c in pieces.chars() {
+                let piece = Piece::from_char(c)
+                    .filter(|piece| piece.color() == Color::White)
+                    .ok_or(())?;
+                side[u8::from(piece.piece_type()) as usize] += 1;
+            }
+
+            if side[5] != 1 {
+                return Err(());
+            }
+        }
+
+        let material = Self { counts };
+        (material.piece_count() <= MAX_PIECES).then_some(material).ok_or(())
+    }
+}
+
+impl Display for Material {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, color) in [Color::White, Color::Black].into_iter().enumerate() {
+            if i == 1 {
+                write!(f, "v")?;
+            }
+
+            for piece_type in PieceType::ALL.into_iter().rev() {
+                let piece = Piece::new(piece_type, Color::White);
+                let count = self.count(Piece::new(piece_type, color));
+                for _ in 0..count {
+                    write!(f, "{}", piece.to_char())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How the pieces of a subtable are grouped, and the factors of the groups in the index.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Groups {
+    pub(super) pieces: [u8; MAX_PIECES], // The piece codes in the order of the encoding
+    lens: [usize; MAX_PIECES + 1],       // The number of pieces per group, terminated by 0
+    factors: [u64; MAX_PIECES + 1],      // The last factor (after the last group) is the size of the subtable
+}
+
+impl Groups {
+    /// Groups the pieces. The leading group is formed by the pawns of the leading color, or without pawns by three
+    /// unique pieces or just the kings. All other groups are pieces of the same type and color. The first nibble
+    /// of `order` tells the position of the leading group in the encoding, the second one the position of the
+    /// remaining pawns.
+    pub(super) fn new(material: &Material, pieces: [u8; MAX_PIECES], order: [u8; 2], file: usize) -> Self {
+        let piece_count = material.piece_count();
+        let has_pawns = material.has_pawns();
+        let mut lens = [0; MAX_PIECES + 1];
+        let mut first_len: i32 = match (has_pawns, material.has_unique_pieces()) {
+            (true, _) => 0,
+            (false, true) => 3,
+            (false, false) => 2,
+        };
+
+        let mut n = 0;
+        lens[0] = 1;
+        for i in 1..piece_count {
+            first_len -= 1;
+            if first_len > 0 || pieces[i] == pieces[i - 1] {
+                lens[n] += 1;
+            } else {
+                n += 1;
+                lens[n] = 1;
+            }
+        }
+        n += 1;
+
+        let remaining_pawns = has_pawns && material.pawn_counts()[1] > 0;
+        let mut next = if remaining_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - lens[0] - if remaining_pawns { lens[1] } else { 0 };
+        let mut factors = [0; MAX_PIECES + 1];
+        let mut factor = 1_u64;
+
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                factors[0] = factor;
+                factor *= match (has_pawns, material.has_unique_pieces()) {
+                    (true, _) => INDEX_TABLES.lead_pawns_size[lens[0]][file],
+                    (false, true) => 31332,
+                    (false, false) => 462,
+                };
+            } else if k == order[1] {
+                factors[1] = factor;
+                factor *= INDEX_TABLES.binomial[lens[1]][48 - lens[0]];
+            } else {
+                factors[next] = factor;
+                factor *= INDEX_TABLES.binomial[lens[next]][free_squares];
+                free_squares -= lens[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        factors[n] = factor;
+
+        Self { pieces, lens, factors }
+    }
+
+    /// The number of positions in the subtable.
+    pub(super) fn size(&self) -> u64 {
+        let groups = self.lens.iter().position(|&len| len == 0).unwrap_or(MAX_PIECES);
+        self.factors[groups]
+    }
+
+    /// The index of the position within the subtable.
+    pub(super) fn index(&self, material: &Material, mut position: Position) -> u64 {
+        let tables = &*INDEX_TABLES;
+        let lead_pawns = position.lead_pawns;
+        let pieces = &mut position.pieces;
+
+        // Order the pieces like the encoding. Pieces of the same kind are interchangeable.
+        for i in lead_pawns..pieces.len().saturating_sub(1) {
+            if let Some(j) = (i + 1..pieces.len()).find(|&j| pieces[j].0 == self.pieces[i]) {
+                pieces.swap(i, j);
+            }
+        }
+
+        let mut squares: SmallVec<[u8; MAX_PIECES]> = pieces.iter().map(|&(_, square)| square).collect();
+
+        // Move the leading piece to the files a to d.
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+
+        let mut index = if lead_pawns > 0 {
+            let mut index = tables.lead_pawn_idx[lead_pawns][squares[0] as usize];
+            squares[1..lead_pawns].sort_by_key(|&square| tables.map_pawns[square as usize]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                index += tables.binomial[i][tables.map_pawns[square as usize] as usize];
+            }
+            index
+        } else {
+            // Without pawns, the leading piece is also moved below the fifth rank ...
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|square| *square ^= 56);
+            }
+
+            // ... and the first piece of the leading group, that is not on the a1-h8 diagonal, below it.
+            for i in 0..self.lens[0] {
+                match off_diagonal(squares[i]) {
+                    0 => continue,
+                    1.. => squares[i..]
+                        .iter_mut()
+                        .for_each(|square| *square = flip_diagonal(*square)),
+                    _ => {}
+                }
+                break;
+            }
+
+            if material.has_unique_pieces() {
+                unique_pieces_index(&squares)
+            } else {
+                let king = tables.map_a1d1d4[squares[0] as usize] as usize;
+                tables.map_kk[king][squares[1] as usize] as u64
+            }
+        };
+        index *= self.factors[0];
+
+        // The squares of the other groups skip the squares of the earlier groups.
+        let mut remaining_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+        let mut start = self.lens[0];
+        for group in 1..MAX_PIECES {
+            let len = self.lens[group];
+            if len == 0 {
+                break;
+            }
+
+            squares[start..start + len].sort_unstable();
+            let mut combination = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&other| square > other).count();
+                let free = square as usize - adjust - if remaining_pawns { 8 } else { 0 };
+                combination += tables.binomial[i + 1][free];
+            }
+
+            remaining_pawns = false;
+            index += combination * self.factors[group];
+            start += len;
+        }
+
+        index
+    }
+}
+
+/// Encodes the three unique pieces of the leading group, of which the first is in the triangle a1-d1-d4 and the
+/// first piece off the a1-h8 diagonal is below it.
+fn unique_pieces_index(squares: &[u8]) -> u64 {
+    let tables = &*INDEX_TABLES;
+    let [s0, s1, s2] = [squares[0], squares[1], squares[2]].map(u64::from);
+    let adjust1 = (s1 > s0) as u64;
+    let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+    let rank = |square: u64| square / 8;
+
+    if off_diagonal(squares[0]) != 0 {
+        (tables.map_a1d1d4[s0 as usize] as u64 * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    } else if off_diagonal(squares[1]) != 0 {
+        (6 * 63 + rank(s0) * 28 + tables.map_b1h1h7[s1 as usize] as u64) * 62 + s2 - adjust2
+    } else if off_diagonal(squares[2]) != 0 {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + rank(s0) * 7 * 28
+            + (rank(s1) - adjust1) * 28
+            + tables.map_b1h1h7[s2 as usize] as u64
+    } else {
+        6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)
+    }
+}
+
+/// A position from the point of view of a table. With pawns, the leading pawns come first and the one closest to
+/// the edge and to the first rank is the very first piece.
+#[derive(Debug, Clone)]
+pub(super) struct Position {
+    pub(super) side: usize, // The side to move: 0 for the side of the table, that is called white
+    pub(super) file: usize, // The file of the leading pawn (mirrored to the files a to d), or 0 without pawns
+    pieces: SmallVec<[(u8, u8); MAX_PIECES]>, // Piece codes and squares
+    lead_pawns: usize,
+}
+
+impl Position {
+    /// Flips the colors and mirrors the board vertically, if `flip` is set. `lead_pawn` is the code of the leading
+    /// pawns, if the table has pawns.
+    pub(super) fn new(pieces: &[(Piece, u8)], color: Color, flip: bool, lead_pawn: Option<u8>) -> Self {
+        let (flip_color, flip_square) = if flip { (8, 56) } else { (0, 0) };
+        let side = (flip as usize) ^ (color == Color::Black) as usize;
+        let (lead, rest): (SmallVec<[_; MAX_PIECES]>, SmallVec<[_; MAX_PIECES]>) = pieces
+            .iter()
+            .map(|&(piece, square)| (piece_code(piece) ^ flip_color, square ^ flip_square))
+            .partition(|&(code, _)| Some(code) == lead_pawn);
+
+        let mut pieces: SmallVec<[(u8, u8); MAX_PIECES]> = lead.into_iter().chain(rest).collect();
+        let lead_pawns = pieces.iter().filter(|&&(code, _)| Some(code) == lead_pawn).count();
+
+        let mut file = 0;
+        if lead_pawns > 0 {
+            let tables = &*INDEX_TABLES;
+            let first = (0..lead_pawns)
+                .max_by_key(|&i| tables.map_pawns[pieces[i].1 as usize])
+                .unwrap_or(0);
+            pieces.swap(0, first);
+
+            file = (pieces[0].1 % 8) as usize;
+            if file > 3 {
+                file = 7 - file;
+            }
+        }
+
+        Self {
+            side,
+            file,
+            pieces,
+            lead_pawns,
+        }
+    }
+}
+
+/// How far a square is above the a1-h8 diagonal (negative below).
+fn off_diagonal(square: u8) -> i8 {
+    (square / 8) as i8 - (square % 8) as i8
+}
+
+/// Mirrors a square at the a1-h8 diagonal.
+fn flip_diagonal(square: u8) -> u8 {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+struct IndexTables {
+    map_pawns: [u8; 64],               // Squares a2-h7 to 0..47, decreasing towards the center and up
+    map_b1h1h7: [u8; 64],              // Squares below the a1-h8 diagonal to 0..27
+    map_a1d1d4: [u8; 64],              // Squares in the triangle a1-d1-d4 to 0..9, the diagonal last
+    map_kk: [[u16; 64]; 10],           // The 462 legal placements of two kings, the first in the triangle
+    binomial: [[u64; 64]; MAX_PIECES], // Ways to choose k of n elements, indexed by k and n
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES], // Indexed by the number of leading pawns and the first one's square
+    lead_pawns_size: [[u64; 4]; MAX_PIECES], // Indexed by the number of leading pawns and the file
+}
+
+#[ctor]
+static INDEX_TABLES: IndexTables = {
+    let mut tables = IndexTables {
+        map_pawns: [0; 64],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        binomial: [[0; 64]; MAX_PIECES],
+        lead_pawn_idx: [[0; 64]; MAX_PIECES],
+        lead_pawns_size: [[0; 4]; MAX_PIECES],
+    };
+
+    for (code, square) in (0..64).filter(|&square| off_diagonal(square) < 0).enumerate() {
+        tables.map_b1h1h7[square as usize] = code as u8;
+    }
+
+    const TRIANGLE: [u8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27]; // a1, b1, c1, d1, b2, c2, d2, c3, d3, d4
+    let below = TRIANGLE.iter().filter(|&&square| off_diagonal(square) < 0);
+    let diagonal = TRIANGLE.iter().filter(|&&square| off_diagonal(square) == 0);
+    for (code, &square) in below.chain(diagonal).enumerate() {
+        tables.map_a1d1d4[square as usize] = code as u8;
+    }
+
+    // If the first king is on the diagonal, the second one is not above it. Both kings on the diagonal come last.
+    let adjacent = |a: u8, b: u8| (a / 8).abs_diff(b / 8) <= 1 && (a % 8).abs_diff(b % 8) <= 1;
+    let mut both_on_diagonal = Vec::new();
+    let mut code = 0;
+    for king in 0..10 {
+        // Squares outside the triangle are mapped to 0 as well, so b1 is picked explicitly.
+        for s1 in (0..28).filter(|&s1| tables.map_a1d1d4[s1 as usize] as usize == king && (king != 0 || s1 == 1)) {
+            for s2 in 0..64 {
+                if adjacent(s1, s2) || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                    continue;
+                } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                    both_on_diagonal.push((king, s2));
+                } else {
+                    tables.map_kk[king][s2 as usize] = code;
+                    code += 1;
+                }
+            }
+        }
+    }
+    for (king, s2) in both_on_diagonal {
+        tables.map_kk[king][s2 as usize] = code;
+        code += 1;
+    }
+
+    tables.binomial[0][0] = 1;
+    for n in 1..64 {
+        for k in 0..MAX_PIECES.min(n + 1) {
+            tables.binomial[k][n] = if k > 0 { tables.binomial[k - 1][n - 1] } else { 0 }
+                + if k < n { tables.binomial[k][n - 1] } else { 0 };
+        }
+    }
+
+    // The pawn closest to the edge and to the first rank leads, so the other pawns can only be on the squares with
+    // a lower value.
+    let mut available_squares = 48;
+    for lead_pawns in 1..MAX_PIECES - 1 {
+        for file in 0..4 {
+            let mut index = 0;
+            for rank in 1..7 {
+                let square = rank * 8 + file;
+                if lead_pawns == 1 {
+                    available_squares -= 1;
+                    tables.map_pawns[square] = available_squares;
+                    available_squares -= 1;
+                    tables.map_pawns[square ^ 7] = available_squares;
+                }
+                tables.lead_pawn_idx[lead_pawns][square] = index;
+                index += tables.binomial[lead_pawns - 1][tables.map_pawns[square] as usize];
+            }
+            tables.lead_pawns_size[lead_pawns][file] = index;
+        }
+    }
+
+    tables
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{Groups, Material, Position, INDEX_TABLES, MAX_PIECES};
+    use mattis_types::{Color, Piece};
+    use std::collections::HashSet;
+
+    #[test]
+    fn material_names() {
+        for name in ["KvK", "KQvK", "KRvKP", "KBNvK", "KPPvKP", "KQRBNvKP"] {
+            assert_eq!(name.parse::<Material>().unwrap().to_string(), name);
+        }
+
+        for invalid in ["", "KQK", "QvK", "KKvK", "KXvK", "kqvk", "KQRBNPvKP"] {
+            assert!(invalid.parse::<Material>().is_err(), "{invalid}");
+        }
+
+        let material: Material = "KRvKP".parse().unwrap();
+        assert_eq!(material.mirrored().to_string(), "KPvKR");
+        assert_ne!(material.key(), material.mirrored().key());
+        assert_eq!(material.pawn_counts(), [1, 0]);
+        assert!(material.has_pawns() && material.has_unique_pieces() && !material.is_symmetric());
+    }
+
+    #[test]
+    fn index_tables() {
+        let tables = &*INDEX_TABLES;
+        let king_codes: HashSet<_> = tables.map_kk.iter().flatten().copied().collect();
+        assert_eq!(king_codes.len(), 462);
+        assert_eq!(tables.map_pawns.iter().filter(|&&code| code > 0).count(), 47);
+        assert_eq!(tables.binomial[3][10], 120);
+        assert_eq!(tables.lead_pawns_size[1], [6, 6, 6, 6]);
+    }
+
+    #[test]
+    fn indices_cover_the_subtable() {
+        // Every placement of the three pieces gets an index within the table, and every index is used.
+        let material: Material = "KQvK".parse().unwrap();
+        let mut pieces = [0; MAX_PIECES];
+        pieces[..3].copy_from_slice(&[6, 14, 5]);
+        let groups = Groups::new(&material, pieces, [0, 0xF], 0);
+        assert_eq!(groups.size(), 31332);
+
+        let mut indices = HashSet::new();
+        for (a, b, c) in (0..64).flat_map(|a| (0..64).flat_map(move |b| (0..64).map(move |c| (a, b, c)))) {
+            if a == b || a == c || b == c {
+                continue;
+            }
+
+            let pieces = [(Piece::WhiteKing, a), (Piece::BlackKing, b), (Piece::WhiteQueen, c)];
+            let index = groups.index(&material, Position::new(&pieces, Color::White, false, None));
+            assert!(index < groups.size());
+            indices.insert(index);
+        }
+        assert_eq!(indices.len() as u64, groups.size());
+    }
+}
@@ -0,0 +1,520 @@
+//! Reading the Syzygy table files.
+//!
+//! A file starts with a magic number and the piece order of its subtables. A table has a subtable for each side to
+//! move (unless both sides have the same pieces, or it is a DTZ table) and, with pawns, for each file of the leading
+//! pawn. The values of a subtable are compressed by recursive pairing, where a symbol stands for a value or for a
+//! pair of other symbols, followed by a canonical Huffman code. The compressed data is split into blocks, which are
+//! found through a sparse index.
+//!
+//! The files are memory mapped on the first probe, so only the existence of the files is checked while loading.
+
+use super::{
+    encoding::{decode_piece, Groups, Material, Position, MAX_PIECES},
+    Wdl,
+};
+use mattis_types::{Color, Piece, PieceType};
+use memmap2::Mmap;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum TableKind {
+    Wdl, // Win, draw or loss
+    Dtz, // Distance to the next capture or pawn move (zeroing the fifty move counter)
+}
+
+impl TableKind {
+    pub(super) const fn extension(self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+
+    pub(super) const fn magic(self) -> [u8; 4] {
+        match self {
+            TableKind::Wdl => [0x71, 0xE8, 0x23, 0x5D],
+            TableKind::Dtz => [0xD7, 0x66, 0x0C, 0xA5],
+        }
+    }
+}
+
+// The flags of a subtable
+pub(super) const FLAG_STM: u8 = 1; // The side to move of a DTZ table
+const FLAG_MAPPED: u8 = 2; // DTZ values are mapped through the value map
+pub(super) const FLAG_WIN_PLIES: u8 = 4; // DTZ values of wins are stored in plies instead of moves
+pub(super) const FLAG_LOSS_PLIES: u8 = 8; // DTZ values of losses are stored in plies instead of moves
+const FLAG_WIDE: u8 = 16; // The value map has 16 bit entries
+const FLAG_SINGLE_VALUE: u8 = 128; // All positions of the subtable have the same value
+
+// The flags of a table
+pub(super) const FLAG_SPLIT: u8 = 1; // There are subtables for both sides to move
+pub(super) const FLAG_HAS_PAWNS: u8 = 2;
+
+/// Why a value could not be looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProbeFailure {
+    Unavailable, // The table is missing or invalid
+    ChangeStm,   // The DTZ table only stores the positions of the other side to move
+}
+
+#[derive(Debug)]
+pub(super) struct Table {
+    kind: TableKind,
+    material: Material,
+    path: PathBuf,
+    mapped: OnceLock<Option<MappedTable>>,
+}
+
+impl Table {
+    pub(super) fn new(kind: TableKind, material: Material, path: PathBuf) -> Self {
+        Self {
+            kind,
+            material,
+            path,
+            mapped: OnceLock::new(),
+        }
+    }
+
+    /// Looks up the value of the position, which has to have the material of the table (or the mirrored material).
+    ///
+    /// A WDL table returns the result from -2 (loss) to 2 (win), a DTZ table the DTZ in plies without a sign. The
+    /// result `wdl` of the position is needed to decode the DTZ.
+    pub(super) fn probe(&self, pieces: &[(Piece, u8)], color: Color, key: u64, wdl: Wdl) -> Result<i32, ProbeFailure> {
+        let mapped = self
+            .mapped
+            .get_or_init(|| MappedTable::open(&self.path, self.kind, &self.material))
+            .as_ref()
+            .ok_or(ProbeFailure::Unavailable)?;
+
+        // The tables store the positions with the stronger side as white. Tables with the same pieces on both sides
+        // only store the positions with white to move.
+        let flip = (self.material.is_symmetric() && color == Color::Black) || key != self.material.key();
+        let lead_pawn = self.material.has_pawns().then(|| mapped.subtables[0].groups.pieces[0]);
+        let position = Position::new(pieces, color, flip, lead_pawn);
+
+        let subtable = mapped.subtable(position.side, position.file);
+        let both_sides_stored = self.material.is_symmetric() && !self.material.has_pawns();
+        if self.kind == TableKind::Dtz && (subtable.flags & FLAG_STM) as usize != position.side && !both_sides_stored {
+            return Err(ProbeFailure::ChangeStm);
+        }
+
+        let file = position.file;
+        let index = subtable.groups.index(&self.material, position);
+        let value = mapped.decompress(subtable, index).ok_or(ProbeFailure::Unavailable)?;
+
+        match self.kind {
+            TableKind::Wdl => Ok(value as i32 - 2),
+            TableKind::Dtz => mapped.map_score(file, value, wdl).ok_or(ProbeFailure::Unavailable),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MappedTable {
+    bytes: Mmap,
+    sides: usize,
+    subtables: Vec<Subtable>, // Indexed by file and side to move
+    value_map: usize,         // Offset of the DTZ value map
+}
+
+/// The indexing and compression information of a subtable. The offsets point into the file.
+#[derive(Debug, Clone, Default)]
+struct Subtable {
+    flags: u8,
+    groups: Groups,
+    block_size: usize,         // Bytes per block of compressed data
+    span: u64,                 // Every `span` values there is an entry in the sparse index
+    block_count: usize,        // Blocks of compressed data
+    block_lengths: usize,      // Offset of the values per block (minus one) as u16
+    block_lengths_size: usize, // Entries of the block lengths, padded, so the sparse index can't point past them
+    sparse_index: usize,       // Offset of the sparse index entries (block as u32 and offset within it as u16)
+    sparse_index_size: usize,
+    min_symbol_len: u8,    // The shortest code length, or the value of a subtable with a single value
+    lowest_symbols: usize, // Offset of the lowest symbol per code length as u16
+    base: Vec<u64>,        // The lowest code of each length, left aligned
+    symbol_lens: Vec<u8>,  // The number of values (minus one) of each symbol
+    pairs: usize,          // Offset of the pairs of each symbol as two 12 bit values
+    data: usize,           // Offset of the compressed blocks
+    value_map_index: [u16; 4], // The value map of DTZ wins, losses, cursed wins and blessed losses
+}
+
+impl MappedTable {
+    fn open(path: &Path, kind: TableKind, material: &Material) -> Option<Self> {
+        let file = File::open(path).ok()?;
+
+        // Safety: The file is only read. Other processes could still modify it while it is mapped, but tablebase
+        // files are never changed once they are generated.
+        let bytes = unsafe { Mmap::map(&file) }.ok()?;
+        if bytes.get(..4)? != kind.magic() {
+            return None;
+        }
+
+        Self::parse(bytes, kind, material)
+    }
+
+    fn parse(bytes: Mmap, kind: TableKind, material: &Material) -> Option<Self> {
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 4,
+        };
+
+        let flags = reader.u8()?;
+        if (flags & FLAG_HAS_PAWNS != 0) != material.has_pawns() || (flags & FLAG_SPLIT != 0) == material.is_symmetric()
+        {
+            return None;
+        }
+
+        let split = kind == TableKind::Wdl && !material.is_symmetric();
+        let sides = if split { 2 } else { 1 };
+        let files = if material.has_pawns() { 4 } else { 1 };
+        let remaining_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+        let mut subtables = vec![Subtable::default(); files * sides];
+
+        for file in 0..files {
+            let first = reader.u8()?;
+            let second = if remaining_pawns { reader.u8()? } else { 0xFF };
+            let orders = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+
+            // The pieces of both sides to move are stored in the nibbles of the same bytes.
+            let [mut white, mut black] = [[0; MAX_PIECES]; 2];
+            for (white, black) in white.iter_mut().zip(&mut black).take(material.piece_count()) {
+                let byte = reader.u8()?;
+                (*white, *black) = (byte & 0xF, byte >> 4);
+            }
+            let pieces = [white, black];
+
+            for side in 0..sides {
+                if !pieces_match(&pieces[side], material) {
+                    return None;
+                }
+                subtables[file * sides + side].groups = Groups::new(material, pieces[side], orders[side], file);
+            }
+        }
+        reader.align(2);
+
+        for subtable in &mut subtables {
+            subtable.read_sizes(&mut reader)?;
+        }
+
+        let value_map = reader.offset;
+        if kind == TableKind::Dtz {
+            for file in 0..files {
+                let subtable = &mut subtables[file * sides];
+                if subtable.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+
+                for index in &mut subtable.value_map_index {
+                    if subtable.flags & FLAG_WIDE != 0 {
+                        reader.align(2);
+                        *index = ((reader.offset - value_map) / 2 + 1) as u16;
+                        reader.offset += 2 * reader.u16()? as usize;
+                    } else {
+                        *index = (reader.offset - value_map + 1) as u16;
+                        reader.offset += reader.u8()? as usize;
+                    }
+                }
+            }
+            reader.align(2);
+        }
+
+        for subtable in &mut subtables {
+            subtable.sparse_index = reader.offset;
+            reader.offset += subtable.sparse_index_size * 6;
+        }
+
+        for subtable in &mut subtables {
+            subtable.block_lengths = reader.offset;
+            reader.offset += subtable.block_lengths_size * 2;
+        }
+
+        for subtable in &mut subtables {
+            reader.align(64);
+            subtable.data = reader.offset;
+            reader.offset += subtable.block_count * subtable.block_size;
+        }
+
+        (reader.offset <= bytes.len()).then_some(Self {
+            bytes,
+            sides,
+            subtables,
+            value_map,
+        })
+    }
+
+    fn subtable(&self, side: usize, file: usize) -> &Subtable {
+        &self.subtables[file * self.sides + side % self.sides]
+    }
+
+    fn reader(&self, offset: usize) -> Reader<'_> {
+        Reader {
+            bytes: &self.bytes,
+            offset,
+        }
+    }
+
+    /// Finds the value at the index of the subtable.
+    fn decompress(&self, subtable: &Subtable, index: u64) -> Option<u16> {
+        if subtable.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(subtable.min_symbol_len as u16);
+        }
+
+        // The sparse index points to the block and the offset of every `span`-th value, starting in the middle of
+        // the first span. From there, we move to the block of our value.
+        let k = (index / subtable.span) as usize;
+        let mut reader = self.reader(subtable.sparse_index + 6 * k);
+        let mut block = reader.u32()? as usize;
+        let mut offset = reader.u16()? as i64 + (index % subtable.span) as i64 - (subtable.span / 2) as i64;
+
+        let block_length = |block: usize| {
+            (block < subtable.block_lengths_size)
+                .then(|| self.reader(subtable.block_lengths + 2 * block).u16())
+                .flatten()
+                .map(i64::from)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        // Read the Huffman codes of the block, until we reach the symbol, that contains our value.
+        let mut reader = self.reader(subtable.data + block * subtable.block_size);
+        let mut buffer = reader.u64_be()?;
+        let mut buffer_size = 64;
+        let min_len = subtable.min_symbol_len as usize;
+
+        let mut symbol = loop {
+            // Longer codes have smaller values, so we find the length of the next code by comparing with the base.
+            let len = subtable.base.iter().position(|&base| buffer >= base)?;
+            let code = (buffer - subtable.base[len]) >> (64 - len - min_len);
+            let symbol = code as usize + self.reader(subtable.lowest_symbols + 2 * len).u16()? as usize;
+            let symbol_len = *subtable.symbol_lens.get(symbol)? as i64;
+
+            if offset <= symbol_len {
+                break symbol;
+            }
+
+            offset -= symbol_len + 1;
+            buffer <<= len + min_len;
+            buffer_size -= len + min_len;
+
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (reader.u32_be().unwrap_or(0) as u64) << (64 - buffer_size);
+            }
+        };
+
+        // Expand the pairs of the symbol, until we reach the single value.
+        while subtable.symbol_lens[symbol] != 0 {
+            let (left, right) = self.pair(subtable, symbol)?;
+            let left_len = *subtable.symbol_lens.get(left)? as i64;
+
+            if offset <= left_len {
+                symbol = left;
+            } else {
+                offset -= left_len + 1;
+                symbol = right;
+            }
+        }
+
+        Some(self.pair(subtable, symbol)?.0 as u16)
+    }
+
+    /// The left and right symbol of a pair. A single value is stored as the left symbol.
+    fn pair(&self, subtable: &Subtable, symbol: usize) -> Option<(usize, usize)> {
+        read_pair(&self.bytes, subtable.pairs + 3 * symbol)
+    }
+
+    /// Converts a stored DTZ value into plies.
+    fn map_score(&self, file: usize, value: u16, wdl: Wdl) -> Option<i32> {
+        let subtable = self.subtable(0, file);
+        let mut value = value as usize;
+
+        if subtable.flags & FLAG_MAPPED != 0 {
+            let map = match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            };
+            let index = subtable.value_map_index[map] as usize + value;
+
+            value = match subtable.flags & FLAG_WIDE != 0 {
+                true => self.reader(self.value_map + 2 * index).u16()? as usize,
+                false => self.reader(self.value_map + index).u8()? as usize,
+            };
+        }
+
+        let in_moves = match wdl {
+            Wdl::Win => subtable.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => subtable.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+
+        Some(value as i32 + 1)
+    }
+}
+
+impl Subtable {
+    /// Reads the sizes of the compressed data and the Huffman code.
+    fn read_sizes(&mut self, reader: &mut Reader) -> Option<()> {
+        self.flags = reader.u8()?;
+
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            self.min_symbol_len = reader.u8()?;
+            return Some(());
+        }
+
+        self.block_size = 1 << reader.u8()?.min(31);
+        self.span = 1_u64 << reader.u8()?.min(63);
+        self.sparse_index_size = self.groups.size().div_ceil(self.span) as usize;
+        let padding = reader.u8()? as usize;
+        self.block_count = reader.u32()? as usize;
+        self.block_lengths_size = self.block_count + padding;
+
+        let max_symbol_len = reader.u8()?;
+        self.min_symbol_len = reader.u8()?;
+        if self.min_symbol_len == 0 || max_symbol_len < self.min_symbol_len || max_symbol_len > 32 {
+            return None;
+        }
+
+        // The codes of the same length are consecutive numbers, and longer codes have smaller values. We compute
+        // the lowest code of each length, left aligned to 64 bits, so a buffer of bits can be compared against it.
+        self.lowest_symbols = reader.offset;
+        let lens = (max_symbol_len - self.min_symbol_len + 1) as usize;
+        let mut lowest = Vec::with_capacity(lens);
+        for _ in 0..lens {
+            lowest.push(reader.u16()? as u64);
+        }
+
+        self.base = vec![0; lens];
+        for i in (0..lens - 1).rev() {
+            self.base[i] = self.base[i + 1].wrapping_add(lowest[i]).wrapping_sub(lowest[i + 1]) / 2;
+        }
+        for (i, base) in self.base.iter_mut().enumerate() {
+            *base <<= 64 - i - self.min_symbol_len as usize;
+        }
+
+        let symbols = reader.u16()? as usize;
+        self.pairs = reader.offset;
+        self.symbol_lens = symbol_lens(reader.bytes, self.pairs, symbols)?;
+        reader.offset += 3 * symbols + (symbols & 1);
+
+        Some(())
+    }
+}
+
+/// Computes the number of values (minus one) of every symbol by expanding its pairs.
+fn symbol_lens(bytes: &[u8], pairs: usize, symbols: usize) -> Option<Vec<u8>> {
+    let mut lens = vec![0_u8; symbols];
+    let mut visited = vec![false; symbols];
+
+    // The pairs form a tree, which is expanded with an explicit stack, so corrupt files can't overflow the call stack.
+    for symbol in 0..symbols {
+        let mut stack = vec![symbol];
+        while let Some(&top) = stack.last() {
+            if visited[top] {
+                stack.pop();
+                continue;
+            }
+
+            let (left, right) = read_pair(bytes, pairs + 3 * top)?;
+            if right == 0xFFF {
+                visited[top] = true;
+                stack.pop();
+                continue;
+            }
+
+            if left >= symbols || right >= symbols || stack.len() > symbols {
+                return None;
+            }
+
+            match (visited[left], visited[right]) {
+                (true, true) => {
+                    lens[top] = lens[left].wrapping_add(lens[right]).wrapping_add(1);
+                    visited[top] = true;
+                    stack.pop();
+                }
+                (false, _) => stack.push(left),
+                (true, false) => stack.push(right),
+            }
+        }
+    }
+
+    Some(lens)
+}
+
+fn read_pair(bytes: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let pair = bytes.get(offset..offset + 3)?;
+    let left = ((pair[1] as usize & 0xF) << 8) | pair[0] as usize;
+    let right = ((pair[2] as usize) << 4) | (pair[1] as usize >> 4);
+    Some((left, right))
+}
+
+/// Checks, that the pieces of a subtable are the pieces of the table, with the leading pawns first.
+fn pieces_match(codes: &[u8; MAX_PIECES], material: &Material) -> bool {
+    let count = material.piece_count();
+    let Some(pieces) = codes[..count]
+        .iter()
+        .map(|&code| decode_piece(code))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+
+    let counts_match = Piece::ALL
+        .into_iter()
+        .all(|piece| pieces.iter().filter(|&&p| p == piece).count() == material.count(piece) as usize);
+    counts_match && (!material.has_pawns() || pieces[0].piece_type() == PieceType::Pawn)
+}
+
+/// Reads little endian numbers (unless noted otherwise) from the file.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.bytes.get(self.offset..self.offset + N)?;
+        self.offset += N;
+        bytes.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take().map(u8::from_le_bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u32_be(&mut self) -> Option<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    fn u64_be(&mut self) -> Option<u64> {
+        self.take().map(u64::from_be_bytes)
+    }
+
+    /// Skips to the next multiple of `alignment` (a power of two) from the start of the file.
+    fn align(&mut self, alignment: usize) {
+        self.offset = self.offset.next_multiple_of(alignment);
+    }
+}
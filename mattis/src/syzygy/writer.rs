@@ -0,0 +1,245 @@
+//! Writes Syzygy tables for the tests.
+//!
+//! The values are not really compressed: Every value is a symbol of its own with a code of fixed length. So the
+//! files are much larger than the real ones, but they have the same format.
+
+use super::{
+    encoding::{piece_code, Groups, Material, Position, MAX_PIECES},
+    table::{TableKind, FLAG_HAS_PAWNS, FLAG_LOSS_PLIES, FLAG_SPLIT, FLAG_WIN_PLIES},
+};
+use crate::board::Board;
+use mattis_types::{Color, Piece, PieceType};
+use std::path::{Path, PathBuf};
+
+const BLOCK_SIZE_LOG: u8 = 6; // Blocks of 64 bytes
+const SPAN_LOG: u8 = 6; // A sparse index entry for every 64 values
+
+/// Writes the table of the material into the directory. `value` returns the value to store for a position, or
+/// `None` for illegal positions. WDL values range from 0 (loss) to 4 (win), DTZ values are stored in plies minus one.
+///
+/// DTZ tables only store the positions with the side of the table, that is called white, to move.
+pub(super) fn write_table(dir: &Path, kind: TableKind, name: &str, value: impl Fn(&Board) -> Option<u16>) -> PathBuf {
+    let material: Material = name.parse().unwrap();
+    let pieces = table_pieces(&material);
+    let lead_pawn = material.has_pawns().then_some(pieces[0]);
+    let remaining_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+    let order = [0, if remaining_pawns { 1 } else { 0xF }];
+
+    let sides = match kind {
+        TableKind::Wdl if !material.is_symmetric() => 2,
+        _ => 1,
+    };
+    let files = if material.has_pawns() { 4 } else { 1 };
+    let groups: Vec<_> = (0..files * sides)
+        .map(|i| Groups::new(&material, pieces, order, i / sides))
+        .collect();
+    let mut values: Vec<Vec<Option<u16>>> = groups.iter().map(|groups| vec![None; groups.size() as usize]).collect();
+
+    // Every legal position is stored at its index. Mirrored positions share an index, so they need the same value.
+    let table_pieces = table_piece_list(&material);
+    for_each_placement(table_pieces.len(), &mut |squares| {
+        let placed: Vec<(Piece, u8)> = table_pieces.iter().copied().zip(squares.iter().copied()).collect();
+        if placed
+            .iter()
+            .any(|&(piece, square)| piece.piece_type() == PieceType::Pawn && !(8..56).contains(&square))
+        {
+            return;
+        }
+
+        for color in [Color::White, Color::Black].into_iter().take(sides) {
+            let mut board = Board::new();
+            for &(piece, square) in &placed {
+                board.pieces[square as usize] = Some(piece);
+            }
+            board.color = color;
+            board.update_redundant_data();
+            board.position_key = board.generate_position_key();
+
+            let Some(value) = value(&board) else {
+                continue;
+            };
+
+            let mut sorted = placed.clone();
+            sorted.sort_by_key(|&(_, square)| square);
+            let position = Position::new(&sorted, color, false, lead_pawn);
+            let subtable = position.file * sides + position.side;
+            let index = groups[subtable].index(&material, position) as usize;
+
+            let stored = values[subtable][index].get_or_insert(value);
+            assert_eq!(
+                *stored,
+                value,
+                "{} has a different value than its mirrored positions",
+                board.as_fen()
+            );
+        }
+    });
+
+    let flags = match kind {
+        TableKind::Wdl => 0,
+        TableKind::Dtz => FLAG_WIN_PLIES | FLAG_LOSS_PLIES,
+    };
+    let subtables: Vec<_> = values
+        .iter()
+        .map(|values| Subtable::new(values.iter().map(|value| value.unwrap_or(0)).collect(), flags))
+        .collect();
+
+    let mut bytes = kind.magic().to_vec();
+    bytes.push(
+        if material.is_symmetric() { 0 } else { FLAG_SPLIT } | if material.has_pawns() { FLAG_HAS_PAWNS } else { 0 },
+    );
+
+    for _ in 0..files {
+        bytes.push(order[0] | (order[0] << 4));
+        if remaining_pawns {
+            bytes.push(order[1] | (order[1] << 4));
+        }
+        bytes.extend(pieces[..material.piece_count()].iter().map(|&code| code | (code << 4)));
+    }
+    align(&mut bytes, 2);
+
+    for subtable in &subtables {
+        subtable.write_sizes(&mut bytes);
+    }
+    if kind == TableKind::Dtz {
+        align(&mut bytes, 2);
+    }
+    for subtable in &subtables {
+        bytes.extend(&subtable.sparse_index);
+    }
+    for subtable in &subtables {
+        bytes.extend(&subtable.block_lengths);
+    }
+    for subtable in &subtables {
+        align(&mut bytes, 64);
+        bytes.extend(&subtable.data);
+    }
+
+    let path = dir.join(format!("{name}.{}", kind.extension()));
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+/// The compressed values of a subtable.
+struct Subtable {
+    flags: u8,
+    symbols: u16,
+    symbol_len: u8,
+    block_count: u32,
+    padding: u8,
+    sparse_index: Vec<u8>,
+    block_lengths: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Subtable {
+    fn new(values: Vec<u16>, flags: u8) -> Self {
+        let symbols = values.iter().max().map_or(1, |&max| max + 1);
+        let symbol_len = (u16::BITS - (symbols - 1).leading_zeros()).max(1) as u8;
+        let block_size = 1 << BLOCK_SIZE_LOG;
+        let per_block = block_size * 8 / symbol_len as usize;
+        let block_count = values.len().div_ceil(per_block);
+
+        // The codes are written from the most significant bit on.
+        let mut data = vec![0_u8; block_count * block_size];
+        for (i, &value) in values.iter().enumerate() {
+            let start = (i / per_block) * block_size * 8 + (i % per_block) * symbol_len as usize;
+            for bit in 0..symbol_len as usize {
+                if value >> (symbol_len as usize - 1 - bit) & 1 != 0 {
+                    data[(start + bit) / 8] |= 0x80 >> ((start + bit) % 8);
+                }
+            }
+        }
+
+        // Each entry points to the value in the middle of its span, which can be beyond the last block.
+        let span = 1 << SPAN_LOG;
+        let mut sparse_index = Vec::new();
+        let mut last_block = 0;
+        for k in 0..values.len().div_ceil(span) {
+            let middle = k * span + span / 2;
+            last_block = last_block.max(middle / per_block);
+            sparse_index.extend(((middle / per_block) as u32).to_le_bytes());
+            sparse_index.extend(((middle % per_block) as u16).to_le_bytes());
+        }
+
+        let padding = (last_block + 1).saturating_sub(block_count);
+        let block_lengths = (0..block_count + padding)
+            .flat_map(|_| (per_block as u16 - 1).to_le_bytes())
+            .collect();
+
+        Self {
+            flags,
+            symbols,
+            symbol_len,
+            block_count: block_count as u32,
+            padding: padding as u8,
+            sparse_index,
+            block_lengths,
+            data,
+        }
+    }
+
+    fn write_sizes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend([self.flags, BLOCK_SIZE_LOG, SPAN_LOG, self.padding]);
+        bytes.extend(self.block_count.to_le_bytes());
+        bytes.extend([self.symbol_len, self.symbol_len]);
+        bytes.extend(0_u16.to_le_bytes()); // The lowest symbol of the only code length
+        bytes.extend(self.symbols.to_le_bytes());
+
+        // Every symbol is a single value, which is stored as the left symbol.
+        for symbol in 0..self.symbols {
+            bytes.extend([symbol as u8, 0xF0 | (symbol >> 8) as u8, 0xFF]);
+        }
+        if self.symbols % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+}
+
+/// The piece codes in the order of the encoding: The leading pawns first, then the other pieces by their code.
+fn table_pieces(material: &Material) -> [u8; MAX_PIECES] {
+    let lead_color = match material.pawn_counts()[0] == material.count(Piece::WhitePawn) {
+        true => Color::White,
+        false => Color::Black,
+    };
+    let lead_pawn = material
+        .has_pawns()
+        .then(|| piece_code(Piece::new(PieceType::Pawn, lead_color)));
+
+    let mut codes: Vec<u8> = table_piece_list(material).into_iter().map(piece_code).collect();
+    codes.sort_by_key(|&code| (Some(code) != lead_pawn, code));
+
+    let mut pieces = [0; MAX_PIECES];
+    pieces[..codes.len()].copy_from_slice(&codes);
+    pieces
+}
+
+fn table_piece_list(material: &Material) -> Vec<Piece> {
+    Piece::ALL
+        .into_iter()
+        .flat_map(|piece| std::iter::repeat_n(piece, material.count(piece) as usize))
+        .collect()
+}
+
+/// Calls `f` with every placement of `count` pieces on distinct squares.
+pub(super) fn for_each_placement(count: usize, f: &mut impl FnMut(&[u8])) {
+    fn place(squares: &mut Vec<u8>, count: usize, f: &mut impl FnMut(&[u8])) {
+        if squares.len() == count {
+            return f(squares);
+        }
+
+        for square in 0..64 {
+            if !squares.contains(&square) {
+                squares.push(square);
+                place(squares, count, f);
+                squares.pop();
+            }
+        }
+    }
+
+    place(&mut Vec::with_capacity(count), count, f);
+}
+
+fn align(bytes: &mut Vec<u8>, alignment: usize) {
+    bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+}
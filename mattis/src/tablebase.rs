@@ -0,0 +1,826 @@
+//! Endgame tablebases for positions with up to four pieces.
+//!
+//! The tables are generated by retrograde analysis (see [`generator`]), so no external files are needed. They store
+//! the distance to mate (DTM) in plies of every position of a material signature, from the view of the side to move.
+//! Positions with castling rights are not covered. En passant captures are ignored, so positions, in which one is
+//! possible, are not probed. The fifty move rule is ignored as well.
+//!
+//! # File Format
+//! Each table is stored in its own file `<material>.mtb`, for example `KRKP.mtb`.
+//! ```text
+//! // offset   type        content
+//! // 0        [u8; 8]     magic bytes `MATTISTB`
+//! // 8        [u8; N]     one value for each position
+//! ```
+//! The positions are reduced by the symmetries of the board first: The white king is mirrored onto the files a to d.
+//! Without pawns, it is mirrored into the triangle a1-d1-d4 as well, and the first piece, that is not on the a1-h8
+//! diagonal, below it. Only these placements of both kings are indexed, if the kings don't touch each other (1806
+//! with pawns and 462 without). A position is indexed by `(color * kings + king_index) * 64^(n-2) + square_2 *
+//! 64^(n-3) + ... + square_n-1`, with the squares of the other pieces in the order of the material signature.
+//!
+//! A value of `0` is a draw and `255` an illegal position. All other values are the DTM plus one: odd values are
+//! losses and even values are wins for the side to move. A position, in which the weaker side has more material
+//! (like `KPKR`), is looked up in the table of the mirrored material (`KRKP`) with flipped colors.
+
+mod generator;
+
+use crate::{
+    board::{movegen::MoveList, Board},
+    chess_move::ChessMove,
+};
+use mattis_types::{CastlePerms, Color, Eval, Piece, PieceType, Square};
+use smallvec::SmallVec;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    fmt::Display,
+    io,
+    path::Path,
+    str::FromStr,
+    sync::LazyLock,
+};
+use thiserror::Error;
+
+/// The most pieces (including both kings), a table can have.
+pub const MAX_PIECES: usize = 4;
+
+const MAGIC: &[u8; 8] = b"MATTISTB";
+const FILE_EXTENSION: &str = "mtb";
+
+/// Pieces and their squares. The squares are stored as indices, to make the generation faster.
+type PieceList = SmallVec<[(Piece, u8); MAX_PIECES]>;
+
+/// The indexed placements of both kings, without and with pawns.
+static KING_PAIRS: LazyLock<[KingPairs; 2]> = LazyLock::new(|| [KingPairs::new(false), KingPairs::new(true)]);
+
+#[derive(Debug, Error)]
+pub enum TablebaseError {
+    #[error("could not read or write the table file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid material signature `{0}`")]
+    InvalidMaterial(String),
+
+    #[error("table file `{0}` is invalid")]
+    InvalidFile(String),
+}
+
+/// A material signature like `KRKP`: The white pieces starting with the king, followed by the black pieces.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Material {
+    pieces: SmallVec<[Piece; MAX_PIECES - 2]>, // Without kings, white first and from strongest to weakest
+}
+
+impl Material {
+    fn from_pieces(pieces: impl IntoIterator<Item = Piece>) -> Self {
+        let mut pieces: SmallVec<_> = pieces
+            .into_iter()
+            .filter(|piece| piece.piece_type() != PieceType::King)
+            .collect();
+        pieces.sort_by_key(|&piece| piece_order(piece));
+
+        Self { pieces }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len() + 2
+    }
+
+    /// The material with swapped colors.
+    #[must_use]
+    pub fn mirrored(&self) -> Self {
+        Self::from_pieces(self.pieces.iter().map(|&piece| flip_color(piece)))
+    }
+
+    /// Tables are only generated for the side with more material as white.
+    pub fn is_canonical(&self) -> bool {
+        let side = |color| {
+            self.pieces
+                .iter()
+                .filter(move |piece| piece.color() == color)
+                .map(|&piece| u8::from(piece.piece_type()))
+        };
+
+        side(Color::White).cmp(side(Color::Black)) != Ordering::Less
+    }
+
+    #[must_use]
+    pub fn canonical(&self) -> Self {
+        match self.is_canonical() {
+            true => self.clone(),
+            false => self.mirrored(),
+        }
+    }
+
+    /// The materials, that can be reached by a capture or a promotion.
+    pub fn successors(&self) -> Vec<Self> {
+        let mut successors = vec![];
+
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            let others = self.pieces.iter().enumerate().filter(|&(j, _)| j != i).map(|(_, &p)| p);
+            successors.push(Self::from_pieces(others.clone()).canonical());
+
+            if piece.piece_type() == PieceType::Pawn {
+                for promoted in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                    let promoted = Piece::new(promoted, piece.color());
+                    successors.push(Self::from_pieces(others.clone().chain([promoted])).canonical());
+                }
+            }
+        }
+
+        successors
+    }
+
+    /// All pieces in the order of the table index.
+    fn table_pieces(&self) -> SmallVec<[Piece; MAX_PIECES]> {
+        [Piece::WhiteKing, Piece::BlackKing]
+            .into_iter()
+            .chain(self.pieces.iter().copied())
+            .collect()
+    }
+}
+
+impl FromStr for Material {
+    type Err = TablebaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TablebaseError::InvalidMaterial(s.to_string());
+
+        let (white, black) = s
+            .to_ascii_uppercase()
+            .strip_prefix('K')
+            .and_then(|s| s.split_once('K').map(|(w, b)| (w.to_string(), b.to_string())))
+            .ok_or_else(invalid)?;
+
+        let mut pieces = vec![];
+        for (side, color) in [(white, Color::White), (black, Color::Black)] {
+            for c in side.chars() {
+                match Piece::from_char(c).map(Piece::piece_type) {
+                    Some(PieceType::King) | None => return Err(invalid()),
+                    Some(piece_type) => pieces.push(Piece::new(piece_type, color)),
+                }
+            }
+        }
+
+        if pieces.len() + 2 > MAX_PIECES {
+            return Err(invalid());
+        }
+
+        Ok(Self::from_pieces(pieces))
+    }
+}
+
+impl Display for Material {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for color in [Color::White, Color::Black] {
+            write!(f, "K")?;
+            for piece in self.pieces.iter().filter(|piece| piece.color() == color) {
+                write!(f, "{}", Piece::new(piece.piece_type(), Color::White).to_char())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of a position for the side to move. Mates are counted in plies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TbValue {
+    Win(u8),
+    Draw,
+    Loss(u8),
+}
+
+impl TbValue {
+    const ILLEGAL: u8 = u8::MAX;
+
+    fn decode(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Draw),
+            Self::ILLEGAL => None,
+            v if v % 2 == 1 => Some(Self::Loss(v - 1)),
+            v => Some(Self::Win(v - 1)),
+        }
+    }
+
+    fn encode(self) -> u8 {
+        match self {
+            Self::Draw => 0,
+            Self::Win(plies) | Self::Loss(plies) => plies + 1,
+        }
+    }
+
+    /// The value for the side, that made a move into a position with this value.
+    #[must_use]
+    pub fn before_move(self) -> Self {
+        match self {
+            Self::Win(plies) => Self::Loss(plies.saturating_add(1)),
+            Self::Draw => Self::Draw,
+            Self::Loss(plies) => Self::Win(plies.saturating_add(1)),
+        }
+    }
+
+    /// Converts the value into a mate score at the given distance from the root.
+    pub fn eval(self, ply: usize) -> Eval {
+        let mate_ply = |plies: u8| (ply + plies as usize).min(u8::MAX as usize) as u8;
+
+        match self {
+            Self::Win(plies) => Eval::mate_in(mate_ply(plies)),
+            Self::Draw => Eval::DRAW,
+            Self::Loss(plies) => -Eval::mate_in(mate_ply(plies)),
+        }
+    }
+}
+
+/// The values of all positions of one material signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    material: Material,
+    values: Vec<u8>,
+}
+
+impl Table {
+    fn size(table_pieces: &[Piece]) -> usize {
+        let kings = KingPairs::get(has_pawns(table_pieces.iter())).squares.len();
+        (2 * kings) << (6 * (table_pieces.len() - 2))
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{FILE_EXTENSION}", self.material)
+    }
+
+    /// Loads a table. The material is taken from the file name.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TablebaseError> {
+        let path = path.as_ref();
+        let invalid = || TablebaseError::InvalidFile(path.display().to_string());
+
+        let material: Material = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(invalid)?
+            .parse()?;
+        if !material.is_canonical() {
+            return Err(invalid());
+        }
+
+        let bytes = std::fs::read(path)?;
+        let values = bytes
+            .strip_prefix(MAGIC)
+            .filter(|values| values.len() == Self::size(&material.table_pieces()))
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            material,
+            values: values.to_vec(),
+        })
+    }
+
+    /// Writes the table into the directory and returns the path of the file.
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<std::path::PathBuf> {
+        let path = dir.as_ref().join(self.file_name());
+        std::fs::write(&path, [MAGIC.as_slice(), &self.values].concat())?;
+        Ok(path)
+    }
+
+    /// The longest distance to mate of all won positions, in plies.
+    pub fn longest_mate(&self) -> u8 {
+        self.values
+            .iter()
+            .filter_map(|&value| match TbValue::decode(value) {
+                Some(TbValue::Win(plies)) => Some(plies),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn value(&self, pieces: &[(Piece, u8)], color: Color) -> Option<TbValue> {
+        TbValue::decode(self.values[index(pieces, color)?])
+    }
+}
+
+/// The placements of both kings, which are indexed.
+struct KingPairs {
+    indices: [[u16; 64]; 64], // By the squares of the white and the black king
+    squares: Vec<(u8, u8)>,
+}
+
+impl KingPairs {
+    const NONE: u16 = u16::MAX;
+
+    fn new(has_pawns: bool) -> Self {
+        let mut pairs = Self {
+            indices: [[Self::NONE; 64]; 64],
+            squares: vec![],
+        };
+
+        for white in 0..64 {
+            for black in 0..64 {
+                let mut squares = [white, black];
+                mirror_canonical(&mut squares, has_pawns);
+
+                let touching = (white % 8).abs_diff(black % 8) <= 1 && (white / 8).abs_diff(black / 8) <= 1;
+                if squares == [white, black] && !touching {
+                    pairs.indices[white as usize][black as usize] = pairs.squares.len() as u16;
+                    pairs.squares.push((white, black));
+                }
+            }
+        }
+
+        pairs
+    }
+
+    fn get(has_pawns: bool) -> &'static Self {
+        &KING_PAIRS[has_pawns as usize]
+    }
+}
+
+fn has_pawns<'a>(mut pieces: impl Iterator<Item = &'a Piece>) -> bool {
+    pieces.any(|piece| piece.piece_type() == PieceType::Pawn)
+}
+
+/// Mirrors the squares, so the white king (the first square) is on the files a to d. Without pawns, it is mirrored
+/// into the triangle a1-d1-d4 and the first square, that is not on the a1-h8 diagonal, below it.
+fn mirror_canonical(squares: &mut [u8], has_pawns: bool) {
+    fn mirror(squares: &mut [u8], flip: fn(u8) -> u8) {
+        squares.iter_mut().for_each(|square| *square = flip(*square));
+    }
+
+    if squares[0] % 8 > 3 {
+        mirror(squares, |square| square ^ 7);
+    }
+
+    if !has_pawns {
+        if squares[0] / 8 > 3 {
+            mirror(squares, |square| square ^ 56);
+        }
+
+        // Compares the rank with the file.
+        let off_diagonal = squares
+            .iter()
+            .map(|&square| (square / 8).cmp(&(square % 8)))
+            .find(|&side| side != Ordering::Equal);
+        if off_diagonal == Some(Ordering::Greater) {
+            mirror(squares, |square| (square % 8) * 8 + square / 8);
+        }
+    }
+}
+
+/// The index of a position within its table. The pieces are in the order of the table.
+///
+/// Returns `None`, if the kings touch each other.
+fn index(pieces: &[(Piece, u8)], color: Color) -> Option<usize> {
+    let mut squares: SmallVec<[u8; MAX_PIECES]> = pieces.iter().map(|&(_, square)| square).collect();
+    let has_pawns = has_pawns(pieces.iter().map(|(piece, _)| piece));
+    mirror_canonical(&mut squares, has_pawns);
+
+    let kings = KingPairs::get(has_pawns);
+
+    let king_index = kings.indices[squares[0] as usize][squares[1] as usize];
+    let first = u8::from(color) as usize * kings.squares.len() + king_index as usize;
+
+    (king_index != KingPairs::NONE).then(|| {
+        squares[2..]
+            .iter()
+            .fold(first, |index, &square| (index << 6) | square as usize)
+    })
+}
+
+/// Sets up the pieces of the position with the index. The position can be illegal.
+fn position(table_pieces: &[Piece], index: usize) -> (PieceList, Color) {
+    let kings = KingPairs::get(has_pawns(table_pieces.iter()));
+    let others = table_pieces.len() - 2;
+    let first = index >> (6 * others);
+    let (white_king, black_king) = kings.squares[first % kings.squares.len()];
+    let color = if first < kings.squares.len() {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let squares = [white_king, black_king]
+        .into_iter()
+        .chain((0..others).map(|i| ((index >> (6 * (others - 1 - i))) & 63) as u8));
+    (table_pieces.iter().copied().zip(squares).collect(), color)
+}
+
+/// Checks, if the position is indexed with the same squares, and not with the squares of a mirrored position.
+fn is_canonical(pieces: &[(Piece, u8)]) -> bool {
+    let squares: SmallVec<[u8; MAX_PIECES]> = pieces.iter().map(|&(_, square)| square).collect();
+    let mut mirrored = squares.clone();
+    mirror_canonical(&mut mirrored, has_pawns(pieces.iter().map(|(piece, _)| piece)));
+
+    mirrored == squares
+}
+
+/// The order of the pieces in a material signature: white first and from strongest to weakest.
+fn piece_order(piece: Piece) -> (u8, Reverse<u8>) {
+    (u8::from(piece.color()), Reverse(u8::from(piece.piece_type())))
+}
+
+fn flip_color(piece: Piece) -> Piece {
+    Piece::new(piece.piece_type(), piece.color().flipped())
+}
+
+/// A collection of tables, which can be probed with any position they cover.
+#[derive(Debug, Clone, Default)]
+pub struct Tablebases {
+    tables: HashMap<Material, Table>,
+    max_pieces: usize, // The most pieces of any table
+}
+
+impl Tablebases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads all tables of the directory.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Self, TablebaseError> {
+        let mut tablebases = Self::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == FILE_EXTENSION) {
+                tablebases.insert(Table::load(path)?);
+            }
+        }
+
+        Ok(tablebases)
+    }
+
+    pub fn insert(&mut self, table: Table) {
+        self.max_pieces = self.max_pieces.max(table.material.piece_count());
+        self.tables.insert(table.material.clone(), table);
+    }
+
+    pub fn get(&self, material: &Material) -> Option<&Table> {
+        self.tables.get(material)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Generates the table of the material, if it is missing. The tables of all materials, that can be reached
+    /// from it, are generated first. `on_table` is called with every newly generated table.
+    pub fn generate(
+        &mut self,
+        material: &Material,
+        on_table: &mut impl FnMut(&Table) -> Result<(), TablebaseError>,
+    ) -> Result<(), TablebaseError> {
+        let material = material.canonical();
+        if material.pieces.is_empty() || self.tables.contains_key(&material) {
+            return Ok(());
+        }
+
+        for successor in material.successors() {
+            self.generate(&successor, on_table)?;
+        }
+
+        let table = generator::generate(&material, self);
+        on_table(&table)?;
+        self.insert(table);
+
+        Ok(())
+    }
+
+    /// Returns the value of the position for the side to move, if it is covered by a table.
+    pub fn probe(&self, board: &Board) -> Option<TbValue> {
+        if board.bb_all.bit_count() as usize > self.max_pieces
+            || board.castle_perms != CastlePerms::NONE
+            || en_passant_possible(board)
+        {
+            return None;
+        }
+
+        let pieces: PieceList = board
+            .pieces
+            .iter()
+            .enumerate()
+            .filter_map(|(square, piece)| piece.map(|piece| (piece, square as u8)))
+            .collect();
+
+        self.probe_pieces(&pieces, board.color)
+    }
+
+    /// Returns the best move and the value of the position, if it is covered by the tables.
+    ///
+    /// Winning positions are won as fast as possible and losing positions are defended as long as possible.
+    pub fn best_move(&self, board: &mut Board) -> Option<(ChessMove, TbValue)> {
+        self.probe(board)?;
+
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+
+        legal_moves
+            .into_iter()
+            .filter_map(|m| {
+                board.make_move(m);
+                let value = self.probe(board).map(TbValue::before_move);
+                board.take_move();
+                value.map(|value| (m, value))
+            })
+            .max_by_key(|&(_, value)| value.eval(0))
+    }
+
+    fn probe_pieces(&self, pieces: &[(Piece, u8)], color: Color) -> Option<TbValue> {
+        let material = Material::from_pieces(pieces.iter().map(|&(piece, _)| piece));
+        if material.pieces.is_empty() {
+            return Some(TbValue::Draw);
+        }
+
+        // Positions of the weaker side are mirrored vertically and get their colors swapped.
+        let (material, pieces, color): (_, PieceList, _) = match material.is_canonical() {
+            true => (material, pieces.iter().copied().collect(), color),
+            false => (
+                material.mirrored(),
+                pieces
+                    .iter()
+                    .map(|&(piece, square)| (flip_color(piece), square ^ 56))
+                    .collect(),
+                color.flipped(),
+            ),
+        };
+
+        let table = self.tables.get(&material)?;
+        table.value(&table_order(&pieces), color)
+    }
+}
+
+/// Sorts the pieces into the order of the table index.
+fn table_order(pieces: &[(Piece, u8)]) -> PieceList {
+    let mut others: PieceList = pieces
+        .iter()
+        .copied()
+        .filter(|(piece, _)| piece.piece_type() != PieceType::King)
+        .collect();
+    others.sort_by_key(|&(piece, _)| piece_order(piece));
+
+    let king = |king| {
+        pieces
+            .iter()
+            .find(|&&(piece, _)| piece == king)
+            .map_or(0, |&(_, square)| square)
+    };
+
+    [
+        (Piece::WhiteKing, king(Piece::WhiteKing)),
+        (Piece::BlackKing, king(Piece::BlackKing)),
+    ]
+    .into_iter()
+    .chain(others)
+    .collect()
+}
+
+/// Checks, if the side to move can capture en passant.
+fn en_passant_possible(board: &Board) -> bool {
+    let Some(square) = board.en_passant else {
+        return false;
+    };
+
+    // The capturing pawns stand next to the pawn, that has just been pushed.
+    let square = u8::from(square) as i8;
+    let (pushed, pawn) = match board.color {
+        Color::White => (square - 8, Piece::WhitePawn),
+        Color::Black => (square + 8, Piece::BlackPawn),
+    };
+
+    [-1, 1]
+        .into_iter()
+        .map(|offset| pushed + offset)
+        .filter(|&neighbour| neighbour / 8 == pushed / 8)
+        .filter_map(|neighbour| Square::try_from(neighbour as u8).ok())
+        .any(|neighbour| board.pieces[neighbour] == Some(pawn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index, position, table_order, KingPairs, Material, Table, TablebaseError, Tablebases, TbValue};
+    use crate::{
+        board::{movegen::MoveList, Board},
+        engine::Engine,
+        notation::SmithNotation,
+        search::lazy_smp::LazySMPSetup,
+    };
+    use mattis_types::Color;
+    use mattis_uci as uci;
+    use std::sync::{Arc, OnceLock};
+
+    /// The tables are shared by all tests, because generating them takes a while.
+    fn tablebases() -> &'static Tablebases {
+        static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+
+        TABLEBASES.get_or_init(|| {
+            let mut tablebases = Tablebases::new();
+            for material in ["KQK", "KRK", "KPK"] {
+                tablebases
+                    .generate(&material.parse().unwrap(), &mut |_| Ok(()))
+                    .unwrap();
+            }
+            tablebases
+        })
+    }
+
+    fn probe(fen: &str) -> Option<TbValue> {
+        tablebases().probe(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn material_signatures() {
+        let material: Material = "KRKP".parse().unwrap();
+        assert_eq!(material.to_string(), "KRKP");
+        assert_eq!(material.piece_count(), 4);
+        assert!(material.is_canonical());
+
+        let mirrored: Material = "kpkr".parse().unwrap();
+        assert!(!mirrored.is_canonical());
+        assert_eq!(mirrored.canonical(), material);
+
+        let successors: Vec<_> = material.successors().iter().map(ToString::to_string).collect();
+        assert_eq!(successors, ["KPK", "KRK", "KQKR", "KRKR", "KRKB", "KRKN"]);
+
+        for invalid in ["", "KQ", "QKK", "KQRKP", "KXK", "KKK"] {
+            assert!(
+                matches!(invalid.parse::<Material>(), Err(TablebaseError::InvalidMaterial(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn symmetric_positions_share_their_index() {
+        assert_eq!(KingPairs::get(false).squares.len(), 462);
+        assert_eq!(KingPairs::get(true).squares.len(), 1806);
+        assert_eq!(
+            Table::size(&"KQK".parse::<Material>().unwrap().table_pieces()),
+            2 * 462 * 64
+        );
+
+        // The same KRK position in all eight orientations
+        let fens = [
+            "8/8/8/8/8/1k6/8/1K5R w - - 0 1",
+            "8/8/8/8/8/6k1/8/R5K1 w - - 0 1",
+            "1K5R/8/1k6/8/8/8/8/8 w - - 0 1",
+            "R5K1/8/6k1/8/8/8/8/8 w - - 0 1",
+            "R7/8/8/8/8/8/K1k5/8 w - - 0 1",
+            "8/K1k5/8/8/8/8/8/R7 w - - 0 1",
+            "7R/8/8/8/8/8/5k1K/8 w - - 0 1",
+            "8/5k1K/8/8/8/8/8/7R w - - 0 1",
+        ];
+        let indices: Vec<_> = fens
+            .iter()
+            .map(|fen| {
+                let pieces: Vec<_> = Board::from_fen(fen)
+                    .unwrap()
+                    .pieces
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(square, piece)| piece.map(|piece| (piece, square as u8)))
+                    .collect();
+                index(&table_order(&pieces), Color::White).unwrap()
+            })
+            .collect();
+        assert!(indices.iter().all(|&index| index == indices[0]), "{indices:?}");
+
+        // With pawns, only the files are mirrored.
+        assert_ne!(
+            probe("8/8/8/8/8/1k6/1P6/1K6 w - - 0 1"),
+            probe("1K6/1P6/1k6/8/8/8/8/8 w - - 0 1")
+        );
+        assert_eq!(
+            probe("8/8/8/8/8/1k6/1P6/1K6 w - - 0 1"),
+            probe("8/8/8/8/8/6k1/6P1/6K1 w - - 0 1")
+        );
+    }
+
+    #[test]
+    fn known_endgames() {
+        // The longest mates of KQK (10 moves) and KRK (16 moves)
+        let longest_mate = |material: &str| tablebases().get(&material.parse().unwrap()).unwrap().longest_mate();
+        assert_eq!(longest_mate("KQK"), 19);
+        assert_eq!(longest_mate("KRK"), 31);
+
+        assert_eq!(probe("7k/8/6K1/8/8/8/Q7/8 w - - 0 1"), Some(TbValue::Win(1)));
+        assert_eq!(probe("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(TbValue::Draw)); // Stalemate
+        assert_eq!(probe("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1"), Some(TbValue::Loss(0)));
+
+        // The weaker side is white
+        assert_eq!(probe("8/8/8/8/8/1k6/8/K6r w - - 0 1"), Some(TbValue::Loss(0)));
+
+        // Kings in front of the pawn
+        assert!(matches!(
+            probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"),
+            Some(TbValue::Win(_))
+        ));
+        assert_eq!(probe("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), Some(TbValue::Draw)); // Stalemate
+        assert_eq!(probe("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(TbValue::Draw));
+
+        // Positions, which are not covered
+        assert_eq!(probe("4k3/8/8/8/8/8/8/4K2R w K - 0 1"), None);
+        assert_eq!(probe("4k3/8/8/8/8/8/8/3QKR2 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn values_match_the_moves() {
+        // Every value has to be the best value of all moves. Checking every 97th position with the move generator
+        // of the board is an independent test of the generator.
+        for material in ["KQK", "KPK"] {
+            let table = tablebases().get(&material.parse().unwrap()).unwrap();
+            let pieces = table.material.table_pieces();
+
+            for index in (0..table.values.len()).step_by(97) {
+                let Some(value) = TbValue::decode(table.values[index]) else {
+                    continue;
+                };
+
+                let (pieces, color) = position(&pieces, index);
+                let mut board = Board::new();
+                for (piece, square) in pieces {
+                    board.pieces[square as usize] = Some(piece);
+                }
+                board.color = color;
+                board.update_redundant_data();
+                board.position_key = board.generate_position_key();
+
+                let mut legal_moves = MoveList::new();
+                board.generate_legal_moves(&mut legal_moves);
+
+                let best = legal_moves
+                    .into_iter()
+                    .map(|m| {
+                        board.make_move(m);
+                        let value = tablebases().probe(&board).unwrap().before_move();
+                        board.take_move();
+                        value
+                    })
+                    .max_by_key(|value| value.eval(0));
+
+                let expected = match best {
+                    Some(best) => best,
+                    None if board.in_check() => TbValue::Loss(0),
+                    None => TbValue::Draw,
+                };
+                assert_eq!(value, expected, "{}", board.as_fen());
+            }
+        }
+    }
+
+    #[test]
+    fn best_moves() {
+        let mut board = Board::from_fen("8/8/8/8/8/1k6/8/1K5R w - - 0 1").unwrap();
+        let (m, value) = tablebases().best_move(&mut board).unwrap();
+        assert!(matches!(value, TbValue::Win(_)));
+
+        // Following the best moves of both sides leads to mate in exactly the predicted number of plies.
+        let TbValue::Win(plies) = value else { unreachable!() };
+        board.make_move(m);
+        for _ in 1..plies {
+            let (m, _) = tablebases().best_move(&mut board).unwrap();
+            board.make_move(m);
+        }
+
+        let mut legal_moves = MoveList::new();
+        board.generate_legal_moves(&mut legal_moves);
+        assert!(legal_moves.is_empty() && board.in_check());
+
+        // Capturing the rook is the only way to draw.
+        let mut board = Board::from_fen("7k/8/8/8/8/8/6r1/7K w - - 0 1").unwrap();
+        let capture = board.find_move::<SmithNotation>("h1g2");
+        assert_eq!(tablebases().best_move(&mut board), capture.map(|m| (m, TbValue::Draw)));
+    }
+
+    #[test]
+    fn search_with_tablebases() {
+        let fen = "8/8/8/3k4/8/8/8/R3K3 w - - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let (best_move, value) = tablebases().best_move(&mut board).unwrap();
+
+        let mut engine = Engine::new(LazySMPSetup::default().thread_count(1).ttable_size(1));
+        engine.set_tablebases(Some(Arc::new(tablebases().clone())));
+        engine.set_position(fen, &[] as &[&str]).unwrap();
+
+        // The move is played right away.
+        let go = uci::Go {
+            depth: Some(20),
+            ..Default::default()
+        };
+        let result = engine.search(go, ()).unwrap().wait();
+        assert_eq!((result.bestmove, result.score), (best_move, value.eval(0)));
+
+        // Mate searches probe the tables within the search.
+        let go = uci::Go {
+            mate: Some(20),
+            ..Default::default()
+        };
+        let result = engine.search(go, ()).unwrap().wait();
+        assert_eq!(result.score, value.eval(0));
+    }
+}
@@ -0,0 +1,328 @@
+//! Generating tables by retrograde analysis.
+//!
+//! First, every position is set up once, to find the illegal positions and the checkmates and to count the moves,
+//! that stay within the table. Captures and promotions lead into other tables, which are generated before, so their
+//! values are known right away. Then the positions are resolved ply by ply, by going backwards from the positions
+//! resolved in the previous ply: Every predecessor of a lost position is won, and a predecessor of a won position is
+//! lost, once all of its moves lead to won positions. All positions, that are never resolved, are draws.
+//!
+//! Mirrored positions share their index, so a position can reach the same index with several moves, and one
+//! position can have several predecessors with the same index. Therefore, the moves are counted per distinct index.
+
+use super::{index, is_canonical, position, Material, PieceList, Table, Tablebases, TbValue};
+use crate::{
+    board::movegen::{bishop_moves, rook_moves},
+    tables::{KING_MOVE_PATTERNS, KNIGHT_MOVE_PATTERNS},
+};
+use mattis_bitboard::BitBoard;
+use mattis_types::{Color, Piece, PieceType, Square, UnsafeFromPrimitive};
+use smallvec::SmallVec;
+
+/// The distinct indices of the positions, that can be reached by moves within the table.
+type Indices = SmallVec<[usize; 64]>;
+
+const UNKNOWN: u8 = 0; // Positions, that are not resolved yet, are encoded like draws
+const CANNOT_LOSE: u8 = 0x80; // Set in the move counter, if a move leads into a drawn position of another table
+
+pub(super) fn generate(material: &Material, tablebases: &Tablebases) -> Table {
+    let pieces = material.table_pieces();
+    let size = Table::size(&pieces);
+
+    let mut values = vec![UNKNOWN; size];
+    let mut counters = vec![0_u8; size]; // The moves within the table, that don't lead into won positions yet
+    let mut floors = vec![0_u8; size]; // The longest mate of the moves into other tables, that win for the opponent
+
+    // Setting up the positions is by far the slowest part, so it is split over all cores.
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = size.div_ceil(threads);
+    std::thread::scope(|scope| {
+        let chunks = values
+            .chunks_mut(chunk_size)
+            .zip(counters.chunks_mut(chunk_size))
+            .zip(floors.chunks_mut(chunk_size));
+
+        for (chunk, ((values, counters), floors)) in chunks.enumerate() {
+            let pieces = &pieces;
+            scope.spawn(move || {
+                for (i, ((value, counter), floor)) in values.iter_mut().zip(counters).zip(floors).enumerate() {
+                    (*value, *counter, *floor) = initialize(pieces, chunk * chunk_size + i, tablebases);
+                }
+            });
+        }
+    });
+
+    let mut max_plies = values
+        .iter()
+        .filter_map(|&value| TbValue::decode(value))
+        .map(|value| match value {
+            TbValue::Win(plies) | TbValue::Loss(plies) => plies as usize,
+            TbValue::Draw => 0,
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut predecessors = Indices::new();
+    let mut plies = 0;
+    while plies <= max_plies {
+        assert!(
+            plies + 2 < TbValue::ILLEGAL as usize,
+            "The mates of a table are too long to be stored"
+        );
+
+        let is_loss = plies.is_multiple_of(2);
+        let resolved = match is_loss {
+            true => TbValue::Loss(plies as u8).encode(),
+            false => TbValue::Win(plies as u8).encode(),
+        };
+
+        for index in 0..size {
+            if values[index] != resolved {
+                continue;
+            }
+
+            let (position_pieces, color) = position(&pieces, index);
+            predecessors.clear();
+            for_each_unmove(&position_pieces, color, |predecessor| predecessors.push(predecessor));
+            predecessors.sort_unstable();
+            predecessors.dedup();
+
+            for &predecessor in &predecessors {
+                let value = values[predecessor];
+
+                if is_loss {
+                    // A faster win replaces a win through another table.
+                    let win = TbValue::Win(plies as u8 + 1).encode();
+                    if value == UNKNOWN || (value.is_multiple_of(2) && value > win) {
+                        values[predecessor] = win;
+                        max_plies = max_plies.max(plies + 1);
+                    }
+                } else if value == UNKNOWN {
+                    counters[predecessor] -= 1;
+                    if counters[predecessor] == 0 {
+                        let loss = plies.max(floors[predecessor] as usize) + 1;
+                        values[predecessor] = TbValue::Loss(loss as u8).encode();
+                        max_plies = max_plies.max(loss);
+                    }
+                }
+            }
+        }
+
+        plies += 1;
+    }
+
+    Table {
+        material: material.clone(),
+        values,
+    }
+}
+
+/// Returns the value, the move counter and the floor of a position before the retrograde analysis.
+fn initialize(table_pieces: &[Piece], table_index: usize, tablebases: &Tablebases) -> (u8, u8, u8) {
+    let (pieces, color) = position(table_pieces, table_index);
+    if !is_canonical(&pieces) || !is_legal(&pieces, color) {
+        return (TbValue::ILLEGAL, 0, 0);
+    }
+
+    let mut moves = 0;
+    let mut children = Indices::new();
+    let mut floor = 0;
+    let mut win: Option<u8> = None;
+    let mut cannot_lose = false;
+
+    for_each_move(&pieces, color, |child, stays_in_table| {
+        moves += 1;
+        if stays_in_table {
+            children.extend(index(child, color.flipped()));
+            return;
+        }
+
+        let value = tablebases
+            .probe_pieces(child, color.flipped())
+            .expect("The tables of captures and promotions are generated first");
+        match value.before_move() {
+            TbValue::Win(plies) => win = Some(win.map_or(plies, |win| win.min(plies))),
+            TbValue::Draw => cannot_lose = true,
+            TbValue::Loss(plies) => floor = floor.max(plies - 1),
+        }
+    });
+
+    children.sort_unstable();
+    children.dedup();
+    let counter = children.len() as u8 | if cannot_lose { CANNOT_LOSE } else { 0 };
+
+    let value = match win {
+        Some(plies) => TbValue::Win(plies).encode(),
+        None if moves == 0 && king_attacked(&pieces, color) => TbValue::Loss(0).encode(),
+        None if moves == 0 => UNKNOWN, // Stalemate
+        None if counter == 0 => TbValue::Loss(floor + 1).encode(),
+        None => UNKNOWN,
+    };
+
+    (value, counter, floor)
+}
+
+/// Checks for overlapping pieces, pawns on the back ranks and the side not to move being in check.
+fn is_legal(pieces: &PieceList, color: Color) -> bool {
+    let occupied = occupancy(pieces);
+    if occupied.bit_count() as usize != pieces.len() {
+        return false;
+    }
+
+    let pawn_on_back_rank = pieces
+        .iter()
+        .any(|&(piece, square)| piece.piece_type() == PieceType::Pawn && matches!(square / 8, 0 | 7));
+
+    !pawn_on_back_rank && !king_attacked(pieces, color.flipped())
+}
+
+fn occupancy<'a>(pieces: impl IntoIterator<Item = &'a (Piece, u8)>) -> BitBoard {
+    pieces.into_iter().fold(BitBoard::EMPTY, |bb, &(_, square)| {
+        bb.union(BitBoard::from_u64(1 << square))
+    })
+}
+
+fn square(index: u8) -> Square {
+    debug_assert!(index < 64);
+    // Safety: The squares of the tables are always in the range of 0 to 63.
+    unsafe { Square::unchecked_transmute_from(index) }
+}
+
+/// The squares attacked by the piece. Pawns only attack diagonally.
+fn attacks(piece: Piece, from: u8, occupied: BitBoard) -> BitBoard {
+    let from = square(from);
+
+    match piece.piece_type() {
+        PieceType::Pawn => {
+            let bb = BitBoard::from_u64(1 << from as u8);
+            match piece.color() {
+                Color::White => bb.shifted_northeast().union(bb.shifted_northwest()),
+                Color::Black => bb.shifted_southeast().union(bb.shifted_southwest()),
+            }
+        }
+        PieceType::Knight => KNIGHT_MOVE_PATTERNS[from],
+        PieceType::Bishop => bishop_moves(from, occupied),
+        PieceType::Rook => rook_moves(from, occupied),
+        PieceType::Queen => bishop_moves(from, occupied).union(rook_moves(from, occupied)),
+        PieceType::King => KING_MOVE_PATTERNS[from],
+    }
+}
+
+fn king_attacked(pieces: &[(Piece, u8)], color: Color) -> bool {
+    let occupied = occupancy(pieces);
+    let king = Piece::new(PieceType::King, color);
+    let Some(&(_, king_square)) = pieces.iter().find(|&&(piece, _)| piece == king) else {
+        return false;
+    };
+
+    pieces
+        .iter()
+        .filter(|(piece, _)| piece.color() != color)
+        .any(|&(piece, from)| attacks(piece, from, occupied).get(square(king_square)))
+}
+
+/// Calls `f` with the pieces after every legal move, and whether the move stays within the table
+/// (it is neither a capture nor a promotion).
+fn for_each_move(pieces: &PieceList, color: Color, mut f: impl FnMut(&PieceList, bool)) {
+    let occupied = occupancy(pieces);
+    let own = occupancy(pieces.iter().filter(|(piece, _)| piece.color() == color));
+
+    for &(piece, from) in pieces.iter().filter(|(piece, _)| piece.color() == color) {
+        let targets = match piece.piece_type() {
+            PieceType::Pawn => {
+                let enemies = occupied.without(own);
+                let captures = attacks(piece, from, occupied).intersection(enemies);
+                captures.union(pawn_pushes(color, from, occupied))
+            }
+            _ => attacks(piece, from, occupied).without(own),
+        };
+
+        for to in targets.iter_bit_indices().map(u8::from) {
+            let mut child: PieceList = pieces.iter().copied().filter(|&(_, square)| square != to).collect();
+            let is_capture = child.len() < pieces.len();
+
+            let moved = child.iter().position(|&(_, square)| square == from).unwrap();
+            child[moved].1 = to;
+            if king_attacked(&child, color) {
+                continue;
+            }
+
+            if piece.piece_type() == PieceType::Pawn && matches!(to / 8, 0 | 7) {
+                for promoted in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                    child[moved].0 = Piece::new(promoted, color);
+                    f(&child, false);
+                }
+            } else {
+                f(&child, !is_capture);
+            }
+        }
+    }
+}
+
+fn pawn_pushes(color: Color, from: u8, occupied: BitBoard) -> BitBoard {
+    let (forward, start_rank): (i8, u8) = match color {
+        Color::White => (8, 1),
+        Color::Black => (-8, 6),
+    };
+
+    let mut pushes = BitBoard::EMPTY;
+    let single = (from as i8 + forward) as u8;
+    if !occupied.get(square(single)) {
+        pushes.set(square(single));
+
+        let double = (single as i8 + forward) as u8;
+        if from / 8 == start_rank && !occupied.get(square(double)) {
+            pushes.set(square(double));
+        }
+    }
+
+    pushes
+}
+
+/// Calls `f` with the index of every position, from which the side, that is not to move, could have reached the
+/// position with a move within the table. The predecessors can be illegal and indices can repeat.
+fn for_each_unmove(pieces: &PieceList, color: Color, mut f: impl FnMut(usize)) {
+    let occupied = occupancy(pieces);
+    let mover = color.flipped();
+    let mut predecessor = pieces.clone();
+
+    for (i, &(piece, to)) in pieces
+        .iter()
+        .enumerate()
+        .filter(|(_, (piece, _))| piece.color() == mover)
+    {
+        let origins = match piece.piece_type() {
+            PieceType::Pawn => pawn_origins(mover, to, occupied),
+            _ => attacks(piece, to, occupied).without(occupied),
+        };
+
+        for from in origins.iter_bit_indices().map(u8::from) {
+            predecessor[i].1 = from;
+            if let Some(index) = index(&predecessor, mover) {
+                f(index);
+            }
+        }
+        predecessor[i].1 = to;
+    }
+}
+
+/// The squares, from which a pawn could have been pushed to `to`.
+fn pawn_origins(color: Color, to: u8, occupied: BitBoard) -> BitBoard {
+    let (backward, double_push_rank): (i8, u8) = match color {
+        Color::White => (-8, 3),
+        Color::Black => (8, 4),
+    };
+
+    let mut origins = BitBoard::EMPTY;
+    let single = (to as i8 + backward) as u8;
+    if matches!(single / 8, 0 | 7) || occupied.get(square(single)) {
+        return origins;
+    }
+    origins.set(square(single));
+
+    let double = (single as i8 + backward) as u8;
+    if to / 8 == double_push_rank && !occupied.get(square(double)) {
+        origins.set(square(double));
+    }
+
+    origins
+}